@@ -22,7 +22,10 @@ fn search_startpos(c: &mut Criterion) {
                     black_box(pos),
                     c_rx,
                     MainEvaluator::new(),
-                    SearcherConfig { depth: Some(6) },
+                    SearcherConfig {
+                        depth: Some(6),
+                        ..SearcherConfig::default()
+                    },
                     &stats,
                     &mut tt,
                 );
@@ -1,211 +1,66 @@
+use brain::bench::BENCH_POSITIONS;
 use brain::evaluator::MainEvaluator;
 use brain::position_hash_history::PositionHashHistory;
 use brain::searcher::{Searcher, SearcherConfig};
 use brain::statistics::StatisticsHolder;
 use brain::transposition_table::TranspositionTable;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
 use guts::Position;
+use std::str::FromStr;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 
-const MIB: u64 = 1024 * 1024;
-const DEPTH: u16 = 6;
+const KIB: u64 = 1024;
+const DEPTH: u16 = 5;
+// Entries are 16 bytes, so a search of one position already overfills the smaller tables
+const SIZES_KIB: [u64; 5] = [64, 256, 1024, 4096, 16384];
 
-fn mb_2(c: &mut Criterion) {
-    c.bench_function("mb_2", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(2 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
+/// Searches every bench position with one table, like consecutive moves of a game would.
+/// Returns the number of nodes searched.
+fn search_bench_positions(tt: &mut TranspositionTable) -> u64 {
+    let (_c_tx, c_rx) = watch::channel(());
+    let mut nodes = 0;
+    for fen in BENCH_POSITIONS {
+        let pos = Position::from_str(fen).unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let stats = StatisticsHolder::new();
+        tt.new_search();
+        let mut searcher = Searcher::with_evaluator_and_config(
+            black_box(history),
+            black_box(pos),
+            c_rx.clone(),
+            MainEvaluator::new(),
+            SearcherConfig {
+                depth: Some(DEPTH),
+                ..SearcherConfig::default()
+            },
+            &stats,
+            tt,
+        );
+        let (tx, _rx) = mpsc::unbounded_channel();
+        searcher.search(tx);
+        nodes += stats.nodes_searched();
+    }
+    nodes
 }
 
-fn mb_4(c: &mut Criterion) {
-    c.bench_function("mb_4", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(4 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
+fn tt_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tt_size");
+    group.sample_size(10);
+    for size_kib in SIZES_KIB {
+        // A smaller table loses more entries, so it searches more nodes
+        let nodes = search_bench_positions(&mut TranspositionTable::new(size_kib * KIB));
+        println!("{size_kib} KiB: {nodes} nodes");
+        group.bench_with_input(BenchmarkId::from_parameter(size_kib), &size_kib, |b, &s| {
+            b.iter_batched_ref(
+                || TranspositionTable::new(s * KIB),
+                search_bench_positions,
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
 }
 
-fn mb_8(c: &mut Criterion) {
-    c.bench_function("mb_8", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(8 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-fn mb_16(c: &mut Criterion) {
-    c.bench_function("mb_16", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(16 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-fn mb_32(c: &mut Criterion) {
-    c.bench_function("mb_32", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(32 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-fn mb_64(c: &mut Criterion) {
-    c.bench_function("mb_64", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(64 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-fn mb_128(c: &mut Criterion) {
-    c.bench_function("mb_128", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(128 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-fn mb_256(c: &mut Criterion) {
-    c.bench_function("mb_256", |b| {
-        b.to_async(tokio::runtime::Builder::new_multi_thread().build().unwrap())
-            .iter(|| async {
-                let pos = Position::default();
-                let history = PositionHashHistory::new(pos.hash());
-                let (_c_tx, c_rx) = watch::channel(());
-                let mut tt = TranspositionTable::new(256 * MIB);
-                let stats = StatisticsHolder::new();
-                let mut searcher = Searcher::with_evaluator_and_config(
-                    black_box(history),
-                    black_box(pos),
-                    c_rx,
-                    MainEvaluator::new(),
-                    SearcherConfig { depth: Some(DEPTH) },
-                    &stats,
-                    &mut tt,
-                );
-                let (tx, _rx) = mpsc::unbounded_channel();
-                searcher.search(tx);
-            });
-    });
-}
-
-criterion_group! {
-    name = search;
-    config = Criterion::default();
-    targets = mb_2, mb_4, mb_8, mb_16, mb_32, mb_64, mb_128, mb_256
-}
+criterion_group!(search, tt_size);
 criterion_main!(search);
@@ -1,10 +1,14 @@
+use crate::evaluator::NnueEvaluator;
 use crate::position_hash_history::PositionHashHistory;
 use crate::searcher::{Searcher, SearcherConfig};
 use crate::statistics::StatisticsHolder;
-use crate::time_manager::TimeManagerHandle;
-use crate::transposition_table::TranspositionTable;
-use crate::{EngineUpdate, SearchConfiguration};
-use guts::Position;
+use crate::time_manager::{IterationReport, TimeManagerHandle, DEFAULT_MOVE_OVERHEAD};
+use crate::transposition_table::{TTEntry, TranspositionTable};
+use crate::{
+    ack, AckTx, CentipawnScore, EngineUpdate, EvaluatorKind, MoveResult, SearchConfiguration,
+    SHARED_COMPONENTS,
+};
+use guts::{BasicMoveBuffer, Move, Position};
 use log::{debug, info};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -13,15 +17,22 @@ use tokio::sync::watch;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::Instant;
 
+const INFO_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 enum AggregatorMessage {
     StartSearch(
+        AckTx,
         mpsc::UnboundedSender<EngineUpdate>,
         oneshot::Receiver<()>,
+        watch::Receiver<bool>,
         Position,
         PositionHashHistory,
         SearchConfiguration,
     ),
+    SetHashSize(AckTx, usize),
+    ClearHash(AckTx),
 }
 
 #[derive(Clone)]
@@ -43,17 +54,23 @@ impl AggregatorHandle {
         Self { sender }
     }
 
+    /// `done` is acknowledged right before the best move is sent.
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_search(
         &self,
+        done: AckTx,
         stop: oneshot::Receiver<()>,
+        pondering: watch::Receiver<bool>,
         position: Position,
         position_history: PositionHashHistory,
         search_configuration: SearchConfiguration,
         updates: mpsc::UnboundedSender<EngineUpdate>,
     ) {
         let msg = AggregatorMessage::StartSearch(
+            done,
             updates,
             stop,
+            pondering,
             position,
             position_history,
             search_configuration,
@@ -61,6 +78,22 @@ impl AggregatorHandle {
 
         let _ = self.sender.send(msg);
     }
+
+    pub async fn set_hash_size(&self, size_mib: usize) {
+        let (tx, rx) = ack();
+        let msg = AggregatorMessage::SetHashSize(tx, size_mib);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
+
+    pub async fn clear_hash(&self) {
+        let (tx, rx) = ack();
+        let msg = AggregatorMessage::ClearHash(tx);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
 }
 
 struct AggregatorActor {
@@ -74,23 +107,46 @@ impl AggregatorActor {
     async fn handle_event(&mut self, message: AggregatorMessage) {
         debug!("Got aggregator message");
         match message {
-            AggregatorMessage::StartSearch(updates, stop, position, position_history, config) => {
+            AggregatorMessage::StartSearch(
+                done,
+                updates,
+                stop,
+                mut pondering,
+                position,
+                position_history,
+                config,
+            ) => {
                 info!("Starting search");
                 let (result_tx, mut result_rx) = mpsc::unbounded_channel();
                 let (stop_tx, stop_rx) = watch::channel(());
                 let searcher_stop_rx = stop_rx.clone();
-                self.time_manager.update(config.remaining_time).await;
+                self.time_manager
+                    .update(
+                        config.remaining_time,
+                        config.move_overhead.unwrap_or(DEFAULT_MOVE_OVERHEAD),
+                    )
+                    .await;
+                let search_moves = find_moves(&position, &config.search_moves);
+                let root_position = position.clone();
                 let searcher_config = SearcherConfig {
                     depth: config.depth,
+                    nodes: config.nodes,
+                    mate: config.mate,
+                    search_moves: search_moves.clone(),
+                    multi_pv: config.multi_pv,
+                    ..SearcherConfig::default()
                 };
                 let stats = Arc::new(StatisticsHolder::new());
                 let stats_search = stats.clone();
+                let stats_iterations = stats.clone();
                 let mut stats_cancel_rx = self.cancellation_rx.clone();
                 let mut stats_stop_rx = stop_rx.clone();
                 let stats_updates_tx = updates.clone();
                 let start = Instant::now();
                 let _show_stats = tokio::task::spawn(async move {
-                    let mut interval = tokio::time::interval(Duration::from_secs(5));
+                    let mut interval = tokio::time::interval(INFO_INTERVAL);
+                    // The first tick is immediate, and there's nothing to report yet
+                    interval.tick().await;
                     let mut previous_stats = stats.get_statistics();
                     loop {
                         select! {
@@ -98,13 +154,17 @@ impl AggregatorActor {
                             _ = stats_cancel_rx.changed() => break,
                             _ = interval.tick() => {
                                 let new_stats = stats.get_statistics();
-                                let nps =  (new_stats.nodes_searched - previous_stats.nodes_searched) / interval.period().as_secs();
+                                let nps = (new_stats.nodes_searched - previous_stats.nodes_searched) * 1000
+                                    / interval.period().as_millis() as u64;
                                 let _ = stats_updates_tx.send(EngineUpdate::Info{
                                     nps: Some(nps),
                                     depth: Some(new_stats.current_depth),
                                     nodes: Some(new_stats.nodes_searched),
                                     tt_hits: Some(new_stats.tt_hits),
-                                    score: None,
+                                    time: Some(start.elapsed()),
+                                    hashfull: Some(new_stats.hashfull),
+                                    current_move: new_stats.current_move.clone(),
+                                    current_move_number: Some(new_stats.current_move_number),
                                 });
                                 previous_stats = new_stats;
                             }
@@ -117,47 +177,101 @@ impl AggregatorActor {
                     )
                 });
                 let search_tt = self.transposition_table.clone();
-                // Should end by itself after cancellation or dropping of the move receiver
-                let _search_task = std::thread::spawn(move || {
+                let evaluator = config.evaluator;
+                // Ends by itself once stopped, holding the table until then
+                let search_task = std::thread::spawn(move || {
                     let mut guard = search_tt.lock().unwrap();
-                    let mut searcher = Searcher::new(
-                        position_history,
-                        position,
-                        searcher_stop_rx,
-                        searcher_config,
-                        &stats_search,
-                        &mut guard,
-                    );
-                    searcher.search(result_tx)
+                    guard.new_search();
+                    match evaluator {
+                        EvaluatorKind::Classic => Searcher::new(
+                            position_history,
+                            position,
+                            searcher_stop_rx,
+                            searcher_config,
+                            &stats_search,
+                            &mut guard,
+                        )
+                        .search(result_tx),
+                        EvaluatorKind::Nnue => Searcher::with_evaluator_and_config(
+                            position_history,
+                            position,
+                            searcher_stop_rx,
+                            NnueEvaluator::new(),
+                            searcher_config,
+                            &stats_search,
+                            &mut guard,
+                        )
+                        .search(result_tx),
+                    }
                 });
-                let timer = self.time_manager.start(stop_rx);
+                let time_manager = &self.time_manager;
+                let mut timer_pondering = pondering.clone();
+                let (iterations_tx, iterations_rx) = mpsc::unbounded_channel();
+                // The clock only starts running once we're no longer pondering
+                let timer = async move {
+                    let _ = timer_pondering.wait_for(|p| !p).await;
+                    time_manager.start(stop_rx, iterations_rx).await
+                };
 
                 let mut result = None;
 
                 select! {
-                    _ = async { while let Some(r) = result_rx.recv().await {
-                        result = Some(r)
-                    }} => {}
+                    _ = async {
+                        while let Some(lines) = result_rx.recv().await {
+                            result = lines.first().cloned();
+                            if let Some(best) = &result {
+                                let _ = iterations_tx.send(IterationReport {
+                                    best_move: best.first_move().cloned(),
+                                    score: best.score(),
+                                });
+                            }
+                            let iteration_stats = stats_iterations.get_statistics();
+                            let time = start.elapsed();
+                            let _ = updates.send(EngineUpdate::MultiPv {
+                                lines,
+                                nodes: iteration_stats.nodes_searched,
+                                nps: (iteration_stats.nodes_searched as f64
+                                    / time.as_secs_f64().max(0.001))
+                                    as u64,
+                                time,
+                                hashfull: iteration_stats.hashfull,
+                            });
+                        }
+                        // A finished ponder search still has to wait for ponderhit or stop
+                        let _ = pondering.wait_for(|p| !p).await;
+                        if config.infinite {
+                            std::future::pending::<()>().await;
+                        }
+                    } => {}
                     _ = timer => {}
-                    _ = stop => {
-                        let _ = stop_tx.send(());
-                    }
-                    _ = self.cancellation_rx.changed() => {
-                        let _ = stop_tx.send(());
-                    }
+                    _ = stop => {}
+                    _ = self.cancellation_rx.changed() => {}
                 }
+                // The searcher notices on its next node, after that the table is free again
+                let _ = stop_tx.send(());
+                let _ = tokio::task::spawn_blocking(move || search_task.join()).await;
 
                 info!("Best move found: {:?}", result);
-                if let Some(result) = result {
-                    let _ = updates.send(EngineUpdate::Info {
-                        nps: None,
-                        depth: None,
-                        nodes: None,
-                        tt_hits: None,
-                        score: Some(result.score.0),
-                    });
-                    let _ = updates.send(EngineUpdate::BestMove(result));
-                }
+                // Every search ends with exactly one best move, even if it was stopped early
+                let result = match result {
+                    Some(result) if result.first_move().is_some() => result,
+                    _ => fallback_result(&root_position, &self.transposition_table, &search_moves),
+                };
+                let _ = done.send(());
+                let _ = updates.send(EngineUpdate::BestMove(result));
+            }
+            AggregatorMessage::SetHashSize(ack, size_mib) => {
+                info!("Resizing transposition table to {size_mib} MiB");
+                self.transposition_table
+                    .lock()
+                    .unwrap()
+                    .resize(size_mib as u64 * 1024 * 1024);
+                let _ = ack.send(());
+            }
+            AggregatorMessage::ClearHash(ack) => {
+                info!("Clearing transposition table");
+                self.transposition_table.lock().unwrap().clear();
+                let _ = ack.send(());
             }
         }
     }
@@ -182,3 +296,160 @@ impl AggregatorActor {
         }
     }
 }
+
+/// The TT move if it's legal and may be searched, otherwise the first such legal move. Without
+/// legal moves the result has no move at all. Locks the table, so the search has to be over.
+fn fallback_result(
+    position: &Position,
+    transposition_table: &Mutex<TranspositionTable>,
+    search_moves: &[Move],
+) -> MoveResult {
+    let mut buf = BasicMoveBuffer::new();
+    let in_check = SHARED_COMPONENTS
+        .move_generator
+        .generate_legal_moves_for(position, &mut buf);
+    let allowed = |m: &Move| search_moves.is_empty() || search_moves.contains(m);
+    let tt_entry = transposition_table
+        .lock()
+        .unwrap()
+        .get(position.hash())
+        .filter(|e| {
+            e.m.as_ref()
+                .is_some_and(|m| buf.iter().any(|l| l == m) && allowed(m))
+        });
+    if let Some(TTEntry {
+        score, m: Some(m), ..
+    }) = tt_entry
+    {
+        info!("Falling back to the TT move {m}");
+        return MoveResult::with_pv(score, vec![m]);
+    }
+    let first_legal = buf.iter().find(|m| allowed(m)).cloned();
+    match first_legal {
+        Some(m) => {
+            info!("Falling back to the first legal move {m}");
+            MoveResult::with_pv(CentipawnScore::ZERO, vec![m])
+        }
+        None if in_check => MoveResult::new(CentipawnScore::CHECKMATED),
+        None => MoveResult::new(CentipawnScore::ZERO),
+    }
+}
+
+fn find_moves(position: &Position, move_strings: &[String]) -> Vec<Move> {
+    let mut buf = BasicMoveBuffer::new();
+    let _ = SHARED_COMPONENTS
+        .move_generator
+        .generate_legal_moves_for(position, &mut buf);
+    move_strings
+        .iter()
+        .filter_map(|m| {
+            let found = buf.iter().find(|fm| &fm.as_uci() == m).cloned();
+            if found.is_none() {
+                info!("Ignoring unknown search move {m}");
+            }
+            found
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::ScoreBound;
+    use std::str::FromStr;
+
+    fn uci_move(position: &Position, uci: &str) -> Move {
+        find_moves(position, &[uci.to_owned()]).pop().unwrap()
+    }
+
+    #[test]
+    fn fallback_prefers_the_tt_move() {
+        let position = Position::default();
+        let tt = Mutex::new(TranspositionTable::default());
+        let first = fallback_result(&position, &tt, &[]);
+        assert!(first.first_move().is_some());
+
+        let m = uci_move(&position, "d2d4");
+        tt.lock().unwrap().set(TTEntry {
+            hash: position.hash(),
+            depth: 3,
+            score: CentipawnScore(25),
+            bound: ScoreBound::Exact,
+            m: Some(m.clone()),
+        });
+        let result = fallback_result(&position, &tt, &[]);
+        assert_eq!(result.first_move(), Some(&m));
+        assert_eq!(result.score(), CentipawnScore(25));
+
+        // Unless it's not one of the moves we may search
+        let g1f3 = uci_move(&position, "g1f3");
+        let result = fallback_result(&position, &tt, std::slice::from_ref(&g1f3));
+        assert_eq!(result.first_move(), Some(&g1f3));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[allow(clippy::await_holding_lock)]
+    async fn stopping_before_the_first_iteration_falls_back_to_the_tt_move() {
+        let position = Position::default();
+        let m = uci_move(&position, "h2h3");
+        let tt = Arc::new(Mutex::new(TranspositionTable::default()));
+        tt.lock().unwrap().set(TTEntry {
+            hash: position.hash(),
+            depth: 0,
+            score: CentipawnScore(-10),
+            bound: ScoreBound::Upper,
+            m: Some(m.clone()),
+        });
+        let (_cancellation_tx, cancellation_rx) = watch::channel(());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut actor = AggregatorActor::new(
+            receiver,
+            cancellation_rx,
+            TimeManagerHandle::new(),
+            tt.clone(),
+        );
+        tokio::spawn(async move { actor.run().await });
+        let handle = AggregatorHandle { sender };
+
+        // Holding the table keeps the search from getting anywhere before it's stopped
+        let guard = tt.lock().unwrap();
+        let (done_tx, done_rx) = ack();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (_pondering_tx, pondering_rx) = watch::channel(false);
+        let (updates_tx, mut updates_rx) = mpsc::unbounded_channel();
+        handle
+            .start_search(
+                done_tx,
+                stop_rx,
+                pondering_rx,
+                position.clone(),
+                PositionHashHistory::new(position.hash()),
+                SearchConfiguration {
+                    infinite: true,
+                    ..SearchConfiguration::default()
+                },
+                updates_tx,
+            )
+            .await;
+        stop_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(guard);
+
+        done_rx.await.unwrap();
+        loop {
+            if let EngineUpdate::BestMove(result) = updates_rx.recv().await.unwrap() {
+                assert_eq!(result.first_move(), Some(&m));
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn fallback_without_legal_moves_has_no_move() {
+        let mated = Position::from_str("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let tt = Mutex::new(TranspositionTable::default());
+        let result = fallback_result(&mated, &tt, &[]);
+        assert!(result.first_move().is_none());
+        assert_eq!(result.score(), CentipawnScore::CHECKMATED);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::evaluator::MainEvaluator;
+use crate::position_hash_history::PositionHashHistory;
+use crate::searcher::{Searcher, SearcherConfig};
+use crate::statistics::StatisticsHolder;
+use crate::transposition_table::TranspositionTable;
+use guts::Position;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+pub const BENCH_DEPTH: u16 = 6;
+
+/// Openings, middlegames and endgames with tactics, castling, en passant and promotions.
+pub const BENCH_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R w KQ - 3 9",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BenchResult {
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl BenchResult {
+    pub fn nps(&self) -> u64 {
+        (self.nodes as f64 / self.time.as_secs_f64().max(f64::EPSILON)) as u64
+    }
+}
+
+/// The node count is the signature, it only changes when the search itself does.
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total time (ms) : {}", self.time.as_millis())?;
+        writeln!(f, "Nodes searched  : {}", self.nodes)?;
+        write!(f, "Nodes/second    : {}", self.nps())
+    }
+}
+
+/// Searches every bench position to `depth` on the calling thread. Each search starts with an
+/// empty transposition table, so the node count only depends on the engine itself.
+pub fn bench(depth: u16) -> BenchResult {
+    let (_stop_tx, stop_rx) = watch::channel(());
+    let mut transposition_table = TranspositionTable::default();
+    let mut nodes = 0;
+    let start = Instant::now();
+    for fen in BENCH_POSITIONS {
+        let position = Position::from_str(fen).expect("Bench positions are valid");
+        transposition_table.clear();
+        let statistics = StatisticsHolder::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        Searcher::with_evaluator_and_config(
+            PositionHashHistory::new(position.hash()),
+            position,
+            stop_rx.clone(),
+            MainEvaluator::new(),
+            SearcherConfig {
+                depth: Some(depth),
+                ..SearcherConfig::default()
+            },
+            &statistics,
+            &mut transposition_table,
+        )
+        .search(tx);
+        nodes += statistics.nodes_searched();
+    }
+    BenchResult {
+        nodes,
+        time: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_is_deterministic() {
+        let first = bench(3);
+        assert!(first.nodes > 0);
+        assert_eq!(bench(3).nodes, first.nodes);
+    }
+}
@@ -1,14 +1,17 @@
 pub mod pst;
+pub mod terms;
 
 use crate::evaluator::Evaluator;
 use crate::{CentipawnScore, PieceSquareTable, SHARED_COMPONENTS};
-use guts::{Bitboard, Color, Piece, Position};
+use guts::{Bitboard, Color, Move, Position};
 use log::debug;
-use std::collections::HashMap;
+use pst::EvalAccumulator;
+use terms::{EvalParams, Term, MAX_PHASE};
 
 pub struct MainEvaluator<'a> {
-    base_values: HashMap<Piece, i32>,
+    params: &'a EvalParams,
     pst: &'a PieceSquareTable,
+    accumulator: EvalAccumulator,
 }
 
 impl Default for MainEvaluator<'static> {
@@ -19,51 +22,127 @@ impl Default for MainEvaluator<'static> {
 
 impl MainEvaluator<'static> {
     pub fn new() -> Self {
-        Self::with_pst(&SHARED_COMPONENTS.pst)
+        Self::with_params(&SHARED_COMPONENTS.eval_params, &SHARED_COMPONENTS.pst)
     }
 }
 
-impl<'a> MainEvaluator<'a> {
-    const DOUBLED_PAWNS_WEIGHT: i32 = 50;
+/// The piece-square table the engine plays with.
+pub fn embedded_pst() -> &'static PieceSquareTable {
+    &SHARED_COMPONENTS.pst
+}
+
+/// The weights of the other terms the engine plays with.
+pub fn embedded_params() -> &'static EvalParams {
+    &SHARED_COMPONENTS.eval_params
+}
 
+impl<'a> MainEvaluator<'a> {
     pub fn with_pst(pst: &'a PieceSquareTable) -> Self {
+        Self::with_params(&SHARED_COMPONENTS.eval_params, pst)
+    }
+
+    pub fn with_params(params: &'a EvalParams, pst: &'a PieceSquareTable) -> Self {
         Self {
-            base_values: HashMap::from([
-                (Piece::Pawn, 100),
-                (Piece::Bishop, 300),
-                (Piece::Knight, 300),
-                (Piece::Rook, 500),
-                (Piece::Queen, 900),
-                (Piece::King, 0),
-            ]),
+            params,
+            pst,
+            accumulator: EvalAccumulator::default(),
+        }
+    }
+}
+
+/// The contribution of one term, scores are tapered and from white's point of view.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TermTrace {
+    pub term: Term,
+    /// How often the term occurs for white minus how often it occurs for black.
+    pub count: i32,
+    pub midgame: i32,
+    pub endgame: i32,
+    pub score: i32,
+}
+
+/// Every part of an evaluation, from white's point of view.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EvalTrace {
+    pub terms: Vec<TermTrace>,
+    pub phase: i32,
+    pub pst: i32,
+    /// Rounded once instead of per term, so it can differ slightly from the sum of the parts.
+    pub total: i32,
+}
+
+impl MainEvaluator<'_> {
+    /// Splits the evaluation of `position` into its terms, computed from scratch.
+    pub fn trace(&self, position: &Position) -> EvalTrace {
+        let phase = terms::phase(position);
+        let terms = Term::ALL
+            .into_iter()
+            .zip(terms::features(position))
+            .filter(|&(_, count)| count != 0)
+            .map(|(term, count)| {
+                let (mg, eg) = self.params.get(term);
+                let (midgame, endgame) = (count * mg, count * eg);
+                TermTrace {
+                    term,
+                    count,
+                    midgame,
+                    endgame,
+                    score: (midgame * phase + endgame * (MAX_PHASE - phase)) / MAX_PHASE,
+                }
+            })
+            .collect();
+        let pst = match position.active_color() {
+            Color::White => self.pst.get(position),
+            Color::Black => -self.pst.get(position),
+        };
+        EvalTrace {
+            terms,
+            phase,
             pst,
+            total: self.params.evaluate(position) + pst,
         }
     }
 }
 
 impl Evaluator for MainEvaluator<'_> {
     fn evaluate(&self, position: &Position) -> CentipawnScore {
-        let mut score = 0;
-        let my_color = position.active_color();
-        for p in Piece::ALL {
-            score += (position.board()[my_color][p].count_ones() as i32)
-                * self.base_values.get(&p).unwrap();
-        }
-        for p in Piece::ALL {
-            score -= (position.board()[!my_color][p].count_ones() as i32)
-                * self.base_values.get(&p).unwrap();
-        }
+        let (terms, pst_score) = match self.accumulator.get() {
+            Some(sums) => {
+                debug_assert_eq!(sums.pst, self.pst.sum(position), "PST out of sync");
+                debug_assert_eq!(
+                    sums.material,
+                    self.params.material_sum(position),
+                    "Material out of sync"
+                );
+                (
+                    self.params.evaluate_with_material(position, sums.material),
+                    PieceSquareTable::taper(sums.pst, position),
+                )
+            }
+            None => (self.params.evaluate(position), self.pst.get(position)),
+        };
+        debug!("Got PST score: {pst_score}");
+        // The terms are from white's point of view
+        let score = match position.active_color() {
+            Color::White => terms,
+            Color::Black => -terms,
+        };
+
+        CentipawnScore(score + pst_score)
+    }
 
-        score -= doubled_tripled_pawns(position.board()[my_color][Piece::Pawn], my_color)
-            * Self::DOUBLED_PAWNS_WEIGHT;
-        score += doubled_tripled_pawns(position.board()[!my_color][Piece::Pawn], !my_color)
-            * Self::DOUBLED_PAWNS_WEIGHT;
+    fn set_position(&mut self, position: &Position) {
+        self.accumulator
+            .set_position(self.pst, self.params, position)
+    }
 
-        let pst_score = self.pst.get(position) * 1000.0;
-        debug!("Got corrected PST score: {pst_score}");
-        let pst_score = pst_score as i32;
+    fn make_move(&mut self, position: &Position, m: &Move) {
+        self.accumulator
+            .make_move(self.pst, self.params, position, m)
+    }
 
-        CentipawnScore(score + pst_score)
+    fn unmake_move(&mut self) {
+        self.accumulator.unmake_move()
     }
 }
 
@@ -107,46 +186,52 @@ mod tests {
         assert_eq!(evaluator.evaluate(&position), CentipawnScore(500));
     }
 
+    #[test]
+    fn trace_adds_up_to_the_evaluation() {
+        let evaluator = MainEvaluator::new();
+        let position =
+            Position::from_str("r1bqk2r/1p3ppp/2n5/3p4/1b1P4/2N2N2/PP3PPP/R2QKB1R b KQkq - 0 9")
+                .unwrap();
+        let trace = evaluator.trace(&position);
+        assert_eq!(trace.total, -evaluator.evaluate(&position).0);
+        let parts = trace.terms.iter().map(|t| t.score).sum::<i32>() + trace.pst;
+        assert!((parts - trace.total).abs() <= trace.terms.len() as i32);
+        let material = trace
+            .terms
+            .iter()
+            .find(|t| t.term == Term::Material(guts::Piece::Pawn))
+            .unwrap();
+        assert_eq!((material.count, material.score), (1, 100));
+    }
+
     #[test]
     fn test_doubled_pawns() {
-        let bb = Bitboard::from_iter(
-            [
-                Square::new(File::A, Rank::R2),
-                Square::new(File::A, Rank::R5),
-                Square::new(File::B, Rank::R3),
-            ]
-            .into_iter(),
-        );
+        let bb = Bitboard::from_iter([
+            Square::new(File::A, Rank::R2),
+            Square::new(File::A, Rank::R5),
+            Square::new(File::B, Rank::R3),
+        ]);
         assert_eq!(doubled_tripled_pawns(bb, Color::White), 1);
-        let bb = Bitboard::from_iter(
-            [
-                Square::new(File::A, Rank::R2),
-                Square::new(File::A, Rank::R5),
-                Square::new(File::B, Rank::R3),
-                Square::new(File::B, Rank::R7),
-            ]
-            .into_iter(),
-        );
+        let bb = Bitboard::from_iter([
+            Square::new(File::A, Rank::R2),
+            Square::new(File::A, Rank::R5),
+            Square::new(File::B, Rank::R3),
+            Square::new(File::B, Rank::R7),
+        ]);
         assert_eq!(doubled_tripled_pawns(bb, Color::White), 2);
-        let bb = Bitboard::from_iter(
-            [
-                Square::new(File::A, Rank::R2),
-                Square::new(File::A, Rank::R5),
-                Square::new(File::A, Rank::R3),
-            ]
-            .into_iter(),
-        );
+        let bb = Bitboard::from_iter([
+            Square::new(File::A, Rank::R2),
+            Square::new(File::A, Rank::R5),
+            Square::new(File::A, Rank::R3),
+        ]);
         assert_eq!(doubled_tripled_pawns(bb, Color::White), 2);
-        let bb = Bitboard::from_iter(
-            [
-                Square::new(File::A, Rank::R2),
-                Square::new(File::A, Rank::R5),
-                Square::new(File::A, Rank::R3),
-                Square::new(File::B, Rank::R2),
-                Square::new(File::B, Rank::R3),
-            ]
-            .into_iter(),
-        );
+        let bb = Bitboard::from_iter([
+            Square::new(File::A, Rank::R2),
+            Square::new(File::A, Rank::R5),
+            Square::new(File::A, Rank::R3),
+            Square::new(File::B, Rank::R2),
+            Square::new(File::B, Rank::R3),
+        ]);
         assert_eq!(doubled_tripled_pawns(bb, Color::White), 3);
     }
 }
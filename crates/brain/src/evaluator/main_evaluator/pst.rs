@@ -1,19 +1,59 @@
-use guts::{Color, Piece, Position, Square};
+use super::terms::EvalParams;
+use crate::evaluator::for_each_piece_change;
+use guts::{Color, Move, Piece, Position, Square};
+use std::ops::{AddAssign, SubAssign};
 
 const TABLE_SIZE: usize = 2 * 6 * 64; // mid/end * pieces * squares
+/// The trained values are in pawns per 1000 centipawns.
+const CENTIPAWNS_PER_VALUE: f64 = 1000.0;
+
+/// Midgame and endgame values in centipawns, positive is good for white.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PstScore {
+    pub midgame: i32,
+    pub endgame: i32,
+}
+
+impl AddAssign for PstScore {
+    fn add_assign(&mut self, rhs: Self) {
+        self.midgame += rhs.midgame;
+        self.endgame += rhs.endgame;
+    }
+}
+
+impl SubAssign for PstScore {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.midgame -= rhs.midgame;
+        self.endgame -= rhs.endgame;
+    }
+}
 
 #[derive(Debug)]
 pub struct PieceSquareTable {
+    /// The trained floating point values, kept for training.
     values: Vec<f64>,
+    /// The values used for evaluation, indexed by piece and square.
+    scores: Vec<PstScore>,
 }
 
 impl PieceSquareTable {
     pub fn zeroes() -> Self {
-        let mut values = Vec::with_capacity(TABLE_SIZE);
-        for _ in 0..TABLE_SIZE {
-            values.push(0.0)
+        Self::from_values(vec![0.0; TABLE_SIZE])
+    }
+
+    fn from_values(values: Vec<f64>) -> Self {
+        let mut scores = vec![PstScore::default(); Piece::NUM * 64];
+        for p in Piece::ALL {
+            for s in Square::ALL {
+                let (midgame_idx, endgame_idx) = Self::indices_for(p, s);
+                let to_centipawns = |v: f64| (v * CENTIPAWNS_PER_VALUE).round() as i32;
+                scores[Self::score_index(p, s)] = PstScore {
+                    midgame: to_centipawns(values[midgame_idx]),
+                    endgame: to_centipawns(values[endgame_idx]),
+                };
+            }
         }
-        Self { values }
+        Self { values, scores }
     }
 
     pub fn piece_values() -> Self {
@@ -33,38 +73,54 @@ impl PieceSquareTable {
                 pst.values[endgame_idx] = value;
             }
         }
-        pst
+        Self::from_values(pst.values)
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
     }
 
+    /// Only the floating point values are updated, this is meant for training.
     pub fn values_mut(&mut self) -> &mut [f64] {
         &mut self.values
     }
 
-    pub fn get(&self, position: &Position) -> f64 {
-        // White is positive, these tables are not current-relative
-        let sgn = if position.active_color() == Color::White {
-            1.0
-        } else {
-            -1.0
-        };
-        let mut res = 0.0;
-        let endgame_factor = Self::endgame_factor(position);
-        // TODO code duplication
+    /// The tapered score in centipawns for the side to move.
+    pub fn get(&self, position: &Position) -> i32 {
+        Self::taper(self.sum(position), position)
+    }
+
+    /// The untapered score of every piece, computed from scratch.
+    pub fn sum(&self, position: &Position) -> PstScore {
+        // These tables are not current-relative, the same square has the same value for both sides
+        let mut sum = PstScore::default();
         for p in Piece::ALL {
-            let white = position.board()[Color::White][p].into_iter();
-            for s in white {
-                let (midgame_idx, endgame_idx) = Self::indices_for(p, s);
-                res += self.values[midgame_idx] * (1.0 - endgame_factor);
-                res += self.values[endgame_idx] * endgame_factor;
+            for s in position.board()[Color::White][p] {
+                sum += self.score(p, s);
             }
-            let black = position.board()[Color::Black][p].into_iter();
-            for s in black {
-                let (midgame_idx, endgame_idx) = Self::indices_for(p, s);
-                res -= self.values[midgame_idx] * (1.0 - endgame_factor);
-                res -= self.values[endgame_idx] * endgame_factor;
+            for s in position.board()[Color::Black][p] {
+                sum -= self.score(p, s);
             }
         }
-        sgn * res
+        sum
+    }
+
+    /// Blends a white-relative sum into a score for the side to move, by the number of pieces left.
+    pub fn taper(sum: PstScore, position: &Position) -> i32 {
+        let pieces = position.board().all_pieces().count_ones() as i32;
+        let score = (sum.midgame * (pieces - 2) + sum.endgame * (40 - pieces)) / 38;
+        match position.active_color() {
+            Color::White => score,
+            Color::Black => -score,
+        }
+    }
+
+    pub fn score(&self, p: Piece, s: Square) -> PstScore {
+        self.scores[Self::score_index(p, s)]
+    }
+
+    fn score_index(p: Piece, s: Square) -> usize {
+        p.index() * 64 + s.bitboard_index()
     }
 
     pub fn position_as_vec(position: &Position) -> Vec<(usize, f64)> {
@@ -103,7 +159,72 @@ impl PieceSquareTable {
 
     pub fn from_bincode(data: &[u8]) -> Self {
         let values = bincode::deserialize(data).expect("Bincode for PST was invalid");
-        Self { values }
+        Self::from_values(values)
+    }
+}
+
+/// The untapered PST and material sums of a position, both positive when good for white.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Accumulated {
+    pub pst: PstScore,
+    pub material: PstScore,
+}
+
+/// Keeps the untapered PST and material sums up to date along the moves of a search, one entry
+/// per ply. Every move made on the position has to go through it, or it will be out of sync.
+#[derive(Debug, Default)]
+pub struct EvalAccumulator {
+    stack: Vec<Accumulated>,
+}
+
+impl EvalAccumulator {
+    pub fn set_position(
+        &mut self,
+        pst: &PieceSquareTable,
+        params: &EvalParams,
+        position: &Position,
+    ) {
+        self.stack.clear();
+        self.stack.push(Accumulated {
+            pst: pst.sum(position),
+            material: params.material_sum(position),
+        });
+    }
+
+    /// `None` until a position was set.
+    pub fn get(&self) -> Option<Accumulated> {
+        self.stack.last().copied()
+    }
+
+    /// Must be called with the position before `m` is made on it.
+    pub fn make_move(
+        &mut self,
+        pst: &PieceSquareTable,
+        params: &EvalParams,
+        position: &Position,
+        m: &Move,
+    ) {
+        let Some(&(mut sums)) = self.stack.last() else {
+            return;
+        };
+        for_each_piece_change(position, m, |color, p, s, added| {
+            // Adding a black piece is the same as removing a white one
+            if (color == Color::White) == added {
+                sums.pst += pst.score(p, s);
+                sums.material += params.material(p);
+            } else {
+                sums.pst -= pst.score(p, s);
+                sums.material -= params.material(p);
+            }
+        });
+        self.stack.push(sums);
+    }
+
+    pub fn unmake_move(&mut self) {
+        // The position that was set stays, so unbalanced calls can't empty the stack
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
     }
 }
 
@@ -116,3 +237,86 @@ pub fn dot(a: &[(usize, f64)], b: &[f64]) -> f64 {
 
     product
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::{BasicMoveBuffer, MoveGenerator};
+    use std::str::FromStr;
+
+    const POSITIONS: [&str; 4] = [
+        // Castling both ways for both sides
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        // Promotions, with and without capture
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        // En passant
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+
+    fn walk(
+        pst: &PieceSquareTable,
+        params: &EvalParams,
+        accumulator: &mut EvalAccumulator,
+        position: &mut Position,
+        depth: usize,
+    ) {
+        let expected = Accumulated {
+            pst: pst.sum(position),
+            material: params.material_sum(position),
+        };
+        assert_eq!(accumulator.get(), Some(expected), "{position}");
+        if depth == 0 {
+            return;
+        }
+        let mut buf = BasicMoveBuffer::new();
+        let _ = MoveGenerator::new().generate_legal_moves_for(position, &mut buf);
+        for m in buf.iter() {
+            accumulator.make_move(pst, params, position, m);
+            position.make_move(m);
+            walk(pst, params, accumulator, position, depth - 1);
+            position.unmake_move(m);
+            accumulator.unmake_move();
+        }
+    }
+
+    #[test]
+    fn accumulator_matches_sum_from_scratch() {
+        let pst = PieceSquareTable::from_bincode(crate::PST_DATA);
+        let params = EvalParams::default();
+        for fen in POSITIONS {
+            let mut position = Position::from_str(fen).unwrap();
+            let mut accumulator = EvalAccumulator::default();
+            accumulator.set_position(&pst, &params, &position);
+            walk(&pst, &params, &mut accumulator, &mut position, 3);
+        }
+    }
+
+    #[test]
+    fn integer_scores_match_training_values() {
+        let pst = PieceSquareTable::from_bincode(crate::PST_DATA);
+        for fen in POSITIONS {
+            let position = Position::from_str(fen).unwrap();
+            let float = dot(&PieceSquareTable::position_as_vec(&position), &pst.values)
+                * CENTIPAWNS_PER_VALUE;
+            let float = match position.active_color() {
+                Color::White => float,
+                Color::Black => -float,
+            };
+            // Every piece can be off by half a centipawn from rounding
+            let tolerance = position.board().all_pieces().count_ones() as f64 / 2.0 + 1.0;
+            assert!(
+                (pst.get(&position) as f64 - float).abs() <= tolerance,
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn piece_values_are_material() {
+        let pst = PieceSquareTable::piece_values();
+        let position =
+            Position::from_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(pst.get(&position), 5000);
+    }
+}
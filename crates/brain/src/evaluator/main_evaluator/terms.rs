@@ -0,0 +1,536 @@
+use super::doubled_tripled_pawns;
+use super::pst::PstScore;
+use crate::SHARED_COMPONENTS;
+use guts::{Bitboard, Color, Piece, Position, Square};
+
+/// The phase of a position with all minor and major pieces still on the board.
+pub const MAX_PHASE: i32 = 24;
+
+/// An evaluation term, each has a midgame and an endgame weight in [`EvalParams`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Term {
+    Material(Piece),
+    /// Per square attacked that isn't ours or covered by an enemy pawn, for knights up to queens.
+    Mobility(Piece),
+    PawnShield,
+    /// Per square next to our king that the opponent's pieces attack.
+    KingZoneAttack,
+    /// By relative rank, from the second to the seventh.
+    PassedPawn(usize),
+    IsolatedPawn,
+    BackwardPawn,
+    DoubledPawn,
+    BishopPair,
+    RookOnOpenFile,
+    RookOnSemiOpenFile,
+    /// For knights and bishops.
+    Outpost(Piece),
+}
+
+impl Term {
+    pub const NUM: usize = 25;
+    /// Every term, ordered by [`Self::slot`].
+    pub const ALL: [Term; Self::NUM] = [
+        Term::Material(Piece::Pawn),
+        Term::Material(Piece::Knight),
+        Term::Material(Piece::Bishop),
+        Term::Material(Piece::Rook),
+        Term::Material(Piece::Queen),
+        Term::Mobility(Piece::Knight),
+        Term::Mobility(Piece::Bishop),
+        Term::Mobility(Piece::Rook),
+        Term::Mobility(Piece::Queen),
+        Term::PawnShield,
+        Term::KingZoneAttack,
+        Term::PassedPawn(1),
+        Term::PassedPawn(2),
+        Term::PassedPawn(3),
+        Term::PassedPawn(4),
+        Term::PassedPawn(5),
+        Term::PassedPawn(6),
+        Term::IsolatedPawn,
+        Term::BackwardPawn,
+        Term::DoubledPawn,
+        Term::BishopPair,
+        Term::RookOnOpenFile,
+        Term::RookOnSemiOpenFile,
+        Term::Outpost(Piece::Knight),
+        Term::Outpost(Piece::Bishop),
+    ];
+
+    /// Index of the midgame weight, the endgame weight directly follows it.
+    pub fn index(self) -> usize {
+        let term = match self {
+            Term::Material(p) => p.index(),
+            Term::Mobility(p) => 4 + p.index(),
+            Term::PawnShield => 9,
+            Term::KingZoneAttack => 10,
+            Term::PassedPawn(rank) => 11 + rank - 1,
+            Term::IsolatedPawn => 17,
+            Term::BackwardPawn => 18,
+            Term::DoubledPawn => 19,
+            Term::BishopPair => 20,
+            Term::RookOnOpenFile => 21,
+            Term::RookOnSemiOpenFile => 22,
+            Term::Outpost(p) => 22 + p.index(),
+        };
+        2 * term
+    }
+
+    /// Position of the term in [`Features`].
+    pub fn slot(self) -> usize {
+        self.index() / 2
+    }
+}
+
+/// How often each term occurs for white minus how often it occurs for black, by [`Term::slot`].
+pub type Features = [i32; Term::NUM];
+
+/// Every weight of the hand-crafted evaluation in one vector, so it can be tuned as a whole.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EvalParams {
+    weights: Vec<i32>,
+}
+
+impl Default for EvalParams {
+    /// Hand-picked weights, the embedded `eval_params.bincode` holds these until a tuning run on
+    /// real games beats them.
+    fn default() -> Self {
+        let mut params = Self::zeroes();
+        let defaults = [
+            (Term::Material(Piece::Pawn), (100, 100)),
+            (Term::Material(Piece::Knight), (300, 300)),
+            (Term::Material(Piece::Bishop), (300, 300)),
+            (Term::Material(Piece::Rook), (500, 500)),
+            (Term::Material(Piece::Queen), (900, 900)),
+            (Term::Mobility(Piece::Knight), (4, 4)),
+            (Term::Mobility(Piece::Bishop), (5, 5)),
+            (Term::Mobility(Piece::Rook), (2, 4)),
+            (Term::Mobility(Piece::Queen), (1, 2)),
+            (Term::PawnShield, (10, 0)),
+            (Term::KingZoneAttack, (-8, -2)),
+            (Term::PassedPawn(1), (5, 10)),
+            (Term::PassedPawn(2), (5, 15)),
+            (Term::PassedPawn(3), (10, 25)),
+            (Term::PassedPawn(4), (20, 45)),
+            (Term::PassedPawn(5), (35, 70)),
+            (Term::PassedPawn(6), (60, 110)),
+            (Term::IsolatedPawn, (-10, -15)),
+            (Term::BackwardPawn, (-8, -10)),
+            (Term::DoubledPawn, (-15, -25)),
+            (Term::BishopPair, (30, 50)),
+            (Term::RookOnOpenFile, (25, 10)),
+            (Term::RookOnSemiOpenFile, (12, 6)),
+            (Term::Outpost(Piece::Knight), (20, 10)),
+            (Term::Outpost(Piece::Bishop), (12, 6)),
+        ];
+        for (term, weights) in defaults {
+            params.set(term, weights);
+        }
+        params
+    }
+}
+
+impl EvalParams {
+    pub const LEN: usize = 2 * Term::NUM;
+
+    pub fn zeroes() -> Self {
+        Self {
+            weights: vec![0; Self::LEN],
+        }
+    }
+
+    pub fn from_weights(weights: Vec<i32>) -> Self {
+        assert_eq!(weights.len(), Self::LEN, "Wrong number of eval weights");
+        Self { weights }
+    }
+
+    pub fn from_bincode(data: &[u8]) -> Self {
+        let weights = bincode::deserialize(data).expect("Bincode for eval params was invalid");
+        Self::from_weights(weights)
+    }
+
+    pub fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(&self.weights).expect("Eval params can always be serialized")
+    }
+
+    pub fn weights(&self) -> &[i32] {
+        &self.weights
+    }
+
+    pub fn weights_mut(&mut self) -> &mut [i32] {
+        &mut self.weights
+    }
+
+    pub fn get(&self, term: Term) -> (i32, i32) {
+        let idx = term.index();
+        (self.weights[idx], self.weights[idx + 1])
+    }
+
+    pub fn set(&mut self, term: Term, (midgame, endgame): (i32, i32)) {
+        let idx = term.index();
+        self.weights[idx] = midgame;
+        self.weights[idx + 1] = endgame;
+    }
+
+    /// The weights of a piece as material, kings have none.
+    pub fn material(&self, p: Piece) -> PstScore {
+        let (midgame, endgame) = match p {
+            Piece::King => (0, 0),
+            p => self.get(Term::Material(p)),
+        };
+        PstScore { midgame, endgame }
+    }
+
+    /// The untapered material balance, positive is good for white.
+    pub fn material_sum(&self, position: &Position) -> PstScore {
+        let board = position.board();
+        let mut sum = PstScore::default();
+        for p in Piece::ALL {
+            let count = board[Color::White][p].count_ones() as i32
+                - board[Color::Black][p].count_ones() as i32;
+            let value = self.material(p);
+            sum.midgame += count * value.midgame;
+            sum.endgame += count * value.endgame;
+        }
+        sum
+    }
+
+    /// The tapered score in centipawns, positive is good for white.
+    pub fn evaluate(&self, position: &Position) -> i32 {
+        self.evaluate_with_material(position, self.material_sum(position))
+    }
+
+    /// Like [`Self::evaluate`], with the material balance already known, so only the other terms
+    /// are computed.
+    pub fn evaluate_with_material(&self, position: &Position, material: PstScore) -> i32 {
+        let (mut midgame, mut endgame) = (material.midgame, material.endgame);
+        let features = positional_features(position);
+        for (term, count) in Term::ALL.into_iter().zip(features) {
+            let (mg, eg) = self.get(term);
+            midgame += count * mg;
+            endgame += count * eg;
+        }
+        let phase = phase(position);
+        (midgame * phase + endgame * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+
+    /// The position as sparse coefficients for the weights, their dot product is [`Self::evaluate`]
+    /// without the rounding.
+    pub fn position_as_vec(position: &Position) -> Vec<(usize, f64)> {
+        let midgame_factor = phase(position) as f64 / MAX_PHASE as f64;
+        Term::ALL
+            .into_iter()
+            .zip(features(position))
+            .filter(|&(_, count)| count != 0)
+            .flat_map(|(term, count)| {
+                let idx = term.index();
+                [
+                    (idx, count as f64 * midgame_factor),
+                    (idx + 1, count as f64 * (1.0 - midgame_factor)),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Goes from [`MAX_PHASE`] in the opening down to 0 with only pawns and kings left.
+pub fn phase(position: &Position) -> i32 {
+    let board = position.board();
+    let count =
+        |p| (board[Color::White][p].count_ones() + board[Color::Black][p].count_ones()) as i32;
+    let phase = count(Piece::Knight)
+        + count(Piece::Bishop)
+        + 2 * count(Piece::Rook)
+        + 4 * count(Piece::Queen);
+    phase.min(MAX_PHASE)
+}
+
+/// Every term of the position, including material.
+pub fn features(position: &Position) -> Features {
+    let mut features = positional_features(position);
+    let board = position.board();
+    for p in [
+        Piece::Pawn,
+        Piece::Knight,
+        Piece::Bishop,
+        Piece::Rook,
+        Piece::Queen,
+    ] {
+        features[Term::Material(p).slot()] =
+            board[Color::White][p].count_ones() as i32 - board[Color::Black][p].count_ones() as i32;
+    }
+    features
+}
+
+/// Every term except material, which the evaluator keeps track of along the search.
+fn positional_features(position: &Position) -> Features {
+    let mut features = [0; Term::NUM];
+    add_side_features(position, Color::White, 1, &mut features);
+    add_side_features(position, Color::Black, -1, &mut features);
+    features
+}
+
+/// Adds the counts of `color` to `features`, multiplied by `sign`.
+fn add_side_features(position: &Position, color: Color, sign: i32, features: &mut Features) {
+    let board = position.board();
+    let own = &board[color];
+    let their = &board[!color];
+    let occupied = board.all_pieces();
+    let own_pawns = own[Piece::Pawn];
+    let their_pawns = their[Piece::Pawn];
+    let own_pawn_attacks = pawn_attacks(own_pawns, color);
+    let their_pawn_attacks = pawn_attacks(their_pawns, !color);
+
+    let mut push = |term: Term, count: u32| features[term.slot()] += sign * count as i32;
+
+    let mobility_area = !own.all_pieces() & !their_pawn_attacks;
+    for p in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        let mobility = own[p]
+            .into_iter()
+            .map(|s| (attacks(p, s, occupied) & mobility_area).count_ones())
+            .sum();
+        push(Term::Mobility(p), mobility);
+    }
+
+    let king = own[Piece::King];
+    let shield_files = king | king.east_one() | king.west_one();
+    let shield =
+        shield_files.forward_one(color) | shield_files.forward_one(color).forward_one(color);
+    push(Term::PawnShield, (shield & own_pawns).count_ones());
+    let king_zone = king | king.surrounding();
+    let their_attacks = [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+        .into_iter()
+        .flat_map(|p| their[p].into_iter().map(move |s| (p, s)))
+        .fold(Bitboard::EMPTY, |acc, (p, s)| acc | attacks(p, s, occupied));
+    push(
+        Term::KingZoneAttack,
+        (king_zone & their_attacks).count_ones(),
+    );
+
+    let mut passed = [0; 6];
+    let (mut isolated, mut backward) = (0, 0);
+    for s in own_pawns {
+        let pawn = Bitboard::from_square(s);
+        let front = pawn.front_span(color);
+        let front_and_sides = front | front.east_one() | front.west_one();
+        if (front_and_sides & their_pawns).is_empty() {
+            let rank = relative_rank(s, color);
+            if (1..=6).contains(&rank) {
+                passed[rank - 1] += 1;
+            }
+        }
+        let file = pawn.file_fill();
+        let neighbours = own_pawns & (file.east_one() | file.west_one());
+        if neighbours.is_empty() {
+            isolated += 1;
+        } else {
+            let supporting_area = pawn.rear_fill(color);
+            let supporting_area = supporting_area.east_one() | supporting_area.west_one();
+            let stop = pawn.forward_one(color);
+            if (neighbours & supporting_area).is_empty() && !(stop & their_pawn_attacks).is_empty()
+            {
+                backward += 1;
+            }
+        }
+    }
+    for (rank, count) in passed.into_iter().enumerate() {
+        push(Term::PassedPawn(rank + 1), count);
+    }
+    push(Term::IsolatedPawn, isolated);
+    push(Term::BackwardPawn, backward);
+    push(
+        Term::DoubledPawn,
+        doubled_tripled_pawns(own_pawns, color) as u32,
+    );
+
+    push(
+        Term::BishopPair,
+        (own[Piece::Bishop].count_ones() >= 2) as u32,
+    );
+
+    let (mut open, mut semi_open) = (0, 0);
+    for s in own[Piece::Rook] {
+        let file = Bitboard::from_square(s).file_fill();
+        if (file & own_pawns).is_empty() {
+            if (file & their_pawns).is_empty() {
+                open += 1;
+            } else {
+                semi_open += 1;
+            }
+        }
+    }
+    push(Term::RookOnOpenFile, open);
+    push(Term::RookOnSemiOpenFile, semi_open);
+
+    for p in [Piece::Knight, Piece::Bishop] {
+        let outposts = own[p]
+            .into_iter()
+            .filter(|&s| {
+                let square = Bitboard::from_square(s);
+                let front = square.front_span(color);
+                let chasers = their_pawns & (front.east_one() | front.west_one());
+                (3..=5).contains(&relative_rank(s, color))
+                    && !(square & own_pawn_attacks).is_empty()
+                    && chasers.is_empty()
+            })
+            .count();
+        push(Term::Outpost(p), outposts as u32);
+    }
+}
+
+fn pawn_attacks(pawns: Bitboard, color: Color) -> Bitboard {
+    pawns.forward_left_one(color) | pawns.forward_right_one(color)
+}
+
+fn attacks(piece: Piece, s: Square, occupied: Bitboard) -> Bitboard {
+    let square = Bitboard::from_square(s);
+    let empty = !occupied;
+    match piece {
+        Piece::Knight => SHARED_COMPONENTS.move_generator.knight_attacks(s),
+        Piece::Bishop => square.diagonal_attackers(empty),
+        Piece::Rook => square.cardinal_attackers(empty),
+        Piece::Queen => square.diagonal_attackers(empty) | square.cardinal_attackers(empty),
+        Piece::Pawn | Piece::King => unreachable!("Only used for knights up to queens"),
+    }
+}
+
+/// 0 for the first rank of `color`, 7 for the last.
+fn relative_rank(s: Square, color: Color) -> usize {
+    match color {
+        Color::White => s.rank().index(),
+        Color::Black => 7 - s.rank().index(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn params_round_trip() {
+        let params = EvalParams::default();
+        assert_eq!(EvalParams::from_bincode(&params.to_bincode()), params);
+    }
+
+    #[test]
+    fn embedded_params_round_trip() {
+        let params = EvalParams::from_bincode(crate::EVAL_PARAMS_DATA);
+        assert_eq!(params.to_bincode(), crate::EVAL_PARAMS_DATA);
+    }
+
+    fn feature(fen: &str, term: Term) -> i32 {
+        let position = Position::from_str(fen).unwrap();
+        features(&position)[term.slot()]
+    }
+
+    #[test]
+    fn term_indices_are_unique_and_dense() {
+        let indices = Term::ALL.iter().map(|t| t.index()).collect::<Vec<_>>();
+        assert_eq!(indices, (0..Term::NUM).map(|i| 2 * i).collect::<Vec<_>>());
+        assert!(Term::ALL.iter().enumerate().all(|(i, t)| t.slot() == i));
+    }
+
+    #[test]
+    fn start_position_is_balanced() {
+        let position = Position::default();
+        assert_eq!(features(&position), [0; Term::NUM]);
+        assert_eq!(phase(&position), MAX_PHASE);
+        assert_eq!(EvalParams::default().evaluate(&position), 0);
+    }
+
+    #[test]
+    fn evaluation_is_color_symmetric() {
+        let params = EvalParams::default();
+        let position = Position::from_str(
+            "r1bq1rk1/pp3ppp/2n1pn2/2bp4/2P5/2N1PN2/PP1B1PPP/R2QKB1R w KQ - 0 8",
+        )
+        .unwrap();
+        let mirrored = Position::from_str(
+            "r2qkb1r/pp1b1ppp/2n1pn2/2p5/2BP4/2N1PN2/PP3PPP/R1BQ1RK1 b kq - 0 8",
+        )
+        .unwrap();
+        assert_eq!(params.evaluate(&position), -params.evaluate(&mirrored));
+    }
+
+    #[test]
+    fn dot_product_matches_evaluation() {
+        let params = EvalParams::default();
+        let weights = params
+            .weights()
+            .iter()
+            .map(|&w| w as f64)
+            .collect::<Vec<_>>();
+        for fen in [
+            "r1bq1rk1/pp3ppp/2n1pn2/2bp4/2P5/2N1PN2/PP1B1PPP/R2QKB1R w KQ - 0 8",
+            "8/5k2/3p4/1p1P4/1P6/5K2/8/8 w - - 0 1",
+            "4r1k1/1b3ppp/p7/1p6/4N3/1P4P1/P4PBP/3R2K1 b - - 0 25",
+        ] {
+            let position = Position::from_str(fen).unwrap();
+            let dot = crate::evaluator::main_evaluator::pst::dot(
+                &EvalParams::position_as_vec(&position),
+                &weights,
+            );
+            assert!(
+                (dot - params.evaluate(&position) as f64).abs() < 1.0,
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn pawn_structure() {
+        // a2 is isolated, c4 passed, e3 and e4 doubled, and black's f6 can't advance safely
+        let fen = "4k3/8/5p2/4p3/2P1P3/3PP3/P7/4K3 w - - 0 1";
+        assert_eq!(feature(fen, Term::IsolatedPawn), 1);
+        assert_eq!(feature(fen, Term::PassedPawn(3)), 1);
+        assert_eq!(feature(fen, Term::DoubledPawn), 1);
+        assert_eq!(feature(fen, Term::BackwardPawn), -1);
+        // c3 has fallen behind b4 and d5 controls c4
+        let fen = "4k3/8/8/3p4/1P6/2P5/8/4K3 w - - 0 1";
+        assert_eq!(feature(fen, Term::BackwardPawn), 1);
+        assert_eq!(feature(fen, Term::IsolatedPawn), -1);
+    }
+
+    #[test]
+    fn pieces() {
+        assert_eq!(
+            feature("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", Term::BishopPair),
+            1
+        );
+        assert_eq!(
+            feature("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", Term::RookOnOpenFile),
+            1
+        );
+        let fen = "4k3/p7/8/8/8/8/7P/R3K2R w - - 0 1";
+        assert_eq!(feature(fen, Term::RookOnOpenFile), 0);
+        assert_eq!(feature(fen, Term::RookOnSemiOpenFile), 1);
+        assert_eq!(
+            feature(
+                "4k3/8/3p4/3N4/4P3/8/8/4K3 w - - 0 1",
+                Term::Outpost(Piece::Knight)
+            ),
+            1
+        );
+        assert_eq!(
+            feature(
+                "4k3/8/2p5/3N4/4P3/8/8/4K3 w - - 0 1",
+                Term::Outpost(Piece::Knight)
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn king_safety() {
+        assert_eq!(
+            feature("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1", Term::PawnShield),
+            3
+        );
+        assert_eq!(
+            feature("6k1/8/8/8/8/8/8/4r1K1 w - - 0 1", Term::KingZoneAttack),
+            2
+        );
+    }
+}
@@ -1,11 +1,14 @@
 pub mod main_evaluator;
+pub mod nnue;
 #[cfg(test)]
 mod test_evaluator;
 
-use guts::Position;
+use crate::pv_table::MAX_PLY;
+use guts::{Bitboard, Color, File, Move, MoveType, Piece, Position, Square};
 use std::ops::Neg;
 
 pub use main_evaluator::MainEvaluator;
+pub use nnue::NnueEvaluator;
 #[cfg(test)]
 pub use test_evaluator::PieceCountEvaluator;
 
@@ -14,9 +17,57 @@ pub struct CentipawnScore(pub i32);
 
 impl CentipawnScore {
     pub const ZERO: Self = Self(0);
+    /// Checkmated at the root, see [`mated_in`](Self::mated_in) for mates further away.
     pub const CHECKMATED: Self = Self(Self::MIN.0 / 2);
     pub const MAX: Self = Self(i32::MAX);
     pub const MIN: Self = Self(i32::MIN + 1); // To avoid -MIN = MIN
+
+    /// Checkmated `ply` half moves from the root.
+    pub fn mated_in(ply: u16) -> Self {
+        Self(Self::CHECKMATED.0 + ply as i32)
+    }
+
+    /// Whether the score is a mate within reach of the search, for either side.
+    pub fn is_mate(self) -> bool {
+        self.mate_distance().is_some()
+    }
+
+    /// Half moves to the mate, positive when the side to move mates and negative when it gets
+    /// mated.
+    pub fn mate_plies(self) -> Option<i32> {
+        self.mate_distance()
+            .map(|distance| self.0.signum() * distance)
+    }
+
+    fn mate_distance(self) -> Option<i32> {
+        let distance = Self::CHECKMATED.0.abs() - self.0.abs();
+        (0..=MAX_PLY as i32).contains(&distance).then_some(distance)
+    }
+
+    /// Full moves to the mate like UCI's `score mate`, negative when getting mated.
+    pub fn mate_moves(self) -> Option<i32> {
+        self.mate_plies()
+            .map(|plies| plies.signum() * ((plies.abs() + 1) / 2))
+    }
+
+    /// Mates count from the root in the search, but from the position itself in the TT so the
+    /// entry stays right wherever the position comes up again.
+    pub fn to_tt(self, ply: u16) -> Self {
+        if self.is_mate() {
+            Self(self.0 + self.0.signum() * ply as i32)
+        } else {
+            self
+        }
+    }
+
+    /// Reverses [`to_tt`](Self::to_tt) for a probe `ply` half moves from the root.
+    pub fn from_tt(self, ply: u16) -> Self {
+        if self.is_mate() {
+            Self(self.0 - self.0.signum() * ply as i32)
+        } else {
+            self
+        }
+    }
 }
 
 impl Neg for CentipawnScore {
@@ -29,6 +80,14 @@ impl Neg for CentipawnScore {
 
 pub trait Evaluator {
     fn evaluate(&self, position: &Position) -> CentipawnScore;
+
+    /// Starts incremental evaluation from `position`, evaluators without state can ignore this.
+    fn set_position(&mut self, _position: &Position) {}
+
+    /// Called with the position before `m` is made on it.
+    fn make_move(&mut self, _position: &Position, _m: &Move) {}
+
+    fn unmake_move(&mut self) {}
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -37,3 +96,65 @@ pub enum ScoreBound {
     Upper,
     Lower,
 }
+
+/// Calls `f(color, piece, square, added)` for every piece `m` removes from or puts on the board,
+/// `position` is the one before the move.
+pub(crate) fn for_each_piece_change(
+    position: &Position,
+    m: &Move,
+    mut f: impl FnMut(Color, Piece, Square, bool),
+) {
+    let color = position.active_color();
+    f(color, m.piece(), m.from(), false);
+    if m.move_type().contains(MoveType::EN_PASSANT) {
+        let captured = Bitboard::from_square(m.to())
+            .forward_one(!color)
+            .first_set_square()
+            .unwrap();
+        f(!color, Piece::Pawn, captured, false);
+    } else if m.move_type().contains(MoveType::CAPTURE) {
+        let captured = position.board()[!color].piece_at(m.to()).unwrap();
+        f(!color, captured, m.to(), false);
+    } else if m
+        .move_type()
+        .intersects(MoveType::CASTLE_KINGSIDE | MoveType::CASTLE_QUEENSIDE)
+    {
+        let (rook_from, rook_to) = if m.move_type().contains(MoveType::CASTLE_KINGSIDE) {
+            (File::H, File::F)
+        } else {
+            (File::A, File::D)
+        };
+        let rank = m.from().rank();
+        f(color, Piece::Rook, Square::new(rook_from, rank), false);
+        f(color, Piece::Rook, Square::new(rook_to, rank), true);
+    }
+    f(color, m.promotion().unwrap_or(m.piece()), m.to(), true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_scores() {
+        let mating = -CentipawnScore::mated_in(3);
+        assert_eq!(mating.mate_plies(), Some(3));
+        assert_eq!(mating.mate_moves(), Some(2));
+        assert_eq!(CentipawnScore::mated_in(2).mate_moves(), Some(-1));
+        assert!(mating > -CentipawnScore::mated_in(5));
+        assert!(!CentipawnScore(900).is_mate());
+        assert!(!CentipawnScore::MAX.is_mate());
+        assert!(!CentipawnScore::MIN.is_mate());
+    }
+
+    #[test]
+    fn tt_scores_count_from_the_position() {
+        // Mated 5 plies from the root, seen from a node at ply 2
+        let mated = CentipawnScore::mated_in(5);
+        assert_eq!(mated.to_tt(2), CentipawnScore::mated_in(3));
+        assert_eq!(mated.to_tt(2).from_tt(4), CentipawnScore::mated_in(7));
+        assert_eq!((-mated).to_tt(2), -CentipawnScore::mated_in(3));
+        assert_eq!((-mated).to_tt(2).from_tt(2), -mated);
+        assert_eq!(CentipawnScore(50).to_tt(3), CentipawnScore(50));
+    }
+}
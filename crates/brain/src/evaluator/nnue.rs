@@ -0,0 +1,344 @@
+use crate::evaluator::main_evaluator::pst::PieceSquareTable;
+use crate::evaluator::main_evaluator::terms::{EvalParams, Term};
+use crate::evaluator::{for_each_piece_change, Evaluator};
+use crate::{CentipawnScore, SHARED_COMPONENTS};
+use guts::{Color, Move, Piece, Position, Square};
+
+/// One input per color, piece and square.
+pub const INPUT_SIZE: usize = Color::NUM * Piece::NUM * Square::NUM;
+pub const HIDDEN_SIZE: usize = 64;
+/// A hidden activation of 1.0 is stored as this, it's also where the clipped ReLU clips.
+pub const QA: i32 = 255;
+/// Quantization of the output weights.
+pub const QB: i32 = 64;
+/// Centipawns for a network output of 1.0.
+pub const OUTPUT_SCALE: i32 = 400;
+
+/// The hidden layer before activation, aligned so the updates can be vectorized.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C, align(64))]
+struct Accumulator([i16; HIDDEN_SIZE]);
+
+impl Accumulator {
+    fn add(&mut self, weights: &Accumulator) {
+        for (a, w) in self.0.iter_mut().zip(weights.0.iter()) {
+            *a += *w;
+        }
+    }
+
+    fn sub(&mut self, weights: &Accumulator) {
+        for (a, w) in self.0.iter_mut().zip(weights.0.iter()) {
+            *a -= *w;
+        }
+    }
+}
+
+/// A quantized 768→[`HIDDEN_SIZE`]→1 network with a clipped ReLU, its output is from white's
+/// point of view.
+#[derive(Debug)]
+pub struct Network {
+    feature_weights: Vec<Accumulator>,
+    feature_bias: Accumulator,
+    output_weights: [i16; HIDDEN_SIZE],
+    output_bias: i32,
+}
+
+impl Network {
+    /// `feature_weights` holds [`HIDDEN_SIZE`] weights for every input in turn, the output bias is
+    /// scaled by both [`QA`] and [`QB`].
+    pub fn new(
+        feature_weights: &[i16],
+        feature_bias: &[i16],
+        output_weights: &[i16],
+        output_bias: i32,
+    ) -> Self {
+        assert_eq!(feature_weights.len(), INPUT_SIZE * HIDDEN_SIZE);
+        assert_eq!(feature_bias.len(), HIDDEN_SIZE);
+        assert_eq!(output_weights.len(), HIDDEN_SIZE);
+        let to_accumulator = |weights: &[i16]| Accumulator(weights.try_into().unwrap());
+        Self {
+            feature_weights: feature_weights
+                .chunks_exact(HIDDEN_SIZE)
+                .map(to_accumulator)
+                .collect(),
+            feature_bias: to_accumulator(feature_bias),
+            output_weights: output_weights.try_into().unwrap(),
+            output_bias,
+        }
+    }
+
+    /// A network that reproduces material and the averaged PST, so there's something sensible to
+    /// embed before a network has been trained.
+    pub fn bootstrap(params: &EvalParams, pst: &PieceSquareTable) -> Self {
+        // Half the neurons count up the score for white, the other half for black. Each covers
+        // the next QA units of it, so together they span a much larger range than one could.
+        let half = HIDDEN_SIZE / 2;
+        let to_units = |centipawns: i32| (centipawns * 2 + 2 * centipawns.signum()) / 5;
+        let mut feature_weights = vec![0; INPUT_SIZE * HIDDEN_SIZE];
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                let (material_mg, material_eg) = match piece {
+                    Piece::King => (0, 0),
+                    _ => params.get(Term::Material(piece)),
+                };
+                for square in Square::ALL {
+                    let score = pst.score(piece, square);
+                    let value =
+                        to_units((material_mg + material_eg + score.midgame + score.endgame) / 2);
+                    let value = match color {
+                        Color::White => value as i16,
+                        Color::Black => -value as i16,
+                    };
+                    let row = Self::feature_index(color, piece, square) * HIDDEN_SIZE;
+                    for neuron in 0..half {
+                        feature_weights[row + neuron] = value;
+                        feature_weights[row + half + neuron] = -value;
+                    }
+                }
+            }
+        }
+        let feature_bias = (0..HIDDEN_SIZE)
+            .map(|neuron| -((neuron % half) as i32 * QA) as i16)
+            .collect::<Vec<_>>();
+        // 2.5 centipawns per unit
+        let output_weight = (5 * QA * QB / (2 * OUTPUT_SCALE)) as i16;
+        let output_weights = (0..HIDDEN_SIZE)
+            .map(|neuron| {
+                if neuron < half {
+                    output_weight
+                } else {
+                    -output_weight
+                }
+            })
+            .collect::<Vec<_>>();
+        Self::new(&feature_weights, &feature_bias, &output_weights, 0)
+    }
+
+    pub fn from_bincode(data: &[u8]) -> Self {
+        let (feature_weights, feature_bias, output_weights, output_bias): (
+            Vec<i16>,
+            Vec<i16>,
+            Vec<i16>,
+            i32,
+        ) = bincode::deserialize(data).expect("Bincode for network was invalid");
+        Self::new(
+            &feature_weights,
+            &feature_bias,
+            &output_weights,
+            output_bias,
+        )
+    }
+
+    pub fn to_bincode(&self) -> Vec<u8> {
+        let feature_weights = self
+            .feature_weights
+            .iter()
+            .flat_map(|a| a.0)
+            .collect::<Vec<_>>();
+        bincode::serialize(&(
+            feature_weights,
+            self.feature_bias.0.to_vec(),
+            self.output_weights.to_vec(),
+            self.output_bias,
+        ))
+        .expect("Network can always be serialized")
+    }
+
+    pub fn feature_index(color: Color, piece: Piece, square: Square) -> usize {
+        (color.index() * Piece::NUM + piece.index()) * Square::NUM + square.bitboard_index()
+    }
+
+    fn refresh(&self, position: &Position) -> Accumulator {
+        let mut accumulator = self.feature_bias;
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                for square in position.board()[color][piece] {
+                    accumulator
+                        .add(&self.feature_weights[Self::feature_index(color, piece, square)]);
+                }
+            }
+        }
+        accumulator
+    }
+
+    fn output(&self, accumulator: &Accumulator) -> i32 {
+        let sum = accumulator
+            .0
+            .iter()
+            .zip(self.output_weights.iter())
+            .map(|(&a, &w)| (a as i32).clamp(0, QA) * w as i32)
+            .sum::<i32>();
+        // Trained weights easily take the scaled sum past i32
+        let scaled = (sum as i64 + self.output_bias as i64) * OUTPUT_SCALE as i64;
+        (scaled / (QA * QB) as i64) as i32
+    }
+
+    /// The score in centipawns for white, computed from scratch.
+    pub fn evaluate(&self, position: &Position) -> i32 {
+        self.output(&self.refresh(position))
+    }
+}
+
+pub struct NnueEvaluator<'a> {
+    network: &'a Network,
+    /// One accumulator for every ply since the position was set.
+    stack: Vec<Accumulator>,
+}
+
+impl Default for NnueEvaluator<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NnueEvaluator<'static> {
+    pub fn new() -> Self {
+        Self::with_network(&SHARED_COMPONENTS.network)
+    }
+}
+
+impl<'a> NnueEvaluator<'a> {
+    pub fn with_network(network: &'a Network) -> Self {
+        Self {
+            network,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Evaluator for NnueEvaluator<'_> {
+    fn evaluate(&self, position: &Position) -> CentipawnScore {
+        let score = match self.stack.last() {
+            Some(accumulator) => {
+                debug_assert_eq!(
+                    *accumulator,
+                    self.network.refresh(position),
+                    "NNUE accumulator out of sync"
+                );
+                self.network.output(accumulator)
+            }
+            None => self.network.evaluate(position),
+        };
+        match position.active_color() {
+            Color::White => CentipawnScore(score),
+            Color::Black => CentipawnScore(-score),
+        }
+    }
+
+    fn set_position(&mut self, position: &Position) {
+        self.stack.clear();
+        self.stack.push(self.network.refresh(position));
+    }
+
+    fn make_move(&mut self, position: &Position, m: &Move) {
+        let Some(&(mut accumulator)) = self.stack.last() else {
+            return;
+        };
+        for_each_piece_change(position, m, |color, piece, square, added| {
+            let weights =
+                &self.network.feature_weights[Network::feature_index(color, piece, square)];
+            if added {
+                accumulator.add(weights)
+            } else {
+                accumulator.sub(weights)
+            }
+        });
+        self.stack.push(accumulator);
+    }
+
+    fn unmake_move(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::{BasicMoveBuffer, MoveGenerator};
+    use std::str::FromStr;
+
+    #[test]
+    fn embedded_network_round_trips() {
+        let network = Network::from_bincode(crate::NETWORK_DATA);
+        assert_eq!(network.to_bincode(), crate::NETWORK_DATA);
+    }
+
+    #[test]
+    fn bootstrap_counts_material() {
+        let pst = PieceSquareTable::zeroes();
+        let network = Network::bootstrap(&EvalParams::default(), &pst);
+        let evaluator = NnueEvaluator::with_network(&network);
+        for (fen, score) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w KQkq - 0 1",
+                -500,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR b KQkq - 0 1",
+                500,
+            ),
+            ("4k3/8/8/8/8/8/8/QQQQK3 w - - 0 1", 3600),
+        ] {
+            let position = Position::from_str(fen).unwrap();
+            assert_eq!(
+                evaluator.evaluate(&position),
+                CentipawnScore(score),
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn large_outputs_do_not_overflow() {
+        let network = Network::new(
+            &vec![0; INPUT_SIZE * HIDDEN_SIZE],
+            &[QA as i16; HIDDEN_SIZE],
+            &[i16::MAX; HIDDEN_SIZE],
+            0,
+        );
+        let sum = (HIDDEN_SIZE as i64) * QA as i64 * i16::MAX as i64;
+        assert_eq!(
+            network.evaluate(&Position::default()) as i64,
+            sum * OUTPUT_SCALE as i64 / (QA * QB) as i64
+        );
+    }
+
+    fn walk(evaluator: &mut NnueEvaluator, position: &mut Position, depth: usize) {
+        assert_eq!(
+            evaluator.evaluate(position),
+            NnueEvaluator::new().evaluate(position)
+        );
+        if depth == 0 {
+            return;
+        }
+        let mut buf = BasicMoveBuffer::new();
+        let _ = MoveGenerator::new().generate_legal_moves_for(position, &mut buf);
+        for m in buf.iter() {
+            evaluator.make_move(position, m);
+            position.make_move(m);
+            walk(evaluator, position, depth - 1);
+            position.unmake_move(m);
+            evaluator.unmake_move();
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_refresh() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ] {
+            let mut position = Position::from_str(fen).unwrap();
+            let mut evaluator = NnueEvaluator::new();
+            evaluator.set_position(&position);
+            walk(&mut evaluator, &mut position, 3);
+        }
+    }
+}
@@ -1,14 +1,19 @@
 mod aggregator;
+pub mod bench;
 pub mod evaluator;
 pub mod position_hash_history;
 pub mod priority_buffer;
+mod pv_table;
 pub mod searcher;
+mod see;
 pub mod statistics;
 mod time_manager;
 pub mod transposition_table;
 
 use crate::aggregator::AggregatorHandle;
 use crate::evaluator::main_evaluator::pst::PieceSquareTable;
+use crate::evaluator::main_evaluator::terms::EvalParams;
+use crate::evaluator::nnue::Network;
 use crate::evaluator::CentipawnScore;
 use crate::position_hash_history::PositionHashHistory;
 use guts::{BasicMoveBuffer, Color, Move, MoveGenerator, Position};
@@ -19,6 +24,8 @@ use thiserror::Error;
 use tokio::select;
 use tokio::sync::{mpsc, oneshot, watch};
 
+pub use time_manager::DEFAULT_MOVE_OVERHEAD;
+
 type AnswerRx<T> = oneshot::Receiver<T>;
 type AnswerTx<T> = oneshot::Sender<T>;
 type AckRx = AnswerRx<()>;
@@ -35,6 +42,8 @@ fn answer<T>() -> (AnswerTx<T>, AnswerRx<T>) {
 #[derive(Debug, Clone)]
 pub struct MoveResult {
     score: CentipawnScore,
+    depth: u16,
+    seldepth: u16,
     pv: Vec<Move>,
 }
 
@@ -42,6 +51,8 @@ impl MoveResult {
     pub fn new(score: CentipawnScore) -> Self {
         Self {
             score,
+            depth: 0,
+            seldepth: 0,
             pv: Vec::new(),
         }
     }
@@ -50,20 +61,40 @@ impl MoveResult {
         self.score
     }
 
-    pub fn first_move(&self) -> Option<&Move> {
-        self.pv.last()
+    /// The depth of the iteration that produced this result, 0 if it didn't come from the root.
+    pub fn depth(&self) -> u16 {
+        self.depth
+    }
+
+    /// The deepest ply reached by the iteration, including quiescence.
+    pub fn seldepth(&self) -> u16 {
+        self.seldepth
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: u16, seldepth: u16) {
+        self.depth = depth;
+        self.seldepth = seldepth;
+    }
+
+    pub(crate) fn with_pv(score: CentipawnScore, pv: Vec<Move>) -> Self {
+        Self {
+            pv,
+            ..Self::new(score)
+        }
     }
 
-    pub fn _pv(&self) -> &[Move] {
-        &self.pv
+    pub fn first_move(&self) -> Option<&Move> {
+        self.pv.first()
     }
 
-    pub fn push(&mut self, m: Move) {
-        self.pv.push(m)
+    /// The reply we expect from the opponent, if the PV is long enough to have one.
+    pub fn ponder_move(&self) -> Option<&Move> {
+        self.pv.get(1)
     }
 
-    pub fn invert_score(&mut self) {
-        self.score = -self.score;
+    /// The principal variation in the order the moves are played.
+    pub fn pv(&self) -> impl Iterator<Item = &Move> {
+        self.pv.iter()
     }
 }
 
@@ -71,6 +102,29 @@ impl MoveResult {
 pub struct SearchConfiguration {
     pub depth: Option<u16>,
     pub remaining_time: Option<RemainingTime>,
+    pub nodes: Option<u64>,
+    /// Look for a mate in this many moves, not plies.
+    pub mate: Option<u16>,
+    /// Keep searching until stopped, even when a depth or mate limit has been reached.
+    pub infinite: bool,
+    /// Only consider these root moves, in UCI notation. Empty means all legal moves.
+    pub search_moves: Vec<String>,
+    /// The number of best root moves to report, 0 and 1 both mean just the best one.
+    pub multi_pv: usize,
+    /// Search without a time limit until [`EngineHandle::ponder_hit`] is called.
+    pub ponder: bool,
+    /// Time kept in reserve for communication delays, [`DEFAULT_MOVE_OVERHEAD`] if not set.
+    pub move_overhead: Option<Duration>,
+    pub evaluator: EvaluatorKind,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum EvaluatorKind {
+    /// The hand-crafted evaluation.
+    #[default]
+    Classic,
+    /// The embedded neural network.
+    Nnue,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -78,29 +132,38 @@ pub enum RemainingTime {
     ForGame {
         remaining: Duration,
         increment: Duration,
+        moves_to_go: Option<u16>,
     },
     ForMove(Duration),
 }
 
 #[derive(Debug)]
 enum EngineMessage {
-    SetInitialValues(AckTx, Position, Vec<String>),
-    SetMoves(AckTx, Vec<String>),
+    SetInitialValues(AnswerTx<Result<(), EngineError>>, Position, Vec<String>),
+    SetMoves(AnswerTx<Result<(), EngineError>>, Vec<String>),
     CurrentColor(AnswerTx<Color>),
+    CurrentPosition(AnswerTx<Position>),
     Go(
         AnswerTx<Result<mpsc::UnboundedReceiver<EngineUpdate>, EngineError>>,
         SearchConfiguration,
     ),
     Stop(AnswerTx<bool>),
+    PonderHit(AnswerTx<bool>),
+    SetHashSize(AnswerTx<Result<(), EngineError>>, usize),
+    ClearHash(AnswerTx<Result<(), EngineError>>),
 }
 
 static PST_DATA: &[u8] = include_bytes!("../resources/pst.bincode");
+static EVAL_PARAMS_DATA: &[u8] = include_bytes!("../resources/eval_params.bincode");
+static NETWORK_DATA: &[u8] = include_bytes!("../resources/nnue.bincode");
 
 static SHARED_COMPONENTS: Lazy<EngineSharedComponents> = Lazy::new(|| {
     let pst = PieceSquareTable::from_bincode(PST_DATA);
     EngineSharedComponents {
         move_generator: MoveGenerator::new(),
         pst,
+        eval_params: EvalParams::from_bincode(EVAL_PARAMS_DATA),
+        network: Network::from_bincode(NETWORK_DATA),
     }
 });
 
@@ -108,24 +171,39 @@ static SHARED_COMPONENTS: Lazy<EngineSharedComponents> = Lazy::new(|| {
 struct EngineSharedComponents {
     move_generator: MoveGenerator,
     pst: PieceSquareTable,
+    eval_params: EvalParams,
+    network: Network,
 }
 
 #[derive(Debug, Error)]
 pub enum EngineError {
     #[error("calculation already in progress")]
     CalculationAlreadyInProgress,
+    #[error("illegal move {0}")]
+    IllegalMove(String),
 }
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum EngineUpdate {
     BestMove(MoveResult),
+    /// The best lines of a finished iteration, best first, with the search totals so far.
+    MultiPv {
+        lines: Vec<MoveResult>,
+        nodes: u64,
+        nps: u64,
+        time: Duration,
+        hashfull: u64,
+    },
     Info {
         nps: Option<u64>,
         depth: Option<u64>,
         nodes: Option<u64>,
         tt_hits: Option<u64>,
-        score: Option<i32>,
+        time: Option<Duration>,
+        hashfull: Option<u64>,
+        current_move: Option<Move>,
+        current_move_number: Option<u64>,
     },
 }
 
@@ -144,16 +222,22 @@ impl EngineHandle {
         Self { sender }
     }
 
-    pub async fn set_initial_values(&self, position: Position, moves: Vec<String>) {
-        let (tx, rx) = ack();
+    /// Keeps the previous position if any of the moves is illegal.
+    pub async fn set_initial_values(
+        &self,
+        position: Position,
+        moves: Vec<String>,
+    ) -> Result<(), EngineError> {
+        let (tx, rx) = answer();
         let msg = EngineMessage::SetInitialValues(tx, position, moves);
 
         let _ = self.sender.send(msg);
         rx.await.expect("Actor task was killed")
     }
 
-    pub async fn set_moves(&self, moves: Vec<String>) {
-        let (tx, rx) = ack();
+    /// Plays `moves` from the initial position, keeps the previous position if any is illegal.
+    pub async fn set_moves(&self, moves: Vec<String>) -> Result<(), EngineError> {
+        let (tx, rx) = answer();
         let msg = EngineMessage::SetMoves(tx, moves);
 
         let _ = self.sender.send(msg);
@@ -168,6 +252,14 @@ impl EngineHandle {
         rx.await.expect("Actor task was killed")
     }
 
+    pub async fn current_position(&self) -> Position {
+        let (tx, rx) = answer();
+        let msg = EngineMessage::CurrentPosition(tx);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
+
     pub async fn go(
         &self,
         search_configuration: SearchConfiguration,
@@ -186,11 +278,40 @@ impl EngineHandle {
         let _ = self.sender.send(msg);
         rx.await.expect("Actor task was killed")
     }
+
+    /// Switches a pondering search to a normal timed search.
+    pub async fn ponder_hit(&self) -> bool {
+        let (tx, rx) = answer();
+        let msg = EngineMessage::PonderHit(tx);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
+
+    /// Resizes the transposition table, clearing it in the process. Refused while searching.
+    pub async fn set_hash_size(&self, size_mib: usize) -> Result<(), EngineError> {
+        let (tx, rx) = answer();
+        let msg = EngineMessage::SetHashSize(tx, size_mib);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
+
+    /// Refused while searching, the search is still using the table.
+    pub async fn clear_hash(&self) -> Result<(), EngineError> {
+        let (tx, rx) = answer();
+        let msg = EngineMessage::ClearHash(tx);
+
+        let _ = self.sender.send(msg);
+        rx.await.expect("Actor task was killed")
+    }
 }
 
 struct CurrentCalculation {
-    join_handle: tokio::task::JoinHandle<()>,
+    /// Completed just before the best move is sent
+    done: AckRx,
     stop: oneshot::Sender<()>,
+    pondering: watch::Sender<bool>,
 }
 
 struct EngineActor {
@@ -232,35 +353,51 @@ impl EngineActor {
 
     async fn handle_event(&mut self, message: EngineMessage) {
         match message {
-            EngineMessage::SetInitialValues(ack, position, move_strings) => {
-                self.initial_position = position.clone();
-                self.hash_history = PositionHashHistory::new(position.hash());
-                self.current_position = position;
-                self.set_from_strings(&move_strings);
-                let _ = ack.send(());
+            EngineMessage::SetInitialValues(answer, position, move_strings) => {
+                let result = play_moves(&position, &move_strings).map(|(current, history)| {
+                    self.initial_position = position;
+                    self.current_position = current;
+                    self.hash_history = history;
+                });
+                let _ = answer.send(result);
             }
-            EngineMessage::SetMoves(ack, move_strings) => {
-                self.current_position = self.initial_position.clone();
-                self.set_from_strings(&move_strings);
-                let _ = ack.send(());
+            EngineMessage::SetMoves(answer, move_strings) => {
+                let result =
+                    play_moves(&self.initial_position, &move_strings).map(|(current, history)| {
+                        self.current_position = current;
+                        self.hash_history = history;
+                    });
+                let _ = answer.send(result);
             }
             EngineMessage::CurrentColor(answer) => {
                 let _ = answer.send(self.current_position.active_color());
             }
+            EngineMessage::CurrentPosition(answer) => {
+                let _ = answer.send(self.current_position.clone());
+            }
             EngineMessage::Go(ans, config) => {
                 let result = if !self.check_calculation_running() {
                     let (stop_tx, stop_rx) = oneshot::channel();
+                    let (pondering_tx, pondering_rx) = watch::channel(config.ponder);
                     let (updates_tx, updates_rx) = mpsc::unbounded_channel();
                     let pos = self.current_position.clone();
                     let history = self.hash_history.clone();
-                    let agg = self.aggregator.clone();
-                    let join_handle = tokio::spawn(async move {
-                        agg.start_search(stop_rx, pos, history, config, updates_tx)
-                            .await;
-                    });
+                    let (done_tx, done_rx) = ack();
+                    self.aggregator
+                        .start_search(
+                            done_tx,
+                            stop_rx,
+                            pondering_rx,
+                            pos,
+                            history,
+                            config,
+                            updates_tx,
+                        )
+                        .await;
                     self.current_calculation = Some(CurrentCalculation {
-                        join_handle,
+                        done: done_rx,
                         stop: stop_tx,
+                        pondering: pondering_tx,
                     });
                     Ok(updates_rx)
                 } else {
@@ -269,10 +406,11 @@ impl EngineActor {
                 let _ = ans.send(result);
             }
             EngineMessage::Stop(answer) => {
-                let result = if let Some(current_calculation) = self.current_calculation.take() {
-                    if !current_calculation.join_handle.is_finished() {
+                let result = if let Some(mut current_calculation) = self.current_calculation.take()
+                {
+                    if is_running(&mut current_calculation) {
                         let _ = current_calculation.stop.send(());
-                        let _ = current_calculation.join_handle.await;
+                        let _ = current_calculation.done.await;
                         true
                     } else {
                         false
@@ -282,38 +420,119 @@ impl EngineActor {
                 };
                 let _ = answer.send(result);
             }
+            EngineMessage::PonderHit(answer) => {
+                let result = self
+                    .current_calculation
+                    .as_ref()
+                    .is_some_and(|c| c.pondering.send_replace(false));
+                let _ = answer.send(result);
+            }
+            // The aggregator only reads its next message once the search is done, so waiting on
+            // it from here would keep us from ever seeing the stop
+            EngineMessage::SetHashSize(answer, size_mib) => {
+                let result = if self.check_calculation_running() {
+                    Err(EngineError::CalculationAlreadyInProgress)
+                } else {
+                    self.aggregator.set_hash_size(size_mib).await;
+                    Ok(())
+                };
+                let _ = answer.send(result);
+            }
+            EngineMessage::ClearHash(answer) => {
+                let result = if self.check_calculation_running() {
+                    Err(EngineError::CalculationAlreadyInProgress)
+                } else {
+                    self.aggregator.clear_hash().await;
+                    Ok(())
+                };
+                let _ = answer.send(result);
+            }
         }
     }
 
     fn check_calculation_running(&mut self) -> bool {
-        if let Some(ref current_calculation) = self.current_calculation {
+        if let Some(ref mut current_calculation) = self.current_calculation {
             info!("Found a calculation");
-            if current_calculation.join_handle.is_finished() {
-                self.current_calculation = None;
-                false
-            } else {
+            if is_running(current_calculation) {
                 info!("Calculation not done yet");
                 true
+            } else {
+                self.current_calculation = None;
+                false
             }
         } else {
             false
         }
     }
+}
+
+fn is_running(calculation: &mut CurrentCalculation) -> bool {
+    matches!(
+        calculation.done.try_recv(),
+        Err(oneshot::error::TryRecvError::Empty)
+    )
+}
+
+/// The position after `moves` and the hashes of every position along the way.
+fn play_moves(
+    position: &Position,
+    moves: &[String],
+) -> Result<(Position, PositionHashHistory), EngineError> {
+    let mut position = position.clone();
+    let mut hash_history = PositionHashHistory::new(position.hash());
+    for m in moves {
+        let mut buf = BasicMoveBuffer::new();
+        let _ = SHARED_COMPONENTS
+            .move_generator
+            .generate_legal_moves_for(&position, &mut buf);
+        let found_move = buf
+            .iter()
+            .find(|fm| &fm.as_uci() == m)
+            .ok_or_else(|| EngineError::IllegalMove(m.clone()))?;
+        position.make_move(found_move);
+        hash_history.push(position.hash());
+    }
+    Ok((position, hash_history))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn illegal_move_keeps_the_previous_position() {
+        let (_cancellation_tx, cancellation_rx) = watch::channel(());
+        let engine = EngineHandle::new(cancellation_rx);
+        let start = Position::default();
+        assert!(engine
+            .set_initial_values(start.clone(), vec!["e2e4".to_owned()])
+            .await
+            .is_ok());
+        assert_eq!(engine.current_color().await, Color::Black);
+
+        let result = engine
+            .set_initial_values(start, vec!["e7e5".to_owned(), "e2e4".to_owned()])
+            .await;
+        assert!(matches!(result, Err(EngineError::IllegalMove(m)) if m == "e7e5"));
+        assert_eq!(engine.current_color().await, Color::Black);
+
+        let result = engine
+            .set_moves(vec![
+                "e2e4".to_owned(),
+                "e7e5".to_owned(),
+                "e1e3".to_owned(),
+            ])
+            .await;
+        assert!(matches!(result, Err(EngineError::IllegalMove(m)) if m == "e1e3"));
+        assert_eq!(engine.current_color().await, Color::Black);
+    }
 
-    fn set_from_strings(&mut self, moves: &[String]) {
-        moves.iter().for_each(|m| {
-            let mut buf = BasicMoveBuffer::new();
-            let _ = SHARED_COMPONENTS
-                .move_generator
-                .generate_legal_moves_for(&self.current_position, &mut buf);
-
-            let found_move = buf
-                .iter()
-                .find(|fm| &fm.as_uci() == m)
-                .unwrap_or_else(|| panic!("Got invalid move {m}"));
-
-            self.current_position.make_move(found_move);
-            self.hash_history.push(self.current_position.hash());
-        });
+    #[test]
+    fn played_moves_are_in_the_hash_history() {
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"].repeat(2);
+        let moves = moves.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        let (position, history) = play_moves(&Position::default(), &moves).unwrap();
+        assert_eq!(position.hash(), Position::default().hash());
+        assert!(history.is_threefold_repetition());
     }
 }
@@ -60,6 +60,10 @@ impl PriorityMoveBuffer {
             .is_some()
     }
 
+    pub fn retain(&mut self, mut f: impl FnMut(&Move) -> bool) {
+        self.inner.retain(|pm| f(&pm.m))
+    }
+
     pub fn pop(&mut self) -> Option<Move> {
         self.find_highest();
         self.inner.pop().map(|pm| pm.m)
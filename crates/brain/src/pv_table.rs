@@ -0,0 +1,90 @@
+use guts::Move;
+
+/// The deepest ply the search can track a principal variation for.
+pub const MAX_PLY: usize = 128;
+
+/// Triangular principal variation table, each ply holds the best line found from that ply on.
+#[derive(Debug)]
+pub struct PvTable {
+    lines: Vec<Vec<Move>>,
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        Self {
+            lines: (0..MAX_PLY)
+                .map(|ply| Vec::with_capacity(MAX_PLY - ply))
+                .collect(),
+        }
+    }
+
+    /// Should be called when entering a node, so stale lines from sibling subtrees don't leak in.
+    pub fn clear(&mut self, ply: usize) {
+        self.lines[ply].clear();
+    }
+
+    /// `m` is the new best move at `ply`, so its line is `m` followed by the line of the child.
+    pub fn update(&mut self, ply: usize, m: &Move) {
+        let (current, rest) = self.lines.split_at_mut(ply + 1);
+        let line = &mut current[ply];
+        line.clear();
+        line.push(m.clone());
+        if let Some(child) = rest.first() {
+            line.extend_from_slice(child);
+        }
+    }
+
+    /// Sets the line at `ply` to only `m`, for when the child wasn't searched.
+    pub fn set_single(&mut self, ply: usize, m: &Move) {
+        let line = &mut self.lines[ply];
+        line.clear();
+        line.push(m.clone());
+    }
+
+    pub fn line(&self, ply: usize) -> &[Move] {
+        &self.lines[ply]
+    }
+}
+
+impl Default for PvTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::{MoveType, Piece, Square};
+
+    fn mv(from: u8, to: u8) -> Move {
+        Move::new(
+            Square::from_index(from),
+            Square::from_index(to),
+            Piece::King,
+            MoveType::PUSH,
+            None,
+        )
+    }
+
+    #[test]
+    fn update_prepends_to_child_line() {
+        let mut table = PvTable::new();
+        table.clear(2);
+        table.set_single(2, &mv(2, 3));
+        table.clear(1);
+        table.update(1, &mv(1, 2));
+        table.update(0, &mv(0, 1));
+        assert_eq!(table.line(0), &[mv(0, 1), mv(1, 2), mv(2, 3)]);
+        assert_eq!(table.line(1), &[mv(1, 2), mv(2, 3)]);
+    }
+
+    #[test]
+    fn cleared_child_ends_the_line() {
+        let mut table = PvTable::new();
+        table.set_single(1, &mv(1, 2));
+        table.clear(1);
+        table.update(0, &mv(0, 1));
+        assert_eq!(table.line(0), &[mv(0, 1)]);
+    }
+}
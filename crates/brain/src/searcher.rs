@@ -1,28 +1,98 @@
 use crate::evaluator::{Evaluator, MainEvaluator, ScoreBound};
 use crate::position_hash_history::PositionHashHistory;
 use crate::priority_buffer::PriorityMoveBuffer;
+use crate::pv_table::{PvTable, MAX_PLY};
+use crate::see::{see, see_value};
 use crate::statistics::StatisticsHolder;
 use crate::transposition_table::{TTEntry, TranspositionTable};
 use crate::{CentipawnScore, MoveResult, SHARED_COMPONENTS};
-use guts::{Move, MoveType, Position};
-use log::{debug, info};
+use guts::{BasicMoveBuffer, Color, Move, MoveType, Piece, Position, Rank};
+use log::{debug, info, trace};
 use thiserror::Error;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 
+/// Margin on top of the captured piece before a capture in quiescence is considered hopeless
+const DELTA_MARGIN: i32 = 200;
+/// Minimum remaining depth before a TT move is tested for being singular
+const SINGULAR_MIN_DEPTH: u16 = 6;
+/// How much worse than the TT score every other move has to be, per ply of depth
+const SINGULAR_MARGIN_PER_DEPTH: i32 = 2;
+
+/// Which moves get searched one ply deeper than usual.
+#[derive(Debug, Copy, Clone)]
+pub struct Extensions {
+    /// Moves that give check
+    pub check: bool,
+    /// TT moves that are much better than all alternatives
+    pub singular: bool,
+    /// Captures on the square the previous move captured on
+    pub recapture: bool,
+    /// Pawns moving to the rank before promotion
+    pub pawn_push: bool,
+    /// Maximum plies of extensions along a single path from the root
+    pub max_per_path: u16,
+}
+
+impl Extensions {
+    pub const NONE: Self = Self {
+        check: false,
+        singular: false,
+        recapture: false,
+        pawn_push: false,
+        max_per_path: 0,
+    };
+}
+
+impl Default for Extensions {
+    /// Recaptures are already resolved by quiescence at the horizon, so extending them as well
+    /// costs more nodes than it finds.
+    fn default() -> Self {
+        Self {
+            check: true,
+            singular: true,
+            recapture: false,
+            pawn_push: true,
+            max_per_path: 2,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SearcherConfig {
     pub depth: Option<u16>,
+    /// Stop once this many nodes have been searched, as long as there is a result to show for it.
+    pub nodes: Option<u64>,
+    /// Stop after finding a mate in this many moves, or after searching deep enough to find one.
+    pub mate: Option<u16>,
+    /// Restricts the root moves, empty means all legal moves.
+    pub search_moves: Vec<Move>,
+    /// How many of the best root moves to find each iteration.
+    pub multi_pv: usize,
+    pub extensions: Extensions,
+    /// Also search quiet moves that give check at the first ply of quiescence.
+    pub quiescence_checks: bool,
 }
 
 #[derive(Debug)]
 struct SearchResult {
-    move_result: MoveResult,
+    score: CentipawnScore,
+    best_move: Option<Move>,
 }
 
 impl SearchResult {
-    pub fn new(move_result: MoveResult) -> Self {
-        Self { move_result }
+    pub fn new(score: CentipawnScore) -> Self {
+        Self {
+            score,
+            best_move: None,
+        }
+    }
+
+    pub fn with_move(score: CentipawnScore, m: Move) -> Self {
+        Self {
+            score,
+            best_move: Some(m),
+        }
     }
 }
 
@@ -34,6 +104,15 @@ pub struct Searcher<'a, E: Evaluator> {
     config: SearcherConfig,
     statistics: &'a StatisticsHolder,
     transposition_table: &'a mut TranspositionTable,
+    has_result: bool,
+    /// Root moves already reported as a better line in the current iteration.
+    excluded_root_moves: Vec<Move>,
+    seldepth: u16,
+    pv_table: PvTable,
+    /// The move played at each ply of the current path
+    path: Vec<Option<Move>>,
+    /// Moves skipped at a ply while verifying whether they are singular
+    singular_exclusions: Vec<Option<Move>>,
 }
 
 impl<'a> Searcher<'a, MainEvaluator<'static>> {
@@ -75,36 +154,83 @@ impl<'a, E: Evaluator> Searcher<'a, E> {
             config,
             statistics,
             transposition_table,
+            has_result: false,
+            excluded_root_moves: Vec::new(),
+            seldepth: 0,
+            pv_table: PvTable::new(),
+            path: vec![None; MAX_PLY],
+            singular_exclusions: vec![None; MAX_PLY],
         }
     }
 
-    pub fn search(&mut self, output: mpsc::UnboundedSender<MoveResult>) {
+    /// Sends the best lines, best first, after each iteration.
+    pub fn search(&mut self, output: mpsc::UnboundedSender<Vec<MoveResult>>) {
         match self.do_search(output) {
             Ok(_) => info!("Search completed"),
             Err(e) => info!("Search error: {e}"),
         }
     }
 
-    fn do_search(&mut self, output: mpsc::UnboundedSender<MoveResult>) -> Result<(), SearchError> {
+    fn do_search(
+        &mut self,
+        output: mpsc::UnboundedSender<Vec<MoveResult>>,
+    ) -> Result<(), SearchError> {
         #[cfg(debug_assertions)]
         let original_pos = self.current_position.clone();
+        self.evaluator.set_position(&self.current_position);
 
         let mut buf = PriorityMoveBuffer::new();
-        let max_depth = if let Some(depth) = self.config.depth {
-            depth
-        } else {
-            u16::MAX
-        };
+        // Leave room for quiescence beyond the deepest iteration
+        let mut max_depth = self
+            .config
+            .depth
+            .unwrap_or(u16::MAX)
+            .min(MAX_PLY as u16 / 2);
+        if let Some(mate) = self.config.mate {
+            max_depth = max_depth.min((2 * mate).saturating_sub(1).max(1));
+        }
         info!("Setting max depth: {max_depth}");
+        let line_count = self.config.multi_pv.clamp(1, self.root_move_count().max(1));
         for depth in 1..=max_depth {
             self.statistics.depth_changed(depth as u64);
-            let best: Option<SearchResult> =
-                Some(self.recurse(CentipawnScore::MIN, CentipawnScore::MAX, depth, &mut buf)?);
+            self.excluded_root_moves.clear();
+            self.seldepth = 0;
+            let mut lines = Vec::with_capacity(line_count);
+            for _ in 0..line_count {
+                let result = self.recurse(
+                    CentipawnScore::MIN,
+                    CentipawnScore::MAX,
+                    depth,
+                    0,
+                    0,
+                    &mut buf,
+                )?;
+                let mut pv = self.pv_table.line(0).to_vec();
+                self.extend_pv_from_tt(&mut pv);
+                let mut best = MoveResult::with_pv(result.score, pv);
+                debug!("Best move: {best:?}");
+                if let Some(m) = best.first_move() {
+                    self.excluded_root_moves.push(m.clone());
+                }
+                best.set_depth(depth, self.seldepth.max(depth));
+                lines.push(best);
+            }
 
-            debug!("Best move: {best:?}");
+            self.statistics
+                .hashfull_changed(self.transposition_table.hashfull() as u64);
 
-            if let Some(b) = best {
-                output.send(b.move_result).unwrap();
+            // A mate in N moves takes 2N - 1 plies
+            let found_mate = self.config.mate.is_some_and(|mate| {
+                lines[0]
+                    .score
+                    .mate_plies()
+                    .is_some_and(|plies| plies > 0 && plies < 2 * mate as i32)
+            });
+            output.send(lines).unwrap();
+            self.has_result = true;
+            if found_mate {
+                info!("Found the requested mate at depth {depth}");
+                break;
             }
 
             #[cfg(debug_assertions)]
@@ -119,111 +245,162 @@ impl<'a, E: Evaluator> Searcher<'a, E> {
         mut alpha: CentipawnScore,
         mut beta: CentipawnScore,
         depth: u16,
+        ply: u16,
+        extended: u16,
         buf: &mut PriorityMoveBuffer,
     ) -> Result<SearchResult, SearchError> {
         self.stop()?;
-        let mut maybe_previously_best_move: Option<&Move> = None;
-        if let Some(cached) = self.transposition_table.get(self.current_position.hash()) {
-            if cached.hash == self.current_position.hash() {
-                self.statistics.tt_hit();
-                if cached.depth >= depth {
-                    match cached.bound {
-                        ScoreBound::Exact => {
-                            let mut mr = MoveResult::new(cached.score);
-                            mr.push(cached.m.clone().unwrap());
-                            return Ok(SearchResult::new(mr));
-                        }
-                        ScoreBound::Upper => {
-                            beta = cached.score;
-                        }
-                        ScoreBound::Lower => {
-                            alpha = cached.score;
-                        }
+        self.pv_table.clear(ply as usize);
+        // The root always gets at least one ply, so its restrictions never reach quiescence
+        if depth == 0 || ply as usize >= MAX_PLY - 1 {
+            return self.quiescence(alpha, beta, ply, 0, &mut PriorityMoveBuffer::new());
+        }
+        let is_restricted_root = ply == 0
+            && !(self.config.search_moves.is_empty() && self.excluded_root_moves.is_empty());
+        let singular_exclusion = self.singular_exclusions[ply as usize].clone();
+        let is_restricted = is_restricted_root || singular_exclusion.is_some();
+        let mut maybe_previously_best_move: Option<Move> = None;
+        let mut singular_candidate = None;
+        if let Some(mut cached) = self.transposition_table.get(self.current_position.hash()) {
+            self.statistics.tt_hit();
+            cached.score = cached.score.from_tt(ply);
+            if self.is_singular_candidate(&cached, depth, ply, extended, is_restricted) {
+                singular_candidate = cached.m.clone().map(|m| (m, cached.score));
+            }
+            // The cached move might not be one we're allowed to play
+            if cached.depth >= depth && !is_restricted {
+                match cached.bound {
+                    ScoreBound::Exact => {
+                        return Ok(match cached.m {
+                            Some(m) => {
+                                self.pv_table.set_single(ply as usize, &m);
+                                SearchResult::with_move(cached.score, m)
+                            }
+                            None => SearchResult::new(cached.score),
+                        });
+                    }
+                    ScoreBound::Upper => {
+                        beta = cached.score;
+                    }
+                    ScoreBound::Lower => {
+                        alpha = cached.score;
                     }
                 }
             }
-                maybe_previously_best_move = cached.m.as_ref();
+            maybe_previously_best_move = cached.m;
         }
 
         self.statistics.node_searched();
+        self.seldepth = self.seldepth.max(ply);
 
         if self.position_hash_history.is_threefold_repetition() {
-            return Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)));
+            return Ok(SearchResult::new(CentipawnScore::ZERO));
         }
         if self.current_position.halfmove_clock() >= 50 {
-            return Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)));
+            return Ok(SearchResult::new(CentipawnScore::ZERO));
         }
 
         let mut new_buf = PriorityMoveBuffer::new();
-        if depth == 0 {
-            return self.quiescence(-beta, -alpha, &mut new_buf);
-        }
+
+        let singular_move = match singular_candidate {
+            Some((m, tt_score)) => self.verify_singular(m, tt_score, depth, ply, extended, buf)?,
+            None => None,
+        };
 
         buf.clear();
         let in_check = SHARED_COMPONENTS
             .move_generator
             .generate_legal_moves_for(&self.current_position, buf);
+        if let Some(ref excluded) = singular_exclusion {
+            buf.retain(|m| m != excluded);
+            // Without the excluded move there might be nothing left, but that's no mate
+            if buf.is_empty() {
+                return Ok(SearchResult::new(alpha));
+            }
+        }
+        if is_restricted_root {
+            let search_moves = &self.config.search_moves;
+            let excluded = &self.excluded_root_moves;
+            buf.retain(|m| {
+                (search_moves.is_empty() || search_moves.contains(m)) && !excluded.contains(m)
+            });
+        }
 
         if buf.is_empty() {
             return if in_check {
-                debug!("Returning mate");
-                Ok(SearchResult::new(MoveResult::new(
-                    CentipawnScore::CHECKMATED,
-                )))
+                trace!("Returning mate");
+                Ok(SearchResult::new(CentipawnScore::mated_in(ply)))
             } else {
-                debug!("Returning draw");
-                Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)))
+                trace!("Returning draw");
+                Ok(SearchResult::new(CentipawnScore::ZERO))
             };
         }
 
-        let mut best_result: SearchResult = SearchResult::new(MoveResult::new(alpha));
+        let mut best_result = SearchResult::new(alpha);
         let mut was_alpha_increased = false;
         if let Some(m) = maybe_previously_best_move {
-            buf.set_priority(m, u8::MAX);
+            buf.set_priority(&m, u8::MAX);
         }
+        let mut move_number = 0;
         while let Some(m) = buf.pop() {
+            move_number += 1;
+            if ply == 0 {
+                self.statistics.root_move_changed(&m, move_number);
+            }
             #[cfg(debug_assertions)]
             let orig_pos = self.current_position.clone();
             #[cfg(debug_assertions)]
             let orig_history = self.position_hash_history.clone();
 
-            self.current_position.make_move(&m);
-            self.position_hash_history
-                .push(self.current_position.hash());
+            self.make_move(&m);
 
-            let mut new_result = self.recurse(-beta, -alpha, depth - 1, &mut new_buf)?;
-            new_result.move_result.invert_score();
+            let extension = if extended < self.config.extensions.max_per_path
+                && self.should_extend(&m, ply, singular_move.as_ref())
+            {
+                1
+            } else {
+                0
+            };
+            self.path[ply as usize] = Some(m.clone());
+            let score = -self
+                .recurse(
+                    -beta,
+                    -alpha,
+                    depth - 1 + extension,
+                    ply + 1,
+                    extended + extension,
+                    &mut new_buf,
+                )?
+                .score;
 
-            if new_result.move_result.score >= beta {
-                debug!(
+            if score >= beta {
+                trace!(
                     "Got a beta cutoff with beta {beta:?} on move {m}",
                     m = m.as_uci()
                 );
+                self.unmake_move(&m);
                 self.transposition_table.set(TTEntry {
                     hash: self.current_position.hash(),
                     depth,
-                    score: new_result.move_result.score,
+                    score: score.to_tt(ply),
                     bound: ScoreBound::Lower,
                     m: Some(m.clone()),
                 });
-                self.position_hash_history.pop();
-                self.current_position.unmake_move(&m);
-                new_result.move_result.push(m.clone());
-                return Ok(new_result);
+                return Ok(SearchResult::with_move(score, m));
             }
 
-            if new_result.move_result.score > alpha {
+            if score > alpha {
                 was_alpha_increased = true;
-                new_result.move_result.push(m.clone());
-                debug!(
-                    "Got an alpha update with alpha {alpha:?} with new best move {new_result:?}"
+                trace!(
+                    "Got an alpha update with alpha {alpha:?} with new best move {m}",
+                    m = m.as_uci()
                 );
-                alpha = new_result.move_result.score;
-                best_result = new_result;
+                alpha = score;
+                self.pv_table.update(ply as usize, &m);
+                best_result = SearchResult::with_move(score, m.clone());
             }
 
-            let _ = self.position_hash_history.pop();
-            self.current_position.unmake_move(&m);
+            self.unmake_move(&m);
 
             #[cfg(debug_assertions)]
             debug_assert_eq!(
@@ -239,134 +416,373 @@ impl<'a, E: Evaluator> Searcher<'a, E> {
             )
         }
 
+        // A restricted move list isn't representative of the position
+        if is_restricted {
+            return Ok(best_result);
+        }
         self.transposition_table.set(TTEntry {
             hash: self.current_position.hash(),
             depth,
-            score: best_result.move_result.score,
+            score: best_result.score.to_tt(ply),
             bound: if was_alpha_increased {
                 ScoreBound::Exact
             } else {
                 ScoreBound::Upper
             },
-            m: best_result.move_result.first_move().cloned(),
+            m: best_result.best_move.clone(),
         });
 
         Ok(best_result)
     }
 
-    // TODO figure out a way to merge this with `recurse`, might not be possible
+    /// Resolves captures until the position is quiet, so the evaluation isn't taken in the middle
+    /// of an exchange. In check standing pat isn't an option, so every evasion is searched.
     fn quiescence(
         &mut self,
         mut alpha: CentipawnScore,
         beta: CentipawnScore,
+        ply: u16,
+        qply: u16,
         buf: &mut PriorityMoveBuffer,
     ) -> Result<SearchResult, SearchError> {
-        self.statistics.node_searched();
-        // Assume we can do better than the current evaluation
-        let stand_pat = self.evaluator.evaluate(&self.current_position);
-        if stand_pat >= beta {
-            return Ok(SearchResult::new(MoveResult::new(beta)));
-        }
-        if alpha < stand_pat {
-            alpha = stand_pat
-        }
+        self.statistics.qnode_searched();
+        self.seldepth = self.seldepth.max(ply);
         if self.position_hash_history.is_threefold_repetition() {
-            return Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)));
+            return Ok(SearchResult::new(CentipawnScore::ZERO));
         }
         if self.current_position.halfmove_clock() >= 50 {
-            return Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)));
+            return Ok(SearchResult::new(CentipawnScore::ZERO));
         }
-        buf.clear();
+        if ply as usize >= MAX_PLY - 1 {
+            return Ok(SearchResult::new(
+                self.evaluator.evaluate(&self.current_position),
+            ));
+        }
+
+        let mut tt_move = None;
+        if let Some(mut cached) = self.transposition_table.get(self.current_position.hash()) {
+            self.statistics.tt_hit();
+            cached.score = cached.score.from_tt(ply);
+            let is_usable = match cached.bound {
+                ScoreBound::Exact => true,
+                ScoreBound::Lower => cached.score >= beta,
+                ScoreBound::Upper => cached.score <= alpha,
+            };
+            if is_usable {
+                return Ok(SearchResult {
+                    score: cached.score,
+                    best_move: cached.m,
+                });
+            }
+            tt_move = cached.m;
+        }
+
         let in_check = SHARED_COMPONENTS
+            .move_generator
+            .is_in_check(&self.current_position);
+        let original_alpha = alpha;
+        // Assume we can do better than the current evaluation
+        let stand_pat = if in_check {
+            None
+        } else {
+            let stand_pat = self.evaluator.evaluate(&self.current_position);
+            if stand_pat >= beta {
+                return Ok(SearchResult::new(beta));
+            }
+            alpha = alpha.max(stand_pat);
+            Some(stand_pat)
+        };
+
+        buf.clear();
+        let _ = SHARED_COMPONENTS
             .move_generator
             .generate_legal_moves_for(&self.current_position, buf);
         if buf.is_empty() {
             return if in_check {
-                debug!("Returning mate");
-                Ok(SearchResult::new(MoveResult::new(
-                    CentipawnScore::CHECKMATED,
-                )))
+                trace!("Returning mate");
+                Ok(SearchResult::new(CentipawnScore::mated_in(ply)))
             } else {
-                debug!("Returning draw");
-                Ok(SearchResult::new(MoveResult::new(CentipawnScore::ZERO)))
+                trace!("Returning draw");
+                Ok(SearchResult::new(CentipawnScore::ZERO))
             };
         }
-        let mut best_result: SearchResult = SearchResult::new(MoveResult::new(alpha));
+        let quiet_checks = self.config.quiescence_checks && qply == 0;
+        if !in_check && !quiet_checks {
+            buf.retain(is_noisy);
+        }
+        if let Some(m) = &tt_move {
+            buf.set_priority(m, u8::MAX);
+        }
+
+        let mut best_result = SearchResult::new(alpha);
         let mut new_buf = PriorityMoveBuffer::new();
-        for m in buf.unordered_iter() {
-            if m.move_type().contains(MoveType::CAPTURE) {
-                #[cfg(debug_assertions)]
-                let orig_pos = self.current_position.clone();
-                #[cfg(debug_assertions)]
-                let orig_history = self.position_hash_history.clone();
-                self.current_position.make_move(m);
-                self.position_hash_history
-                    .push(self.current_position.hash());
-
-                let mut new_result = self.quiescence(-beta, -alpha, &mut new_buf)?;
-                new_result.move_result.invert_score();
-
-                if new_result.move_result.score >= beta {
-                    debug!(
-                        "Got a beta cutoff with beta {beta:?} on move {m}",
-                        m = m.as_uci()
-                    );
-                    self.position_hash_history.pop();
-                    self.current_position.unmake_move(m);
-                    new_result.move_result.push(m.clone());
-                    return Ok(new_result);
+        while let Some(m) = buf.pop() {
+            if let Some(stand_pat) = stand_pat {
+                if m.move_type().contains(MoveType::CAPTURE) && self.is_futile(&m, stand_pat, alpha)
+                {
+                    continue;
                 }
+            }
+            #[cfg(debug_assertions)]
+            let orig_pos = self.current_position.clone();
+            #[cfg(debug_assertions)]
+            let orig_history = self.position_hash_history.clone();
+            self.make_move(&m);
+            // Outside of check only captures, promotions and, if enabled, checks are searched
+            if stand_pat.is_some()
+                && !is_noisy(&m)
+                && !SHARED_COMPONENTS
+                    .move_generator
+                    .is_in_check(&self.current_position)
+            {
+                self.unmake_move(&m);
+                continue;
+            }
 
-                if new_result.move_result.score > alpha {
-                    new_result.move_result.push(m.clone());
-                    debug!(
-                        "Got an alpha update with alpha {alpha:?} with new best move{new_result:?}"
-                    );
-                    alpha = new_result.move_result.score;
-                    best_result = new_result;
-                }
+            let score = -self
+                .quiescence(-beta, -alpha, ply + 1, qply + 1, &mut new_buf)?
+                .score;
+            self.unmake_move(&m);
 
-                let _ = self.position_hash_history.pop();
-                self.current_position.unmake_move(m);
+            #[cfg(debug_assertions)]
+            debug_assert_eq!(
+                self.position_hash_history, orig_history,
+                "Difference during move {m}, original_history: {:?}",
+                orig_history
+            );
+            #[cfg(debug_assertions)]
+            debug_assert_eq!(
+                self.current_position, orig_pos,
+                "Difference during move {m}, original_position: {}",
+                orig_pos
+            );
 
-                #[cfg(debug_assertions)]
-                debug_assert_eq!(
-                    self.position_hash_history, orig_history,
-                    "Difference during move {m}, original_history: {:?}",
-                    orig_history
+            if score >= beta {
+                trace!(
+                    "Got a beta cutoff with beta {beta:?} on move {m}",
+                    m = m.as_uci()
+                );
+                self.transposition_table.set(TTEntry {
+                    hash: self.current_position.hash(),
+                    depth: 0,
+                    score: score.to_tt(ply),
+                    bound: ScoreBound::Lower,
+                    m: Some(m.clone()),
+                });
+                return Ok(SearchResult::with_move(score, m));
+            }
+
+            if score > alpha {
+                trace!(
+                    "Got an alpha update with alpha {alpha:?} with new best move {m}",
+                    m = m.as_uci()
                 );
-                #[cfg(debug_assertions)]
-                debug_assert_eq!(
-                    self.current_position, orig_pos,
-                    "Difference during move {m}, original_position: {}",
-                    orig_pos
-                )
+                alpha = score;
+                best_result = SearchResult::with_move(score, m);
             }
         }
+
+        self.transposition_table.set(TTEntry {
+            hash: self.current_position.hash(),
+            depth: 0,
+            score: best_result.score.to_tt(ply),
+            bound: if alpha > original_alpha {
+                ScoreBound::Exact
+            } else {
+                ScoreBound::Upper
+            },
+            m: best_result.best_move.clone(),
+        });
         Ok(best_result)
     }
 
+    /// Captures that can't raise alpha even when winning the captured piece for free, or that lose
+    /// material in the exchange.
+    fn is_futile(&self, m: &Move, stand_pat: CentipawnScore, alpha: CentipawnScore) -> bool {
+        if m.promotion().is_none() {
+            let captured = if m.move_type().contains(MoveType::EN_PASSANT) {
+                Some(Piece::Pawn)
+            } else {
+                self.current_position.board().piece_at(m.to())
+            };
+            let best_case = stand_pat.0 + captured.map_or(0, see_value) + DELTA_MARGIN;
+            if best_case < alpha.0 {
+                return true;
+            }
+        }
+        see(&self.current_position, m) < 0
+    }
+
+    fn is_singular_candidate(
+        &self,
+        cached: &TTEntry,
+        depth: u16,
+        ply: u16,
+        extended: u16,
+        is_restricted: bool,
+    ) -> bool {
+        let extensions = &self.config.extensions;
+        extensions.singular
+            && extended < extensions.max_per_path
+            && ply > 0
+            && !is_restricted
+            && depth >= SINGULAR_MIN_DEPTH
+            && cached.m.is_some()
+            && cached.depth + 3 >= depth
+            && matches!(cached.bound, ScoreBound::Exact | ScoreBound::Lower)
+            // Margins don't mean much next to mate scores
+            && !cached.score.is_mate()
+    }
+
+    /// Searches everything but the TT move with a reduced depth and a window just below the TT
+    /// score. If nothing gets close, the TT move is singular and worth extending.
+    fn verify_singular(
+        &mut self,
+        tt_move: Move,
+        tt_score: CentipawnScore,
+        depth: u16,
+        ply: u16,
+        extended: u16,
+        buf: &mut PriorityMoveBuffer,
+    ) -> Result<Option<Move>, SearchError> {
+        let singular_beta = CentipawnScore(tt_score.0 - SINGULAR_MARGIN_PER_DEPTH * depth as i32);
+        self.singular_exclusions[ply as usize] = Some(tt_move.clone());
+        let result = self.recurse(
+            CentipawnScore(singular_beta.0 - 1),
+            singular_beta,
+            (depth - 1) / 2,
+            ply,
+            extended,
+            buf,
+        );
+        self.singular_exclusions[ply as usize] = None;
+        self.pv_table.clear(ply as usize);
+        Ok((result?.score < singular_beta).then_some(tt_move))
+    }
+
+    /// Called after `m` has been made at `ply`.
+    fn should_extend(&self, m: &Move, ply: u16, singular_move: Option<&Move>) -> bool {
+        let extensions = &self.config.extensions;
+        if extensions.singular && singular_move == Some(m) {
+            return true;
+        }
+        if extensions.check
+            && SHARED_COMPONENTS
+                .move_generator
+                .is_in_check(&self.current_position)
+        {
+            return true;
+        }
+        if extensions.recapture && m.move_type().contains(MoveType::CAPTURE) && ply > 0 {
+            if let Some(previous) = &self.path[ply as usize - 1] {
+                if previous.move_type().contains(MoveType::CAPTURE) && previous.to() == m.to() {
+                    return true;
+                }
+            }
+        }
+        if extensions.pawn_push && m.piece() == Piece::Pawn {
+            let seventh = match self.current_position.active_color() {
+                // The opponent of the mover is to move now
+                Color::Black => Rank::R7,
+                Color::White => Rank::R2,
+            };
+            if m.to().rank() == seventh {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn root_move_count(&self) -> usize {
+        let mut buf = BasicMoveBuffer::new();
+        let _ = SHARED_COMPONENTS
+            .move_generator
+            .generate_legal_moves_for(&self.current_position, &mut buf);
+        let search_moves = &self.config.search_moves;
+        buf.iter()
+            .filter(|m| search_moves.is_empty() || search_moves.contains(m))
+            .count()
+    }
+
+    fn make_move(&mut self, m: &Move) {
+        self.evaluator.make_move(&self.current_position, m);
+        self.current_position.make_move(m);
+        self.position_hash_history
+            .push(self.current_position.hash());
+    }
+
+    fn unmake_move(&mut self, m: &Move) {
+        let _ = self.position_hash_history.pop();
+        self.current_position.unmake_move(m);
+        self.evaluator.unmake_move();
+    }
+
+    /// Lines cut short by TT hits are continued with the TT moves, as long as they are legal
+    /// and don't run into a position we've already seen on the line.
+    fn extend_pv_from_tt(&mut self, pv: &mut Vec<Move>) {
+        let mut seen = Vec::with_capacity(MAX_PLY);
+        for m in pv.iter() {
+            seen.push(self.current_position.hash());
+            self.current_position.make_move(m);
+        }
+        while pv.len() < MAX_PLY && !seen.contains(&self.current_position.hash()) {
+            let mut buf = BasicMoveBuffer::new();
+            let _ = SHARED_COMPONENTS
+                .move_generator
+                .generate_legal_moves_for(&self.current_position, &mut buf);
+            let Some(m) = self
+                .transposition_table
+                .get(self.current_position.hash())
+                .and_then(|e| e.m)
+                .filter(|m| buf.iter().any(|l| l == m))
+            else {
+                break;
+            };
+            seen.push(self.current_position.hash());
+            self.current_position.make_move(&m);
+            pv.push(m);
+        }
+        for m in pv.iter().rev() {
+            self.current_position.unmake_move(m);
+        }
+    }
+
     fn stop(&mut self) -> Result<(), SearchError> {
         match self.stop_rx.has_changed() {
-            Ok(false) => Ok(()),
+            Ok(false) => {}
             _ => {
                 info!("Searcher received stop");
-                Err(SearchError::Stopped)
+                return Err(SearchError::Stopped);
+            }
+        }
+        match self.config.nodes {
+            Some(nodes) if self.has_result && self.statistics.nodes_searched() >= nodes => {
+                info!("Searcher reached the node limit");
+                Err(SearchError::NodeLimitReached)
             }
+            _ => Ok(()),
         }
     }
 }
 
+/// Moves that change the material balance.
+fn is_noisy(m: &Move) -> bool {
+    m.move_type().contains(MoveType::CAPTURE) || m.promotion().is_some()
+}
+
 #[derive(Debug, Error)]
 enum SearchError {
     #[error("search was stopped")]
     Stopped,
+    #[error("node limit reached")]
+    NodeLimitReached,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::evaluator::PieceCountEvaluator;
+    use crate::statistics::Statistics;
     use guts::{BasicMoveBuffer, MoveGenerator, Position};
     use std::str::FromStr;
 
@@ -402,7 +818,10 @@ mod tests {
                 history,
                 pos,
                 stop_rx,
-                SearcherConfig { depth: Some(depth) },
+                SearcherConfig {
+                    depth: Some(depth),
+                    ..SearcherConfig::default()
+                },
                 &stats,
                 &mut tt,
             );
@@ -412,7 +831,7 @@ mod tests {
                 let mut tmp = None;
                 let mut ctr = 0;
                 while let Some(r) = rx.recv().await {
-                    tmp = Some(r);
+                    tmp = r.into_iter().next();
                     ctr += 1;
                 }
                 assert!(ctr > 0, "{ctr}");
@@ -477,7 +896,10 @@ mod tests {
             history,
             pos,
             stop_rx,
-            SearcherConfig { depth: Some(depth) },
+            SearcherConfig {
+                depth: Some(depth),
+                ..SearcherConfig::default()
+            },
             &stats,
             &mut tt,
         );
@@ -487,7 +909,7 @@ mod tests {
             let mut tmp = None;
             let mut ctr = 0;
             while let Some(r) = rx.recv().await {
-                tmp = Some(r);
+                tmp = r.into_iter().next();
                 ctr += 1;
             }
             assert!(ctr > 0);
@@ -511,7 +933,10 @@ mod tests {
             history,
             pos.clone(),
             stop_rx,
-            SearcherConfig { depth: Some(4) },
+            SearcherConfig {
+                depth: Some(4),
+                ..SearcherConfig::default()
+            },
             &stats,
             &mut tt,
         );
@@ -521,7 +946,7 @@ mod tests {
             let mut tmp = None;
             let mut ctr = 0;
             while let Some(r) = rx.recv().await {
-                tmp = Some(r);
+                tmp = r.into_iter().next();
                 ctr += 1;
             }
             assert!(ctr > 0);
@@ -559,7 +984,10 @@ mod tests {
             history,
             pos,
             stop_rx,
-            SearcherConfig { depth: Some(4) },
+            SearcherConfig {
+                depth: Some(4),
+                ..SearcherConfig::default()
+            },
             &stats,
             &mut tt,
         );
@@ -569,7 +997,7 @@ mod tests {
             let mut tmp = None;
             let mut ctr = 0;
             while let Some(r) = rx.recv().await {
-                tmp = Some(r);
+                tmp = r.into_iter().next();
                 ctr += 1;
             }
             assert!(ctr > 0);
@@ -593,7 +1021,10 @@ mod tests {
             history,
             pos,
             stop_rx,
-            SearcherConfig { depth: Some(depth) },
+            SearcherConfig {
+                depth: Some(depth),
+                ..SearcherConfig::default()
+            },
             &stats,
             &mut tt,
         );
@@ -603,7 +1034,7 @@ mod tests {
             let mut tmp = None;
             let mut ctr = 0;
             while let Some(r) = rx.recv().await {
-                tmp = Some(r);
+                tmp = r.into_iter().next();
                 ctr += 1;
             }
             assert!(ctr > 0);
@@ -611,6 +1042,483 @@ mod tests {
         };
 
         assert_eq!(mr.first_move().unwrap().as_uci(), "b1a1");
-        assert_eq!(mr.score, -CentipawnScore::CHECKMATED);
+        assert_eq!(mr.score, -CentipawnScore::mated_in(1));
+        assert_eq!(mr.score.mate_moves(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn only_searches_given_root_moves() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        // Taking the pawn is clearly best, but we're only allowed to move the king up
+        let pos = Position::from_str("k7/8/8/8/8/8/2p5/K7 w - - 0 1").unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let restricted = {
+            let mut buf = BasicMoveBuffer::new();
+            let _ = MoveGenerator::new().generate_legal_moves_for(&pos, &mut buf);
+            let m = buf.iter().find(|m| m.as_uci() == "a1a2").unwrap().clone();
+            m
+        };
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                depth: Some(3),
+                search_moves: vec![restricted],
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+
+        while let Some(lines) = rx.recv().await {
+            assert_eq!(lines[0].first_move().unwrap().as_uci(), "a1a2");
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_at_node_limit() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::default();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                nodes: Some(1),
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+
+        let mut ctr = 0;
+        while rx.recv().await.is_some() {
+            ctr += 1;
+        }
+        // The first iteration always finishes, so there's a move to play
+        assert_eq!(ctr, 1);
+        assert_eq!(stats.get_statistics().current_depth, 2);
+    }
+
+    #[tokio::test]
+    async fn stops_after_finding_mate() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::from_str("8/8/k1K5/8/8/8/8/1R6 w - - 0 1").unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                mate: Some(3),
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+
+        let mut last = None;
+        while let Some(lines) = rx.recv().await {
+            last = lines.into_iter().next();
+        }
+        let last = last.unwrap();
+        assert_eq!(last.first_move().unwrap().as_uci(), "b1a1");
+        assert_eq!(last.score(), -CentipawnScore::mated_in(1));
+        // The mate in one is short enough as soon as it is found
+        assert_eq!(stats.get_statistics().current_depth, 1);
+    }
+
+    #[tokio::test]
+    async fn multi_pv_finds_distinct_lines() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        // White only has two legal moves, so asking for more lines can't give more
+        let pos = Position::from_str("k7/8/8/8/8/8/2p5/K7 w - - 0 1").unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                depth: Some(3),
+                multi_pv: 5,
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+
+        let mut last = None;
+        while let Some(lines) = rx.recv().await {
+            last = Some(lines);
+        }
+        let lines = last.unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].first_move().unwrap().as_uci(), "a1b2");
+        assert!(lines.windows(2).all(|w| w[0].score() >= w[1].score()));
+        let mut first_moves = lines
+            .iter()
+            .map(|l| l.first_move().unwrap().as_uci())
+            .collect::<Vec<_>>();
+        first_moves.sort();
+        first_moves.dedup();
+        assert_eq!(first_moves.len(), 2);
+        assert!(lines.iter().all(|l| l.depth() == 3));
+    }
+
+    fn assert_legal_line(position: &Position, pv: &[Move]) {
+        let move_generator = MoveGenerator::new();
+        let mut position = position.clone();
+        for m in pv {
+            let mut buf = BasicMoveBuffer::new();
+            let _ = move_generator.generate_legal_moves_for(&position, &mut buf);
+            assert!(
+                buf.iter().any(|l| l == m),
+                "{m} is not legal in {position}, line: {}",
+                pv.iter().map(|m| m.as_uci()).collect::<Vec<_>>().join(" ")
+            );
+            position.make_move(m);
+        }
+    }
+
+    #[tokio::test]
+    async fn pv_is_legal_line_from_root() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "8/8/k1K5/8/8/8/8/1R6 w - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+        ] {
+            let stats = StatisticsHolder::new();
+            let mut tt = TranspositionTable::default();
+            let pos = Position::from_str(fen).unwrap();
+            let history = PositionHashHistory::new(pos.hash());
+            let (_stop_tx, stop_rx) = watch::channel(());
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let mut searcher = get_pc_searcher(
+                history,
+                pos.clone(),
+                stop_rx,
+                SearcherConfig {
+                    depth: Some(5),
+                    multi_pv: 2,
+                    ..SearcherConfig::default()
+                },
+                &stats,
+                &mut tt,
+            );
+            searcher.search(tx);
+
+            let mut last = None;
+            while let Some(lines) = rx.recv().await {
+                for line in lines.iter() {
+                    assert_legal_line(&pos, &line.pv().cloned().collect::<Vec<_>>());
+                }
+                last = Some(lines);
+            }
+            let best = &last.unwrap()[0];
+            // Only a mate in one can end the line right away
+            assert!(
+                best.pv().count() >= 2 || best.score().mate_plies() == Some(1),
+                "{fen}: {best:?}"
+            );
+        }
+    }
+
+    fn best_line_with_extensions(fen: &str, depth: u16, extensions: Extensions) -> MoveResult {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::from_str(fen).unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                depth: Some(depth),
+                extensions,
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+
+        let mut last = None;
+        while let Ok(lines) = rx.try_recv() {
+            last = lines.into_iter().next();
+        }
+        last.unwrap()
+    }
+
+    #[test]
+    fn check_extension_finds_mate_past_the_horizon() {
+        // Philidor's legacy, Qg8+ Rxg8 Nf7# needs three plies
+        let fen = "r6k/6pp/7N/8/8/1Q6/8/6K1 w - - 0 1";
+
+        let extended = best_line_with_extensions(fen, 2, Extensions::default());
+        assert_eq!(extended.score(), -CentipawnScore::mated_in(3));
+        assert_eq!(
+            extended.pv().map(|m| m.as_uci()).collect::<Vec<_>>(),
+            ["b3g8", "a8g8", "h6f7"]
+        );
+
+        let plain = best_line_with_extensions(fen, 2, Extensions::NONE);
+        assert!(!plain.score().is_mate());
+    }
+
+    #[test]
+    fn mates_from_the_tt_count_from_the_root() {
+        fn best_line(fen: &str, depth: u16, tt: &mut TranspositionTable) -> MoveResult {
+            let stats = StatisticsHolder::new();
+            let pos = Position::from_str(fen).unwrap();
+            let history = PositionHashHistory::new(pos.hash());
+            let (_stop_tx, stop_rx) = watch::channel(());
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let config = SearcherConfig {
+                depth: Some(depth),
+                extensions: Extensions::NONE,
+                ..SearcherConfig::default()
+            };
+            get_pc_searcher(history, pos, stop_rx, config, &stats, tt).search(tx);
+            let mut last = None;
+            while let Ok(lines) = rx.try_recv() {
+                last = lines.into_iter().next();
+            }
+            last.unwrap()
+        }
+
+        let mut tt = TranspositionTable::default();
+        // The mate in one after Qg8+ Rxg8 is stored first, at the root of its own search
+        let after_sacrifice = best_line("6rk/6pp/7N/8/8/8/8/6K1 w - - 0 1", 5, &mut tt);
+        assert_eq!(after_sacrifice.score(), -CentipawnScore::mated_in(1));
+        let best = best_line("r6k/6pp/7N/8/8/1Q6/8/6K1 w - - 0 1", 3, &mut tt);
+        assert_eq!(best.score(), -CentipawnScore::mated_in(3));
+        assert_eq!(best.score().mate_moves(), Some(2));
+    }
+
+    #[test]
+    fn extension_budget_is_respected() {
+        let fen = "r6k/6pp/7N/8/8/1Q6/8/6K1 w - - 0 1";
+        let no_budget = best_line_with_extensions(
+            fen,
+            2,
+            Extensions {
+                max_per_path: 0,
+                ..Extensions::default()
+            },
+        );
+        assert!(!no_budget.score().is_mate());
+    }
+
+    #[test]
+    fn pawn_push_extension_sees_promotion() {
+        let fen = "7k/8/P7/8/8/8/8/K7 w - - 0 1";
+        let only_pawn_pushes = Extensions {
+            pawn_push: true,
+            max_per_path: 8,
+            ..Extensions::NONE
+        };
+
+        let extended = best_line_with_extensions(fen, 2, only_pawn_pushes);
+        assert_eq!(extended.first_move().unwrap().as_uci(), "a6a7");
+        assert!(
+            extended.pv().any(|m| m.promotion().is_some()),
+            "{extended:?}"
+        );
+
+        let plain = best_line_with_extensions(fen, 2, Extensions::NONE);
+        assert!(!plain.pv().any(|m| m.promotion().is_some()), "{plain:?}");
+    }
+
+    #[test]
+    fn recapture_extension() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::from_str("4k3/8/3p4/4p3/3P4/8/8/4K3 w - - 0 1").unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let only_recaptures = Extensions {
+            recapture: true,
+            max_per_path: 8,
+            ..Extensions::NONE
+        };
+        let mut searcher = get_pc_searcher(
+            history,
+            pos.clone(),
+            stop_rx,
+            SearcherConfig {
+                extensions: only_recaptures,
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        let find = |position: &Position, uci: &str| {
+            let mut buf = BasicMoveBuffer::new();
+            let _ = MoveGenerator::new().generate_legal_moves_for(position, &mut buf);
+            let m = buf.iter().find(|m| m.as_uci() == uci).unwrap().clone();
+            m
+        };
+
+        let capture = find(&searcher.current_position, "d4e5");
+        searcher.current_position.make_move(&capture);
+        assert!(!searcher.should_extend(&capture, 0, None));
+        searcher.path[0] = Some(capture);
+
+        let recapture = find(&searcher.current_position, "d6e5");
+        searcher.current_position.make_move(&recapture);
+        assert!(searcher.should_extend(&recapture, 1, None));
+
+        searcher.config.extensions = Extensions::NONE;
+        assert!(!searcher.should_extend(&recapture, 1, None));
+    }
+
+    #[test]
+    fn only_winning_move_is_singular() {
+        for (fen, tt_move, singular) in [
+            // Only the rook can take the queen
+            ("4k3/8/8/8/7q/8/8/4K2R w K - 0 1", "h1h4", true),
+            // Either knight can
+            ("4k3/8/8/8/7q/5N2/6N1/4K3 w - - 0 1", "g2h4", false),
+        ] {
+            let stats = StatisticsHolder::new();
+            let mut tt = TranspositionTable::default();
+            let pos = Position::from_str(fen).unwrap();
+            let history = PositionHashHistory::new(pos.hash());
+            let (_stop_tx, stop_rx) = watch::channel(());
+            let mut searcher = get_pc_searcher(
+                history,
+                pos.clone(),
+                stop_rx,
+                SearcherConfig::default(),
+                &stats,
+                &mut tt,
+            );
+            let m = {
+                let mut buf = BasicMoveBuffer::new();
+                let _ = MoveGenerator::new().generate_legal_moves_for(&pos, &mut buf);
+                let m = buf.iter().find(|m| m.as_uci() == tt_move).unwrap().clone();
+                m
+            };
+
+            let mut buf = PriorityMoveBuffer::new();
+            let result = searcher
+                .verify_singular(m.clone(), CentipawnScore(100), 4, 1, 0, &mut buf)
+                .unwrap();
+            assert_eq!(result.is_some(), singular, "{fen}");
+        }
+    }
+
+    fn quiescence_result(
+        fen: &str,
+        beta: CentipawnScore,
+        quiescence_checks: bool,
+    ) -> (SearchResult, Statistics) {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::from_str(fen).unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                quiescence_checks,
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        let result = searcher
+            .quiescence(
+                CentipawnScore::MIN,
+                beta,
+                0,
+                0,
+                &mut PriorityMoveBuffer::new(),
+            )
+            .unwrap();
+        (result, stats.get_statistics())
+    }
+
+    #[test]
+    fn quiescence_finds_mate_with_quiet_check() {
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        let (result, _) = quiescence_result(fen, CentipawnScore::MAX, false);
+        assert_eq!(result.score, CentipawnScore(-200));
+        let (result, _) = quiescence_result(fen, CentipawnScore::MAX, true);
+        assert_eq!(result.score, -CentipawnScore::mated_in(1));
+        assert_eq!(result.best_move.unwrap().as_uci(), "a1a8");
+    }
+
+    #[test]
+    fn quiescence_does_not_stand_pat_in_check() {
+        // Two pieces up, but mated
+        let (result, _) = quiescence_result(
+            "6k1/8/8/8/8/8/5PPP/4r1K1 w - - 0 1",
+            CentipawnScore(100),
+            false,
+        );
+        assert_eq!(result.score, CentipawnScore::CHECKMATED);
+    }
+
+    #[test]
+    fn quiescence_skips_losing_captures() {
+        let (result, statistics) = quiescence_result(
+            "4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1",
+            CentipawnScore::MAX,
+            false,
+        );
+        assert!(result.best_move.is_none());
+        assert_eq!(statistics.qnodes, 1);
+    }
+
+    #[test]
+    fn qnodes_are_part_of_the_node_count() {
+        let stats = StatisticsHolder::new();
+        let mut tt = TranspositionTable::default();
+        let pos = Position::from_str(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let history = PositionHashHistory::new(pos.hash());
+        let (_stop_tx, stop_rx) = watch::channel(());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut searcher = get_pc_searcher(
+            history,
+            pos,
+            stop_rx,
+            SearcherConfig {
+                depth: Some(2),
+                ..SearcherConfig::default()
+            },
+            &stats,
+            &mut tt,
+        );
+        searcher.search(tx);
+        let statistics = stats.get_statistics();
+        assert!(statistics.qnodes > 0);
+        assert!(statistics.qnodes < statistics.nodes_searched);
     }
 }
@@ -0,0 +1,116 @@
+use crate::SHARED_COMPONENTS;
+use guts::{Bitboard, Move, MoveType, Piece, Position, Square};
+
+/// The longest capture sequence possible on one square, every piece takes part at most once.
+const MAX_EXCHANGES: usize = 32;
+
+pub fn see_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => 100,
+        Piece::Knight => 300,
+        Piece::Bishop => 300,
+        Piece::Rook => 500,
+        Piece::Queen => 900,
+        Piece::King => 20000,
+    }
+}
+
+/// Static exchange evaluation: the material the side to move wins by making capture `m`, if
+/// both sides keep recapturing on the target square with their least valuable piece for as long
+/// as it pays off. Pins and checks are ignored.
+pub fn see(position: &Position, m: &Move) -> i32 {
+    let board = position.board();
+    let to = m.to();
+    let mut occupied = board.all_pieces();
+    let mut gain = [0; MAX_EXCHANGES];
+    gain[0] = if m.move_type().contains(MoveType::EN_PASSANT) {
+        // The captured pawn stands next to the capturing one
+        occupied.clear_mut(Square::new(to.file(), m.from().rank()));
+        see_value(Piece::Pawn)
+    } else {
+        board.piece_at(to).map_or(0, see_value)
+    };
+    // The piece standing on the target square, which the next capture would take
+    let mut on_target = match m.promotion() {
+        Some(p) => {
+            gain[0] += see_value(p) - see_value(Piece::Pawn);
+            p
+        }
+        None => m.piece(),
+    };
+    occupied.clear_mut(m.from());
+    let mut side = !position.active_color();
+    let mut depth = 0;
+    while depth + 1 < MAX_EXCHANGES {
+        depth += 1;
+        // Speculative, assuming the piece on the target gets taken
+        gain[depth] = see_value(on_target) - gain[depth - 1];
+        if (-gain[depth - 1]).max(gain[depth]) < 0 {
+            break;
+        }
+        let attackers = SHARED_COMPONENTS
+            .move_generator
+            .attackers_to(position, to, occupied)
+            & board[side].all_pieces();
+        let Some((piece, from)) = Piece::ALL.into_iter().find_map(|p| {
+            (attackers & board[side][p])
+                .first_set_square()
+                .map(|s| (p, s))
+        }) else {
+            break;
+        };
+        occupied &= !Bitboard::from_square(from);
+        on_target = piece;
+        side = !side;
+    }
+    while depth > 1 {
+        depth -= 1;
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+    }
+    gain[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::BasicMoveBuffer;
+    use std::str::FromStr;
+
+    fn see_for(fen: &str, uci: &str) -> i32 {
+        let position = Position::from_str(fen).unwrap();
+        let mut buf = BasicMoveBuffer::new();
+        let _ = SHARED_COMPONENTS
+            .move_generator
+            .generate_legal_moves_for(&position, &mut buf);
+        let m = buf
+            .iter()
+            .find(|m| m.as_uci() == uci)
+            .unwrap_or_else(|| panic!("{uci} is not legal in {fen}"));
+        see(&position, m)
+    }
+
+    #[test]
+    fn undefended_piece_is_won() {
+        assert_eq!(see_for("4k3/8/8/3r4/8/8/8/3RK3 w - - 0 1", "d1d5"), 500);
+    }
+
+    #[test]
+    fn defended_pawn_costs_the_knight() {
+        assert_eq!(see_for("4k3/8/2p5/3p4/8/4N3/8/4K3 w - - 0 1", "e3d5"), -200);
+    }
+
+    #[test]
+    fn x_ray_recaptures_count() {
+        // The second rook recaptures from behind the first
+        assert_eq!(see_for("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+        assert_eq!(
+            see_for("3qk3/3r4/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"),
+            -400
+        );
+    }
+
+    #[test]
+    fn en_passant_wins_a_pawn() {
+        assert_eq!(see_for("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+    }
+}
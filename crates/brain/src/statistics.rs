@@ -1,5 +1,7 @@
+use guts::Move;
 use std::fmt::{Display, Formatter};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 #[derive(Clone, Default)]
 #[non_exhaustive]
@@ -7,7 +9,13 @@ pub struct Statistics {
     pub current_depth: u64,
     pub nodes_searched: u64,
     pub nodes_searched_this_depth: u64,
+    /// The part of `nodes_searched` that was searched in quiescence
+    pub qnodes: u64,
     pub tt_hits: u64,
+    pub hashfull: u64,
+    pub current_move: Option<Move>,
+    /// 1-based index of `current_move` in the root move order
+    pub current_move_number: u64,
 }
 
 #[derive(Default)]
@@ -15,7 +23,12 @@ struct StatisticsInternal {
     current_depth: AtomicU64,
     nodes_searched: AtomicU64,
     nodes_searched_this_depth: AtomicU64,
+    qnodes: AtomicU64,
     tt_hits: AtomicU64,
+    hashfull: AtomicU64,
+    // Only changes once per root move, so a lock is cheap enough
+    current_move: Mutex<Option<Move>>,
+    current_move_number: AtomicU64,
 }
 
 impl Display for Statistics {
@@ -27,7 +40,9 @@ impl Display for Statistics {
             self.nodes_searched_this_depth
         )?;
         writeln!(f, "nodes searched total: {}", self.nodes_searched)?;
-        write!(f, "transposition table hits: {}", self.tt_hits)?;
+        writeln!(f, "quiescence nodes: {}", self.qnodes)?;
+        writeln!(f, "transposition table hits: {}", self.tt_hits)?;
+        write!(f, "transposition table fill: {}‰", self.hashfull)?;
         Ok(())
     }
 }
@@ -51,6 +66,12 @@ impl StatisticsHolder {
         self.stats.nodes_searched.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Counts as a regular node as well.
+    pub fn qnode_searched(&self) {
+        self.node_searched();
+        self.stats.qnodes.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn depth_changed(&self, new_depth: u64) {
         self.stats
             .nodes_searched_this_depth
@@ -58,21 +79,44 @@ impl StatisticsHolder {
         self.stats.current_depth.store(new_depth, Ordering::Relaxed);
     }
 
+    pub fn nodes_searched(&self) -> u64 {
+        self.stats.nodes_searched.load(Ordering::Relaxed)
+    }
+
     pub fn tt_hit(&self) {
         let _ = self.stats.tt_hits.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn hashfull_changed(&self, hashfull: u64) {
+        self.stats.hashfull.store(hashfull, Ordering::Relaxed);
+    }
+
+    pub fn root_move_changed(&self, m: &Move, move_number: u64) {
+        *self.stats.current_move.lock().unwrap() = Some(m.clone());
+        self.stats
+            .current_move_number
+            .store(move_number, Ordering::Relaxed);
+    }
+
     pub fn get_statistics(&self) -> Statistics {
         let current_depth = self.stats.current_depth.load(Ordering::Relaxed);
         let nodes_searched_this_depth =
             self.stats.nodes_searched_this_depth.load(Ordering::Relaxed);
         let nodes_searched = self.stats.nodes_searched.load(Ordering::Relaxed);
+        let qnodes = self.stats.qnodes.load(Ordering::Relaxed);
         let tt_hits = self.stats.tt_hits.load(Ordering::Relaxed);
+        let hashfull = self.stats.hashfull.load(Ordering::Relaxed);
+        let current_move = self.stats.current_move.lock().unwrap().clone();
+        let current_move_number = self.stats.current_move_number.load(Ordering::Relaxed);
         Statistics {
             current_depth,
             nodes_searched,
             nodes_searched_this_depth,
+            qnodes,
             tt_hits,
+            hashfull,
+            current_move,
+            current_move_number,
         }
     }
 }
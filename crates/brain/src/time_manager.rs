@@ -1,13 +1,116 @@
+use crate::evaluator::CentipawnScore;
 use crate::{ack, AckTx, RemainingTime};
+use guts::Move;
 use log::info;
 use std::time::Duration;
 use tokio::select;
 use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+/// Safety margin for communication delays when none is configured.
+pub const DEFAULT_MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+/// Moves we expect to still have to play when the GUI doesn't tell us.
+const DEFAULT_MOVES_TO_GO: u32 = 20;
+const HARD_TO_SOFT_RATIO: u32 = 4;
+/// Soft limit scale by the number of iterations the best move has stayed the same.
+const STABILITY_SCALES: [f64; 5] = [1.5, 1.2, 1.0, 0.85, 0.7];
+/// A score drop of this many centipawns between iterations gets the most extra time.
+const MAX_SCORE_DROP: i32 = 100;
+const MAX_SCORE_DROP_SCALE: f64 = 0.5;
 
 #[derive(Debug)]
 pub enum TimeManagerMessage {
-    Update(AckTx, Option<RemainingTime>),
-    Start(AckTx, watch::Receiver<()>),
+    Update(AckTx, Option<RemainingTime>, Duration),
+    Start(
+        AckTx,
+        watch::Receiver<()>,
+        mpsc::UnboundedReceiver<IterationReport>,
+    ),
+}
+
+/// The outcome of a finished iteration, used to decide whether another one is worth starting.
+#[derive(Debug, Clone)]
+pub struct IterationReport {
+    pub best_move: Option<Move>,
+    pub score: CentipawnScore,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeBudget {
+    /// No new iteration is started after this, `None` when the time per move is fixed.
+    pub soft: Option<Duration>,
+    /// The search is interrupted once this runs out.
+    pub hard: Duration,
+}
+
+impl TimeBudget {
+    pub fn allocate(remaining_time: RemainingTime, move_overhead: Duration) -> Self {
+        match remaining_time {
+            RemainingTime::ForGame {
+                remaining,
+                increment,
+                moves_to_go,
+            } => {
+                let usable = without_overhead(remaining, move_overhead);
+                let moves = moves_to_go.map_or(DEFAULT_MOVES_TO_GO, |n| {
+                    (n as u32).clamp(1, DEFAULT_MOVES_TO_GO)
+                });
+                // The increment only arrives after moving, so it can't be relied on when low on time
+                let soft = usable / moves + increment * 3 / 4;
+                let limit = if moves == 1 {
+                    usable * 9 / 10
+                } else {
+                    usable / 2
+                };
+                let hard = (soft * HARD_TO_SOFT_RATIO).min(limit);
+                Self {
+                    soft: Some(soft.min(hard)),
+                    hard,
+                }
+            }
+            RemainingTime::ForMove(time) => Self {
+                soft: None,
+                hard: without_overhead(time, move_overhead),
+            },
+        }
+    }
+
+    fn scaled_soft(&self, scale: f64) -> Option<Duration> {
+        self.soft.map(|soft| soft.mul_f64(scale).min(self.hard))
+    }
+}
+
+/// The overhead never takes more than half, so there's always some time to search.
+fn without_overhead(time: Duration, move_overhead: Duration) -> Duration {
+    time - move_overhead.min(time / 2)
+}
+
+/// Tracks how the best move and score evolve over iterations.
+#[derive(Debug, Default)]
+struct Stability {
+    previous: Option<IterationReport>,
+    stable_iterations: usize,
+}
+
+impl Stability {
+    /// Returns the factor to scale the soft limit with after this iteration.
+    fn update(&mut self, report: IterationReport) -> f64 {
+        let mut score_drop = 0;
+        if let Some(previous) = &self.previous {
+            if previous.best_move == report.best_move {
+                self.stable_iterations += 1;
+            } else {
+                self.stable_iterations = 0;
+            }
+            score_drop = previous.score.0.saturating_sub(report.score.0).max(0);
+        }
+        self.previous = Some(report);
+
+        let stability = STABILITY_SCALES[self.stable_iterations.min(STABILITY_SCALES.len() - 1)];
+        let score_drop = 1.0
+            + score_drop.min(MAX_SCORE_DROP) as f64 / MAX_SCORE_DROP as f64 * MAX_SCORE_DROP_SCALE;
+        stability * score_drop
+    }
 }
 
 pub struct TimeManagerHandle {
@@ -23,17 +126,22 @@ impl TimeManagerHandle {
         Self { sender }
     }
 
-    pub async fn start(&self, stop: watch::Receiver<()>) {
+    /// Resolves once the search should stop, the iterations are fed back to decide on the soft limit.
+    pub async fn start(
+        &self,
+        stop: watch::Receiver<()>,
+        iterations: mpsc::UnboundedReceiver<IterationReport>,
+    ) {
         let (tx, rx) = ack();
-        let msg = TimeManagerMessage::Start(tx, stop);
+        let msg = TimeManagerMessage::Start(tx, stop, iterations);
 
         let _ = self.sender.send(msg);
         rx.await.expect("Actor task was killed")
     }
 
-    pub async fn update(&self, remaining_time: Option<RemainingTime>) {
+    pub async fn update(&self, remaining_time: Option<RemainingTime>, move_overhead: Duration) {
         let (tx, rx) = ack();
-        let msg = TimeManagerMessage::Update(tx, remaining_time);
+        let msg = TimeManagerMessage::Update(tx, remaining_time, move_overhead);
         let _ = self.sender.send(msg);
         rx.await.expect("Actor task was killed")
     }
@@ -41,20 +149,31 @@ impl TimeManagerHandle {
 
 struct TimeManager {
     remaining_time: Option<RemainingTime>,
+    move_overhead: Duration,
     receiver: mpsc::UnboundedReceiver<TimeManagerMessage>,
 }
 
 impl TimeManager {
     async fn handle_event(&mut self, msg: TimeManagerMessage) {
         match msg {
-            TimeManagerMessage::Update(ack, remaining_time) => {
+            TimeManagerMessage::Update(ack, remaining_time, move_overhead) => {
                 self.remaining_time = remaining_time;
+                self.move_overhead = move_overhead;
                 let _ = ack.send(());
             }
-            TimeManagerMessage::Start(ack, mut stop) => {
-                let delay = self.delay_for_time();
+            TimeManagerMessage::Start(ack, mut stop, iterations) => {
+                let budget = self
+                    .remaining_time
+                    .map(|time| TimeBudget::allocate(time, self.move_overhead));
+                info!("Timer started with {budget:?}");
+                let start = Instant::now();
                 select! {
-                    _ = delay => {
+                    _ = Self::hard_limit(budget) => {
+                        info!("Hard time limit reached");
+                        let _ = ack.send(());
+                    }
+                    _ = Self::soft_limit(budget, start, iterations) => {
+                        info!("Soft time limit reached after {:?}", start.elapsed());
                         let _ = ack.send(());
                     }
                     _ = stop.changed() => {
@@ -65,28 +184,28 @@ impl TimeManager {
         }
     }
 
-    async fn delay_for_time(&self) {
-        let time = if let Some(remaining_time) = self.remaining_time {
-            match remaining_time {
-                RemainingTime::ForGame {
-                    remaining,
-                    increment,
-                } => increment + ((remaining - increment) / 10),
-                RemainingTime::ForMove(time) => time,
-            }
-        } else {
-            Duration::from_secs(u64::MAX)
-        };
-
-        let time = if time > Duration::from_millis(5) {
-            time - Duration::from_millis(5)
-        } else {
-            time
-        };
+    async fn hard_limit(budget: Option<TimeBudget>) {
+        match budget {
+            Some(budget) => tokio::time::sleep(budget.hard).await,
+            None => std::future::pending().await,
+        }
+    }
 
-        info!("Timer started for {time:?}");
-        tokio::time::sleep(time).await;
-        info!("Timer done");
+    async fn soft_limit(
+        budget: Option<TimeBudget>,
+        start: Instant,
+        mut iterations: mpsc::UnboundedReceiver<IterationReport>,
+    ) {
+        let mut stability = Stability::default();
+        while let Some(report) = iterations.recv().await {
+            let scale = stability.update(report);
+            match budget.and_then(|b| b.scaled_soft(scale)) {
+                Some(soft) if start.elapsed() >= soft => return,
+                _ => {}
+            }
+        }
+        // A finished search is ended by the aggregator itself
+        std::future::pending().await
     }
 
     async fn run(&mut self) {
@@ -98,6 +217,7 @@ impl TimeManager {
     pub fn new(receiver: mpsc::UnboundedReceiver<TimeManagerMessage>) -> Self {
         Self {
             remaining_time: None,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
             receiver,
         }
     }
@@ -107,16 +227,27 @@ impl TimeManager {
 mod tests {
     use super::*;
     use tokio::sync::watch;
-    use tokio::time::Instant;
+
+    fn for_game(remaining_ms: u64, increment_ms: u64, moves_to_go: Option<u16>) -> RemainingTime {
+        RemainingTime::ForGame {
+            remaining: Duration::from_millis(remaining_ms),
+            increment: Duration::from_millis(increment_ms),
+            moves_to_go,
+        }
+    }
 
     #[tokio::test]
     async fn times_out_correctly() {
         let timer = TimeManagerHandle::new();
         timer
-            .update(Some(RemainingTime::ForMove(Duration::from_secs(3))))
+            .update(
+                Some(RemainingTime::ForMove(Duration::from_secs(3))),
+                DEFAULT_MOVE_OVERHEAD,
+            )
             .await;
         let (_tx, rx) = watch::channel(());
-        let s = timer.start(rx);
+        let (_iterations_tx, iterations_rx) = mpsc::unbounded_channel();
+        let s = timer.start(rx, iterations_rx);
         let upper_bound = tokio::time::sleep(Duration::from_secs(4));
 
         let now = Instant::now();
@@ -131,4 +262,110 @@ mod tests {
             "Timer lower bound failed"
         );
     }
+
+    #[tokio::test]
+    async fn soft_limit_stops_between_iterations() {
+        let timer = TimeManagerHandle::new();
+        timer
+            .update(Some(for_game(20_000, 0, None)), Duration::ZERO)
+            .await;
+        let (_tx, rx) = watch::channel(());
+        let (iterations_tx, iterations_rx) = mpsc::unbounded_channel();
+        let now = Instant::now();
+        let s = timer.start(rx, iterations_rx);
+        let report = async {
+            tokio::time::sleep(Duration::from_millis(1_600)).await;
+            let _ = iterations_tx.send(IterationReport {
+                best_move: None,
+                score: CentipawnScore(0),
+            });
+            std::future::pending::<()>().await
+        };
+        select! {
+            _ = s => {}
+            _ = report => {}
+        }
+        let duration = now.elapsed();
+        assert!(duration >= Duration::from_millis(1_600));
+        assert!(duration < Duration::from_millis(2_500), "{duration:?}");
+    }
+
+    #[test]
+    fn never_plans_past_the_clock() {
+        let overhead = Duration::from_millis(300);
+        for remaining in [0, 1, 10, 299, 300, 600, 1_000, 15_000, 60_000, 7_598_039] {
+            for increment in [0, 1_000, 2_000, 30_000, 8_395_219] {
+                for moves_to_go in [None, Some(0), Some(1), Some(2), Some(40)] {
+                    let budget =
+                        TimeBudget::allocate(for_game(remaining, increment, moves_to_go), overhead);
+                    let remaining = Duration::from_millis(remaining);
+                    assert!(budget.hard <= remaining);
+                    if remaining >= overhead * 2 {
+                        assert!(budget.hard + overhead <= remaining);
+                    }
+                    assert!(budget.soft.unwrap() <= budget.hard);
+                    assert!(budget.scaled_soft(10.0).unwrap() <= budget.hard);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fewer_moves_to_go_allows_more_time() {
+        let overhead = Duration::ZERO;
+        let many = TimeBudget::allocate(for_game(60_000, 0, Some(40)), overhead);
+        let few = TimeBudget::allocate(for_game(60_000, 0, Some(2)), overhead);
+        let unknown = TimeBudget::allocate(for_game(60_000, 0, None), overhead);
+        assert!(few.soft > many.soft);
+        assert!(few.hard > many.hard);
+        assert_eq!(many, unknown);
+    }
+
+    #[test]
+    fn fixed_move_time_has_no_soft_limit() {
+        let budget = TimeBudget::allocate(
+            RemainingTime::ForMove(Duration::from_secs(1)),
+            Duration::from_millis(50),
+        );
+        assert_eq!(budget.soft, None);
+        assert_eq!(budget.hard, Duration::from_millis(950));
+    }
+
+    #[test]
+    fn unstable_or_dropping_searches_get_more_time() {
+        let report = |m: Option<Move>, score| IterationReport {
+            best_move: m,
+            score: CentipawnScore(score),
+        };
+        let position = guts::Position::default();
+        let mut buf = guts::BasicMoveBuffer::new();
+        let _ = crate::SHARED_COMPONENTS
+            .move_generator
+            .generate_legal_moves_for(&position, &mut buf);
+        let mut moves = buf.iter().cloned();
+        let (a, b) = (moves.next(), moves.next());
+
+        let mut stability = Stability::default();
+        stability.update(report(a.clone(), 20));
+        let stable = (0..5)
+            .map(|_| stability.update(report(a.clone(), 20)))
+            .last()
+            .unwrap();
+        let changed = stability.update(report(b.clone(), 20));
+        assert!(changed > stable);
+
+        let mut stability = Stability::default();
+        stability.update(report(a.clone(), 20));
+        let steady = stability.update(report(a.clone(), 20));
+        let mut stability = Stability::default();
+        stability.update(report(a.clone(), 20));
+        let dropped = stability.update(report(a, -80));
+        assert!(dropped > steady);
+
+        let mut stability = Stability::default();
+        stability.update(report(b.clone(), CentipawnScore::MAX.0));
+        assert!(stability
+            .update(report(b, CentipawnScore::MIN.0))
+            .is_finite());
+    }
 }
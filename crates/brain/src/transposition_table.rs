@@ -1,18 +1,16 @@
 use crate::evaluator::ScoreBound;
 use crate::CentipawnScore;
-use guts::{Move, ZobristHash};
+use guts::{Move, MoveType, Piece, Square, ZobristHash};
 use log::info;
 
-#[derive(Debug, Copy, Clone)]
-struct TTKey(u64);
-
-impl From<ZobristHash> for TTKey {
-    fn from(zh: ZobristHash) -> Self {
-        Self(zh.0)
-    }
-}
+const ENTRIES_PER_BUCKET: usize = 4;
+// The first slots keep the deepest results, the last one takes whatever comes in
+const DEPTH_PREFERRED_SLOTS: usize = ENTRIES_PER_BUCKET - 1;
+const ALWAYS_REPLACE_SLOT: usize = ENTRIES_PER_BUCKET - 1;
+// Number of buckets looked at when estimating how full the table is
+const HASHFULL_SAMPLE_BUCKETS: usize = 250;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TTEntry {
     pub hash: ZobristHash,
     pub depth: u16,
@@ -21,43 +19,383 @@ pub struct TTEntry {
     pub m: Option<Move>,
 }
 
+/// Everything needed to rebuild a [`Move`], packed into 32 bits.
+/// Zero is never a valid move since the move type always has a bit set.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+struct PackedMove(u32);
+
+impl PackedMove {
+    const NONE: Self = Self(0);
+
+    fn pack(m: &Move) -> Self {
+        let promotion = m.promotion().map_or(0, |p| p.index() as u32 + 1);
+        Self(
+            m.from().bitboard_index() as u32
+                | (m.to().bitboard_index() as u32) << 6
+                | (m.piece().index() as u32) << 12
+                | promotion << 15
+                | (m.move_type().bits() as u32) << 18,
+        )
+    }
+
+    fn unpack(self) -> Option<Move> {
+        if self == Self::NONE {
+            return None;
+        }
+        let from = Square::from_index((self.0 & 0x3F) as u8);
+        let to = Square::from_index((self.0 >> 6 & 0x3F) as u8);
+        let piece = Piece::from_usize_panic((self.0 >> 12 & 0x7) as usize);
+        let promotion = match self.0 >> 15 & 0x7 {
+            0 => None,
+            p => Some(Piece::from_usize_panic(p as usize - 1)),
+        };
+        let move_type = MoveType::from_bits_truncate((self.0 >> 18) as u8);
+        Some(Move::new(from, to, piece, move_type, promotion))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+enum PackedBound {
+    Empty,
+    Exact,
+    Upper,
+    Lower,
+}
+
+impl From<ScoreBound> for PackedBound {
+    fn from(bound: ScoreBound) -> Self {
+        match bound {
+            ScoreBound::Exact => Self::Exact,
+            ScoreBound::Upper => Self::Upper,
+            ScoreBound::Lower => Self::Lower,
+        }
+    }
+}
+
+/// Compact in-table representation, 16 bytes so four fit in a cache line.
+/// The bucket index already covers the low bits of the hash, the key stores the high ones.
+#[derive(Debug, Copy, Clone)]
+struct PackedEntry {
+    key: u32,
+    m: PackedMove,
+    score: i32,
+    depth: u16,
+    generation: u8,
+    bound: PackedBound,
+}
+
+impl Default for PackedEntry {
+    fn default() -> Self {
+        Self {
+            key: 0,
+            m: PackedMove::NONE,
+            score: 0,
+            depth: 0,
+            generation: 0,
+            bound: PackedBound::Empty,
+        }
+    }
+}
+
+impl PackedEntry {
+    fn is_empty(&self) -> bool {
+        self.bound == PackedBound::Empty
+    }
+
+    fn unpack(&self, hash: ZobristHash) -> TTEntry {
+        TTEntry {
+            hash,
+            depth: self.depth,
+            score: CentipawnScore(self.score),
+            bound: match self.bound {
+                PackedBound::Exact => ScoreBound::Exact,
+                PackedBound::Upper => ScoreBound::Upper,
+                PackedBound::Lower => ScoreBound::Lower,
+                PackedBound::Empty => unreachable!("Unpacked an empty entry"),
+            },
+            m: self.m.unpack(),
+        }
+    }
+
+    /// How much we would like to keep this entry around: deep entries from the current search are worth the most.
+    fn worth(&self, generation: u8) -> i32 {
+        if self.is_empty() {
+            i32::MIN
+        } else {
+            let age = generation.wrapping_sub(self.generation) as i32;
+            self.depth as i32 - 2 * age
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C, align(64))]
+struct Bucket {
+    entries: [PackedEntry; ENTRIES_PER_BUCKET],
+}
+
 pub struct TranspositionTable {
-    inner: Vec<Option<TTEntry>>,
+    buckets: Vec<Bucket>,
     mask: u64,
+    generation: u8,
 }
 
 impl Default for TranspositionTable {
     fn default() -> Self {
-        let max_size_bytes = 16 * 1024 * 1024;
+        let max_size_bytes = Self::DEFAULT_SIZE_MIB as u64 * 1024 * 1024;
         Self::new(max_size_bytes)
     }
 }
 
 impl TranspositionTable {
+    pub const DEFAULT_SIZE_MIB: usize = 16;
+
     pub fn new(max_size_bytes: u64) -> Self {
-        let entry_size = std::mem::size_of::<Option<TTEntry>>() as u64;
-        let ideal_entries = max_size_bytes / entry_size;
-        let table_entries = 1 << (63 - ideal_entries.leading_zeros() as u64);
-        let mask = table_entries - 1;
-        let mut inner = Vec::with_capacity(table_entries as usize);
-        for _ in 0..table_entries {
-            inner.push(None)
-        }
-        info!("Initializing transposition table with {table_entries} entries, ({table_size_bytes} bytes total, {entry_size} bytes per entry)", table_size_bytes = table_entries * entry_size);
-        Self { inner, mask }
+        let mut tt = Self {
+            buckets: Vec::new(),
+            mask: 0,
+            generation: 0,
+        };
+        tt.resize(max_size_bytes);
+        tt
+    }
+
+    /// Reallocates the table, throwing away all entries.
+    pub fn resize(&mut self, max_size_bytes: u64) {
+        let bucket_size = std::mem::size_of::<Bucket>() as u64;
+        let ideal_buckets = (max_size_bytes / bucket_size).max(1);
+        let table_buckets = 1 << (63 - ideal_buckets.leading_zeros() as u64);
+        // Free the old table first so we don't hold both in memory
+        self.buckets = Vec::new();
+        self.buckets = vec![Bucket::default(); table_buckets as usize];
+        self.mask = table_buckets - 1;
+        self.generation = 0;
+        info!("Initializing transposition table with {table_buckets} buckets of {ENTRIES_PER_BUCKET} entries, ({table_size_bytes} bytes total, {bucket_size} bytes per bucket)", table_size_bytes = table_buckets * bucket_size);
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.fill(Bucket::default());
+        self.generation = 0;
+    }
+
+    /// Marks the start of a new search, entries from older searches become preferred for replacement.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
     }
 
-    pub fn get(&self, hash: ZobristHash) -> Option<&TTEntry> {
-        self.inner[self.get_index(TTKey::from(hash))].as_ref()
+    pub fn get(&self, hash: ZobristHash) -> Option<TTEntry> {
+        let key = Self::get_key(hash);
+        self.buckets[self.get_index(hash)]
+            .entries
+            .iter()
+            .find(|e| !e.is_empty() && e.key == key)
+            .map(|e| e.unpack(hash))
     }
 
     pub fn set(&mut self, entry: TTEntry) {
-        let hash = entry.hash;
-        let idx = self.get_index(TTKey::from(hash));
-        self.inner[idx] = Some(entry)
+        let key = Self::get_key(entry.hash);
+        let idx = self.get_index(entry.hash);
+        let generation = self.generation;
+        let entries = &mut self.buckets[idx].entries;
+
+        let mut new = PackedEntry {
+            key,
+            m: entry.m.as_ref().map_or(PackedMove::NONE, PackedMove::pack),
+            score: entry.score.0,
+            depth: entry.depth,
+            generation,
+            bound: entry.bound.into(),
+        };
+
+        // Same position: keep a deeper entry from this search, quiescence stores depth 0 all the
+        // time. Don't forget a move we already knew either.
+        if let Some(existing) = entries.iter_mut().find(|e| !e.is_empty() && e.key == key) {
+            if new.depth >= existing.depth
+                || matches!(entry.bound, ScoreBound::Exact)
+                || existing.generation != generation
+            {
+                if new.m == PackedMove::NONE {
+                    new.m = existing.m;
+                }
+                *existing = new;
+            }
+            return;
+        }
+
+        let victim = (0..DEPTH_PREFERRED_SLOTS)
+            .min_by_key(|&i| entries[i].worth(generation))
+            .expect("Bucket has no depth-preferred slots");
+        if new.worth(generation) >= entries[victim].worth(generation) {
+            // Demote the replaced entry instead of throwing it away
+            entries[ALWAYS_REPLACE_SLOT] = entries[victim];
+            entries[victim] = new;
+        } else {
+            entries[ALWAYS_REPLACE_SLOT] = new;
+        }
+    }
+
+    /// Permille of sampled entries that were written during the current search.
+    pub fn hashfull(&self) -> u16 {
+        let sampled = &self.buckets[..HASHFULL_SAMPLE_BUCKETS.min(self.buckets.len())];
+        let used = sampled
+            .iter()
+            .flat_map(|b| b.entries.iter())
+            .filter(|e| !e.is_empty() && e.generation == self.generation)
+            .count();
+        (used * 1000 / (sampled.len() * ENTRIES_PER_BUCKET)) as u16
+    }
+
+    fn get_index(&self, hash: ZobristHash) -> usize {
+        (hash.0 & self.mask) as usize
+    }
+
+    fn get_key(hash: ZobristHash) -> u32 {
+        (hash.0 >> 32) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::{BasicMoveBuffer, MoveGenerator, Position};
+    use std::str::FromStr;
+
+    fn entry(hash: u64, depth: u16) -> TTEntry {
+        TTEntry {
+            hash: ZobristHash(hash),
+            depth,
+            score: CentipawnScore(depth as i32),
+            bound: ScoreBound::Exact,
+            m: None,
+        }
+    }
+
+    // Different keys, same bucket
+    fn colliding(i: u64) -> u64 {
+        (i + 1) << 32
+    }
+
+    #[test]
+    fn entries_fit_in_a_cache_line() {
+        assert_eq!(std::mem::size_of::<PackedEntry>(), 16);
+        assert_eq!(std::mem::size_of::<Bucket>(), 64);
+    }
+
+    #[test]
+    fn packed_moves_roundtrip() {
+        let pos = Position::from_str("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        let mut buf = BasicMoveBuffer::new();
+        let _ = MoveGenerator::new().generate_legal_moves_for(&pos, &mut buf);
+        assert!(buf.iter().any(|m| m.promotion().is_some()));
+        for m in buf.iter() {
+            assert_eq!(PackedMove::pack(m).unpack().as_ref(), Some(m));
+        }
+    }
+
+    #[test]
+    fn get_after_set() {
+        let mut tt = TranspositionTable::new(1024);
+        tt.set(entry(0xDEADBEEF_12345678, 3));
+        let found = tt.get(ZobristHash(0xDEADBEEF_12345678)).unwrap();
+        assert_eq!(found.depth, 3);
+        assert!(tt.get(ZobristHash(0xBEEFDEAD_12345678)).is_none());
+    }
+
+    #[test]
+    fn keeps_deep_entries_and_always_stores_the_latest() {
+        let mut tt = TranspositionTable::new(64);
+        for i in 0..DEPTH_PREFERRED_SLOTS as u64 {
+            tt.set(entry(colliding(i), 10));
+        }
+        for i in 10..20 {
+            tt.set(entry(colliding(i), 1));
+        }
+        for i in 0..DEPTH_PREFERRED_SLOTS as u64 {
+            assert!(tt.get(ZobristHash(colliding(i))).is_some(), "{i}");
+        }
+        assert!(tt.get(ZobristHash(colliding(19))).is_some());
+        assert!(tt.get(ZobristHash(colliding(18))).is_none());
+    }
+
+    #[test]
+    fn shallow_bounds_keep_a_deeper_entry_of_the_same_position() {
+        let mut tt = TranspositionTable::new(1024);
+        let hash = 0xDEADBEEF_12345678;
+        tt.set(entry(hash, 8));
+        tt.set(TTEntry {
+            bound: ScoreBound::Upper,
+            ..entry(hash, 0)
+        });
+        assert_eq!(tt.get(ZobristHash(hash)).unwrap().depth, 8);
+
+        // Unless the shallow result is exact, or the deep one is from an older search
+        tt.set(entry(hash, 0));
+        assert_eq!(tt.get(ZobristHash(hash)).unwrap().depth, 0);
+        tt.set(entry(hash, 8));
+        tt.new_search();
+        tt.set(TTEntry {
+            bound: ScoreBound::Lower,
+            ..entry(hash, 0)
+        });
+        assert_eq!(tt.get(ZobristHash(hash)).unwrap().depth, 0);
     }
 
-    fn get_index(&self, key: TTKey) -> usize {
-        (key.0 & self.mask) as usize
+    #[test]
+    fn old_entries_get_replaced() {
+        let mut tt = TranspositionTable::new(64);
+        for i in 0..DEPTH_PREFERRED_SLOTS as u64 {
+            tt.set(entry(colliding(i), 4));
+        }
+        for _ in 0..3 {
+            tt.new_search();
+        }
+        for i in 10..10 + DEPTH_PREFERRED_SLOTS as u64 {
+            tt.set(entry(colliding(i), 1));
+        }
+        for i in 10..10 + DEPTH_PREFERRED_SLOTS as u64 {
+            assert!(tt.get(ZobristHash(colliding(i))).is_some(), "{i}");
+        }
+    }
+
+    #[test]
+    fn update_keeps_known_move() {
+        let mut tt = TranspositionTable::new(1024);
+        let pos = Position::default();
+        let mut buf = BasicMoveBuffer::new();
+        let _ = MoveGenerator::new().generate_legal_moves_for(&pos, &mut buf);
+        let m = buf.iter().next().unwrap().clone();
+        tt.set(TTEntry {
+            m: Some(m.clone()),
+            ..entry(pos.hash().0, 2)
+        });
+        tt.set(entry(pos.hash().0, 3));
+        let found = tt.get(pos.hash()).unwrap();
+        assert_eq!(found.depth, 3);
+        assert_eq!(found.m, Some(m));
+    }
+
+    #[test]
+    fn clear_and_resize_empty_the_table() {
+        let mut tt = TranspositionTable::new(1024);
+        tt.set(entry(42, 1));
+        tt.clear();
+        assert!(tt.get(ZobristHash(42)).is_none());
+        tt.set(entry(42, 1));
+        tt.resize(4096);
+        assert!(tt.get(ZobristHash(42)).is_none());
+        assert_eq!(tt.buckets.len(), 4096 / 64);
+    }
+
+    #[test]
+    fn hashfull_counts_current_generation() {
+        let mut tt = TranspositionTable::new(64 * 1024);
+        assert_eq!(tt.hashfull(), 0);
+        for i in 0..HASHFULL_SAMPLE_BUCKETS as u64 {
+            tt.set(entry(i, 1));
+        }
+        assert_eq!(tt.hashfull(), 250);
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
     }
 }
@@ -0,0 +1,246 @@
+use crate::lichess::{LichessConfig, Speed};
+use crate::uci::options::{
+    EngineOptions, OptionError, HASH, MOVE_OVERHEAD, MULTI_PV, PONDER, USE_NNUE,
+};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Settings read at startup from a TOML file, or JSON when the file name ends in `.json`. Every
+/// value is optional and falls back to what the binary would use without a file.
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub engine: EngineSection,
+    pub lichess: LichessSection,
+    pub logging: LoggingSection,
+}
+
+/// Engine options, checked against the same bounds as `setoption`.
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineSection {
+    /// Transposition table size in MiB, for Lichess it replaces the sizes per speed
+    pub hash: Option<usize>,
+    /// The search is single threaded, so only 1 is accepted
+    pub threads: Option<usize>,
+    pub move_overhead_ms: Option<u64>,
+    pub multi_pv: Option<usize>,
+    pub ponder: Option<bool>,
+    pub nnue: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LichessSection {
+    pub base_url: Option<String>,
+    pub token_path: Option<PathBuf>,
+    /// Lichess ids, an empty list accepts challenges from anyone
+    pub allowed_challengers: Option<Vec<String>>,
+    pub variants: Option<Vec<String>>,
+    pub speeds: Option<Vec<Speed>>,
+    pub rated: Option<bool>,
+    pub casual: Option<bool>,
+    pub max_concurrent_games: Option<usize>,
+    /// Transposition table size in MiB by game speed, applied after `engine.hash`
+    pub hash_per_speed: Option<HashMap<Speed, usize>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub debug: Option<bool>,
+    /// Where to append the JSON lines of every search
+    pub search_log: Option<PathBuf>,
+}
+
+impl ConfigFile {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let parsed = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+        {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| anyhow!("Invalid config file {}: {e}", path.display()))
+    }
+
+    /// `options` with the engine section applied on top.
+    pub fn engine_options(&self, mut options: EngineOptions) -> Result<EngineOptions> {
+        self.engine
+            .apply(|name, value| options.set(name, Some(value)).map(|_| ()))?;
+        Ok(options)
+    }
+
+    /// `config` with the engine and Lichess sections applied on top.
+    pub fn lichess_config(&self, mut config: LichessConfig) -> Result<LichessConfig> {
+        self.engine
+            .apply(|name, value| config.set_option(name, Some(value)))?;
+        let lichess = self.lichess.clone();
+        let challenges = &mut config.challenges;
+        if let Some(base_url) = lichess.base_url {
+            config.base_url = base_url;
+        }
+        if let Some(token_path) = lichess.token_path {
+            config.token_path = token_path;
+        }
+        if let Some(allowed_challengers) = lichess.allowed_challengers {
+            challenges.allowed_challengers = allowed_challengers;
+        }
+        if let Some(variants) = lichess.variants {
+            challenges.variants = variants;
+        }
+        if let Some(speeds) = lichess.speeds {
+            challenges.speeds = speeds;
+        }
+        if let Some(rated) = lichess.rated {
+            challenges.rated = rated;
+        }
+        if let Some(casual) = lichess.casual {
+            challenges.casual = casual;
+        }
+        if let Some(max_concurrent_games) = lichess.max_concurrent_games {
+            challenges.max_concurrent_games = max_concurrent_games;
+        }
+        if let Some(hash_per_speed) = lichess.hash_per_speed {
+            for (speed, size_mib) in hash_per_speed {
+                HASH.parse(Some(&size_mib.to_string()))?;
+                config.hash_size_mib.insert(speed, size_mib);
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl EngineSection {
+    /// Passes every value that was set to `set` under its UCI option name.
+    fn apply(&self, mut set: impl FnMut(&str, &str) -> Result<(), OptionError>) -> Result<()> {
+        if let Some(threads) = self.threads.filter(|&t| t != 1) {
+            return Err(anyhow!(
+                "Invalid threads value '{threads}', the search is single threaded"
+            ));
+        }
+        let values = [
+            (HASH.name, self.hash.map(|v| v.to_string())),
+            (
+                MOVE_OVERHEAD.name,
+                self.move_overhead_ms.map(|v| v.to_string()),
+            ),
+            (MULTI_PV.name, self.multi_pv.map(|v| v.to_string())),
+            (PONDER.name, self.ponder.map(|v| v.to_string())),
+            (USE_NNUE.name, self.nnue.map(|v| v.to_string())),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                set(name, &value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use brain::EvaluatorKind;
+    use std::time::Duration;
+
+    const TOML: &str = r#"
+[engine]
+hash = 64
+move_overhead_ms = 100
+nnue = true
+
+[lichess]
+base_url = "http://localhost:8080"
+allowed_challengers = []
+speeds = ["blitz", "rapid"]
+max_concurrent_games = 2
+
+[lichess.hash_per_speed]
+classical = 256
+
+[logging]
+search_log = "searches.jsonl"
+"#;
+
+    #[test]
+    fn toml_and_json_files() {
+        let from_toml: ConfigFile = toml::from_str(TOML).unwrap();
+        let from_json: ConfigFile = serde_json::from_str(
+            r#"{
+                "engine": { "hash": 64, "move_overhead_ms": 100, "nnue": true },
+                "lichess": {
+                    "base_url": "http://localhost:8080",
+                    "allowed_challengers": [],
+                    "speeds": ["blitz", "rapid"],
+                    "max_concurrent_games": 2,
+                    "hash_per_speed": { "classical": 256 }
+                },
+                "logging": { "search_log": "searches.jsonl" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(from_toml, from_json);
+        assert_eq!(
+            from_toml.logging.search_log,
+            Some(PathBuf::from("searches.jsonl"))
+        );
+        assert!(toml::from_str::<ConfigFile>("[engine]\nhash_mib = 64").is_err());
+    }
+
+    #[test]
+    fn sections_apply_on_top_of_defaults() {
+        let file: ConfigFile = toml::from_str(TOML).unwrap();
+        let options = file.engine_options(EngineOptions::default()).unwrap();
+        assert_eq!(
+            options,
+            EngineOptions {
+                hash_size_mib: 64,
+                move_overhead: Duration::from_millis(100),
+                evaluator: EvaluatorKind::Nnue,
+                ..EngineOptions::default()
+            }
+        );
+
+        let config = file.lichess_config(LichessConfig::default()).unwrap();
+        assert_eq!(config.hash_size_for(Speed::Bullet), 64);
+        assert_eq!(config.hash_size_for(Speed::Classical), 256);
+        assert_eq!(config.options.evaluator, EvaluatorKind::Nnue);
+        assert!(config.options.ponder);
+        assert_eq!(config.base_url, "http://localhost:8080");
+        assert_eq!(config.token_path, PathBuf::from("lichess-api-token"));
+        assert!(config.challenges.allowed_challengers.is_empty());
+        assert_eq!(config.challenges.speeds, [Speed::Blitz, Speed::Rapid]);
+        assert_eq!(config.challenges.max_concurrent_games, 2);
+        assert!(!config.challenges.rated);
+    }
+
+    #[test]
+    fn hash_per_speed_keeps_the_other_defaults() {
+        let file: ConfigFile = toml::from_str("[lichess.hash_per_speed]\nbullet = 4").unwrap();
+        let config = file.lichess_config(LichessConfig::default()).unwrap();
+        assert_eq!(config.hash_size_for(Speed::Bullet), 4);
+        assert_eq!(config.hash_size_for(Speed::Blitz), 32);
+        assert!(toml::from_str::<ConfigFile>("[lichess.hash_per_speed]\nlightning = 4").is_err());
+    }
+
+    #[test]
+    fn values_are_validated() {
+        let file: ConfigFile = toml::from_str("[engine]\nhash = 0").unwrap();
+        assert_eq!(
+            file.engine_options(EngineOptions::default())
+                .unwrap_err()
+                .to_string(),
+            "Invalid Hash value '0', expected 1 to 1024"
+        );
+        let file: ConfigFile = toml::from_str("[lichess.hash_per_speed]\nblitz = 4096").unwrap();
+        assert!(file.lichess_config(LichessConfig::default()).is_err());
+        let file: ConfigFile = toml::from_str("[engine]\nthreads = 4").unwrap();
+        assert!(file.lichess_config(LichessConfig::default()).is_err());
+    }
+}
@@ -1,3 +1,8 @@
+pub mod config;
 pub mod lichess;
 pub mod profiling;
+#[cfg(test)]
+mod scripted_gui;
+pub mod search_log;
 pub mod uci;
+pub mod xboard;
@@ -1,6 +1,6 @@
 use crate::lichess::decode_response;
 use crate::lichess::engine_handler::EngineHandler;
-use crate::lichess::{GameClient, LichessClient};
+use crate::lichess::{GameClient, LichessClient, LichessConfig, Speed};
 use anyhow::Result;
 use futures::prelude::stream::*;
 use log::{debug, error, info};
@@ -33,8 +33,7 @@ pub struct Challenge {
     pub challenger: ChallengeUser,
     pub variant: Variant,
     pub rated: bool,
-    pub speed: String,
-    // TODO enum
+    pub speed: Speed,
     pub time_control: TimeControl,
     pub color: String, // TODO enum
 }
@@ -96,13 +95,15 @@ struct GameHandle {
 pub struct AccountEventHandler {
     in_progress_games: Mutex<HashMap<String, GameHandle>>,
     client: AccountClient,
+    config: LichessConfig,
 }
 
 impl AccountEventHandler {
-    pub fn new(client: AccountClient) -> Self {
+    pub fn new(client: AccountClient, config: LichessConfig) -> Self {
         Self {
             in_progress_games: Mutex::new(HashMap::with_capacity(10)),
             client,
+            config,
         }
     }
 
@@ -133,8 +134,9 @@ impl AccountEventHandler {
                 info!("Game started: {}", game.id);
                 let game_client = GameClient::new(self.client.base_client.clone(), game.id.clone());
                 let (cancellation_tx, cancellation_rx) = watch::channel(());
-                let mut engine_handler = EngineHandler::new(game_client, cancellation_rx);
-                let _ = tokio::spawn(async move { engine_handler.run().await });
+                let mut engine_handler =
+                    EngineHandler::new(game_client, cancellation_rx, self.config.clone());
+                tokio::spawn(async move { engine_handler.run().await });
                 let game_handle = GameHandle { cancellation_tx };
                 self.in_progress_games
                     .lock()
@@ -158,24 +160,85 @@ impl AccountEventHandler {
     }
 
     async fn should_accept_challenge(&self, challenge: &Challenge) -> Option<DeclineReason> {
-        if self.in_progress_games.lock().await.len() >= 1 {
+        let games_in_progress = self.in_progress_games.lock().await.len();
+        self.config
+            .challenges
+            .decline_reason(challenge, games_in_progress)
+    }
+}
+
+/// Which challenges get accepted, the others are declined with a reason Lichess can show.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChallengePolicy {
+    /// Lichess ids of who may challenge us, anyone when empty
+    pub allowed_challengers: Vec<String>,
+    /// Variant keys like `standard` or `chess960`
+    pub variants: Vec<String>,
+    pub speeds: Vec<Speed>,
+    pub rated: bool,
+    pub casual: bool,
+    pub max_concurrent_games: usize,
+}
+
+impl ChallengePolicy {
+    pub fn decline_reason(
+        &self,
+        challenge: &Challenge,
+        games_in_progress: usize,
+    ) -> Option<DeclineReason> {
+        if games_in_progress >= self.max_concurrent_games {
             info!("Too many in-progress games");
-            Some(DeclineReason::Generic)
-        } else if challenge.challenger.id != "dragnmn" {
+            Some(DeclineReason::Later)
+        } else if !self.allowed_challengers.is_empty()
+            && !self
+                .allowed_challengers
+                .iter()
+                .any(|id| id.eq_ignore_ascii_case(&challenge.challenger.id))
+        {
             info!("Got challenge by wrong account");
             Some(DeclineReason::Generic)
-        } else if challenge.variant.key != "standard" {
+        } else if !self.variants.contains(&challenge.variant.key) {
             Some(DeclineReason::Variant)
-        } else if challenge.rated {
+        } else if challenge.rated && !self.rated {
             Some(DeclineReason::Casual)
+        } else if !challenge.rated && !self.casual {
+            Some(DeclineReason::Rated)
         } else if challenge.time_control == TimeControl::Unlimited {
             Some(DeclineReason::TimeControl)
+        } else if !self.speeds.contains(&challenge.speed) {
+            // Speeds are ordered from fast to slow
+            if self.speeds.iter().all(|s| challenge.speed < *s) {
+                Some(DeclineReason::TooFast)
+            } else if self.speeds.iter().all(|s| challenge.speed > *s) {
+                Some(DeclineReason::TooSlow)
+            } else {
+                Some(DeclineReason::TimeControl)
+            }
         } else {
             None
         }
     }
 }
 
+impl Default for ChallengePolicy {
+    fn default() -> Self {
+        Self {
+            allowed_challengers: vec!["dragnmn".to_owned()],
+            variants: vec!["standard".to_owned()],
+            speeds: vec![
+                Speed::UltraBullet,
+                Speed::Bullet,
+                Speed::Blitz,
+                Speed::Rapid,
+                Speed::Classical,
+            ],
+            rated: false,
+            casual: true,
+            max_concurrent_games: 1,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AccountClient {
     base_client: LichessClient,
@@ -309,7 +372,7 @@ mod tests {
                     key: "standard".to_owned(),
                 },
                 rated: false,
-                speed: "correspondence".to_owned(),
+                speed: Speed::Correspondence,
                 time_control: TimeControl::Unlimited,
                 color: "random".to_owned(),
             },
@@ -359,4 +422,68 @@ mod tests {
 
         assert_eq!(result, expected);
     }
+
+    fn challenge(challenger: &str, speed: Speed, rated: bool) -> Challenge {
+        Challenge {
+            id: "abcdefgh".to_owned(),
+            challenger: ChallengeUser {
+                id: challenger.to_owned(),
+            },
+            variant: Variant {
+                key: "standard".to_owned(),
+            },
+            rated,
+            speed,
+            time_control: TimeControl::Clock {
+                limit: 180,
+                increment: 2,
+            },
+            color: "random".to_owned(),
+        }
+    }
+
+    #[test]
+    fn challenge_policy() {
+        let policy = ChallengePolicy::default();
+        assert_eq!(
+            policy.decline_reason(&challenge("Dragnmn", Speed::Blitz, false), 0),
+            None
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("dragnmn", Speed::Blitz, false), 1),
+            Some(DeclineReason::Later)
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("someone", Speed::Blitz, false), 0),
+            Some(DeclineReason::Generic)
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("dragnmn", Speed::Blitz, true), 0),
+            Some(DeclineReason::Casual)
+        );
+
+        let policy = ChallengePolicy {
+            allowed_challengers: vec![],
+            speeds: vec![Speed::Blitz, Speed::Rapid],
+            rated: true,
+            casual: false,
+            ..ChallengePolicy::default()
+        };
+        assert_eq!(
+            policy.decline_reason(&challenge("someone", Speed::Rapid, true), 0),
+            None
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("someone", Speed::Rapid, false), 0),
+            Some(DeclineReason::Rated)
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("someone", Speed::Bullet, true), 0),
+            Some(DeclineReason::TooFast)
+        );
+        assert_eq!(
+            policy.decline_reason(&challenge("someone", Speed::Classical, true), 0),
+            Some(DeclineReason::TooSlow)
+        );
+    }
 }
@@ -1,35 +1,53 @@
-use crate::lichess::{GameClient, GameStateEvent};
+use crate::lichess::{GameClient, GameStateEvent, LichessConfig};
 use log::{debug, error, info, warn};
 
 use crate::lichess::game::{MakeMove, State};
+use crate::search_log::{self, SearchContext};
 use anyhow::Result;
-use brain::{EngineHandle, EngineUpdate, RemainingTime, SearchConfiguration};
+use brain::{
+    EngineError, EngineHandle, EngineUpdate, MoveResult, RemainingTime, SearchConfiguration,
+};
 use futures::{pin_mut, StreamExt};
 use guts::{Color, Position};
 use itertools::Itertools;
 use std::str::FromStr;
 use std::time::Duration;
 use tokio::select;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 const MY_ID: &str = "chessatiel";
 
+struct PonderSearch {
+    expected_move: String,
+    updates: mpsc::UnboundedReceiver<EngineUpdate>,
+}
+
 pub struct EngineHandler {
     game_client: GameClient,
     engine: EngineHandle,
     my_color: Color,
     cancellation_rx: watch::Receiver<()>,
+    config: LichessConfig,
+    expected_reply: Option<String>,
+    ponder: Option<PonderSearch>,
 }
 
 impl EngineHandler {
-    pub(crate) fn new(game_client: GameClient, cancellation_rx: watch::Receiver<()>) -> Self {
+    pub(crate) fn new(
+        game_client: GameClient,
+        cancellation_rx: watch::Receiver<()>,
+        config: LichessConfig,
+    ) -> Self {
         let engine = EngineHandle::new(cancellation_rx.clone());
         Self {
             game_client,
             engine,
             cancellation_rx,
             my_color: Color::White,
+            config,
+            expected_reply: None,
+            ponder: None,
         }
     }
 
@@ -74,7 +92,16 @@ impl EngineHandler {
                     Color::Black
                 };
                 self.my_color = engine_color;
-                self.engine
+                self.stop_pondering().await;
+                if let Err(e) = self
+                    .engine
+                    .set_hash_size(self.config.hash_size_for(immutable_info.speed))
+                    .await
+                {
+                    error!("Could not resize the hash: {e}");
+                }
+                if let Err(e) = self
+                    .engine
                     .set_initial_values(
                         Position::from_str(&immutable_info.initial_fen).unwrap_or_else(|_| {
                             panic!(
@@ -84,29 +111,21 @@ impl EngineHandler {
                         }),
                         Self::split_moves(&state.moves),
                     )
-                    .await;
+                    .await
+                {
+                    error!("Lichess sent an illegal move: {e}");
+                }
                 if self.is_my_move().await {
-                    let stream = UnboundedReceiverStream::new(
-                        self.engine
-                            .go(self.build_configuration(true, &state))
-                            .await
-                            .unwrap(),
-                    )
-                    .filter_map(|update| async {
-                        match update {
-                            EngineUpdate::BestMove(m) => Some(m),
-                            _ => None,
-                        }
-                    });
-                    pin_mut!(stream);
-                    if let Some(chess_move) = stream
-                        .next()
+                    let updates = self
+                        .go(self.build_configuration(true, &state))
                         .await
-                        .and_then(|mr| mr.first_move().cloned())
-                        .map(|m| m.as_uci())
-                    {
-                        let make_move = MakeMove { chess_move };
-                        self.game_client.submit_move(&make_move).await.unwrap();
+                        .unwrap();
+                    if let Some(result) = Self::best_move(updates).await {
+                        if let Some(chess_move) = result.first_move().map(|m| m.as_uci()) {
+                            let make_move = MakeMove { chess_move };
+                            self.game_client.submit_move(&make_move).await.unwrap();
+                        }
+                        self.expected_reply = result.ponder_move().map(|m| m.as_uci());
                     }
                 }
             }
@@ -118,35 +137,44 @@ impl EngineHandler {
                     );
                     return;
                 };
-                self.engine.set_moves(Self::split_moves(&state.moves)).await;
+                let moves = Self::split_moves(&state.moves);
+                let updates = match self.ponder.take() {
+                    Some(ponder) if moves.last() == Some(&ponder.expected_move) => {
+                        debug!("Ponder hit on {}", ponder.expected_move);
+                        self.engine.ponder_hit().await;
+                        Some(ponder.updates)
+                    }
+                    ponder => {
+                        if ponder.is_some() {
+                            self.engine.stop().await;
+                        }
+                        if let Err(e) = self.engine.set_moves(moves.clone()).await {
+                            error!("Lichess sent an illegal move: {e}");
+                        }
+                        None
+                    }
+                };
                 if self.is_my_move().await {
-                    let stream = UnboundedReceiverStream::new(
-                        self.engine
+                    let updates = match updates {
+                        Some(updates) => updates,
+                        None => self
                             .go(self.build_configuration(false, &state))
                             .await
                             .unwrap(),
-                    )
-                    .filter_map(|update| async {
-                        match update {
-                            EngineUpdate::BestMove(m) => Some(m),
-                            update => {
-                                info!("Got an engine update: {update:?}");
-                                None
-                            }
+                    };
+                    if let Some(result) = Self::best_move(updates).await {
+                        if let Some(chess_move) = result.first_move().map(|m| m.as_uci()) {
+                            let make_move = MakeMove { chess_move };
+                            if !self.game_client.submit_move(&make_move).await.unwrap() {
+                                error!("Got a non-200 from Lichess when making a move");
+                                self.game_client.resign().await.unwrap();
+                            };
                         }
-                    });
-                    pin_mut!(stream);
-                    if let Some(chess_move) = stream
-                        .next()
-                        .await
-                        .and_then(|mr| mr.first_move().cloned())
-                        .map(|m| m.as_uci())
-                    {
-                        let make_move = MakeMove { chess_move };
-                        if !self.game_client.submit_move(&make_move).await.unwrap() {
-                            error!("Got a non-200 from Lichess when making a move");
-                            self.game_client.resign().await.unwrap();
-                        };
+                        self.expected_reply = result.ponder_move().map(|m| m.as_uci());
+                    }
+                } else if let Some(expected_move) = self.expected_reply.take() {
+                    if self.config.options.ponder {
+                        self.start_pondering(moves, expected_move, &state).await;
                     }
                 }
             }
@@ -156,6 +184,69 @@ impl EngineHandler {
         }
     }
 
+    async fn best_move(updates: mpsc::UnboundedReceiver<EngineUpdate>) -> Option<MoveResult> {
+        let stream = UnboundedReceiverStream::new(updates).filter_map(|update| async {
+            match update {
+                EngineUpdate::BestMove(m) => Some(m),
+                update => {
+                    info!("Got an engine update: {update:?}");
+                    None
+                }
+            }
+        });
+        pin_mut!(stream);
+        stream.next().await
+    }
+
+    async fn start_pondering(
+        &mut self,
+        mut moves: Vec<String>,
+        expected_move: String,
+        state: &State,
+    ) {
+        debug!("Pondering on {expected_move}");
+        moves.push(expected_move.clone());
+        if let Err(e) = self.engine.set_moves(moves).await {
+            error!("Can't ponder on {expected_move}: {e}");
+            return;
+        }
+        let config = SearchConfiguration {
+            ponder: true,
+            ..self.build_configuration(false, state)
+        };
+        match self.go(config).await {
+            Ok(updates) => {
+                self.ponder = Some(PonderSearch {
+                    expected_move,
+                    updates,
+                })
+            }
+            Err(e) => warn!("Could not start pondering: {e}"),
+        }
+    }
+
+    /// Starts a search that shows up in the search log under this game's id.
+    async fn go(
+        &self,
+        config: SearchConfiguration,
+    ) -> Result<mpsc::UnboundedReceiver<EngineUpdate>, EngineError> {
+        let position = self.engine.current_position().await;
+        let context = SearchContext::new(
+            self.game_client.game_id().to_owned(),
+            &position,
+            config.remaining_time.as_ref(),
+        );
+        let updates = self.engine.go(config).await?;
+        Ok(search_log::trace(context, updates))
+    }
+
+    async fn stop_pondering(&mut self) {
+        self.expected_reply = None;
+        if self.ponder.take().is_some() {
+            self.engine.stop().await;
+        }
+    }
+
     async fn is_my_move(&self) -> bool {
         self.my_color == self.engine.current_color().await
     }
@@ -168,10 +259,13 @@ impl EngineHandler {
             Some(RemainingTime::ForGame {
                 remaining: time.time,
                 increment: time.increment,
+                moves_to_go: None,
             })
         };
         SearchConfiguration {
             remaining_time,
+            move_overhead: Some(self.config.options.move_overhead),
+            evaluator: self.config.options.evaluator,
             ..SearchConfiguration::default()
         }
     }
@@ -27,11 +27,24 @@ pub enum GameStateEvent {
 #[serde(rename_all = "camelCase")]
 pub struct ImmutableInfo {
     pub clock: Clock,
+    pub speed: Speed,
     pub initial_fen: String,
     pub white: Player,
     pub black: Player,
 }
 
+/// Ordered from fast to slow.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Speed {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq)]
 pub struct Player {
     pub id: String,
@@ -94,6 +107,10 @@ impl GameClient {
         }
     }
 
+    pub fn game_id(&self) -> &str {
+        &self.game_id
+    }
+
     fn game_event_stream_url(&self) -> String {
         format!(
             "{}/api/bot/game/stream/{}",
@@ -217,6 +234,7 @@ mod tests {
                     initial: 1199999,
                     increment: 9999,
                 },
+                speed: Speed::Classical,
                 initial_fen: "startpos".to_owned(),
                 white: Player {
                     id: "lovlas".to_string(),
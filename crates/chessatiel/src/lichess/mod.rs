@@ -4,17 +4,76 @@ mod account;
 mod engine_handler;
 mod game;
 
+use crate::uci::options::{EngineAction, EngineOptions, OptionError};
 use anyhow::Result;
 use bytes::Bytes;
 use log::{debug, error};
 use reqwest::Client;
 use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 pub use crate::lichess::account::{
-    AccountClient, AccountEventHandler, Challenge, LichessEvent, TimeControl,
+    AccountClient, AccountEventHandler, Challenge, ChallengePolicy, DeclineReason, LichessEvent,
+    TimeControl,
 };
 
-pub use crate::lichess::game::{GameClient, GameStateEvent};
+pub use crate::lichess::game::{GameClient, GameStateEvent, Speed};
+
+#[derive(Debug, Clone)]
+pub struct LichessConfig {
+    /// Transposition table size in MiB for each game speed
+    pub hash_size_mib: HashMap<Speed, usize>,
+    pub default_hash_size_mib: usize,
+    /// The same options a GUI can set, `Ponder` makes the engine think on the opponent's time
+    pub options: EngineOptions,
+    pub base_url: String,
+    /// Only read when the `LICHESS_API_TOKEN` environment variable isn't set
+    pub token_path: PathBuf,
+    pub challenges: ChallengePolicy,
+}
+
+impl LichessConfig {
+    pub fn hash_size_for(&self, speed: Speed) -> usize {
+        self.hash_size_mib
+            .get(&speed)
+            .copied()
+            .unwrap_or(self.default_hash_size_mib)
+    }
+
+    /// Sets a UCI option for every game, `Hash` replaces the sizes for every speed.
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), OptionError> {
+        if let Some(EngineAction::ResizeHash(size_mib)) = self.options.set(name, value)? {
+            self.hash_size_mib.clear();
+            self.default_hash_size_mib = size_mib;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LichessConfig {
+    fn default() -> Self {
+        Self {
+            hash_size_mib: HashMap::from([
+                (Speed::UltraBullet, 8),
+                (Speed::Bullet, 16),
+                (Speed::Blitz, 32),
+                (Speed::Rapid, 64),
+                (Speed::Classical, 128),
+            ]),
+            default_hash_size_mib: 128,
+            options: EngineOptions {
+                ponder: true,
+                move_overhead: Duration::from_millis(300),
+                ..EngineOptions::default()
+            },
+            base_url: "https://lichess.org".to_owned(),
+            token_path: PathBuf::from("lichess-api-token"),
+            challenges: ChallengePolicy::default(),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct LichessClient {
@@ -1,13 +1,19 @@
-use chessatiel::lichess::{AccountClient, AccountEventHandler, LichessClient};
-use clap::Parser;
+use brain::bench::{bench, BENCH_DEPTH};
+use chessatiel::config::ConfigFile;
+use chessatiel::lichess::{AccountClient, AccountEventHandler, LichessClient, LichessConfig};
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::select;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use chessatiel::profiling::{run_profile, ProfileMode};
+use chessatiel::search_log;
+use chessatiel::uci::options::EngineOptions;
 use chessatiel::uci::uci;
+use chessatiel::xboard::xboard;
 use futures::prelude::stream::*;
 use log::{debug, error};
 use log::{info, logger, LevelFilter};
@@ -20,19 +26,56 @@ use tokio::task::JoinHandle;
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
 
-const LICHESS_API_TOKEN_PATH: &str = "lichess-api-token";
-
+/// Flags take precedence over the values in the config file.
 #[derive(Parser, Debug)]
 #[clap()]
 struct Args {
+    /// TOML settings file, or JSON when the name ends in `.json`
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+
     #[clap(short, long)]
     lichess: bool,
 
+    /// Speak the xboard protocol (CECP v2) instead of UCI
+    #[clap(short, long, conflicts_with = "lichess")]
+    xboard: bool,
+
     #[clap(short, long, value_enum)]
     profile_mode: Option<ProfileMode>,
 
     #[clap(short, long)]
     debug: bool,
+
+    /// Append a JSON line per finished iteration and per best move to this file
+    #[clap(long)]
+    search_log: Option<PathBuf>,
+
+    /// Transposition table size in MiB
+    #[clap(long)]
+    hash: Option<usize>,
+
+    #[clap(long)]
+    move_overhead_ms: Option<u64>,
+
+    #[clap(long)]
+    lichess_base_url: Option<String>,
+
+    /// Where to read the Lichess API token when `LICHESS_API_TOKEN` isn't set
+    #[clap(long)]
+    lichess_token_path: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Searches a fixed set of positions, the node count is a signature of the search
+    Bench {
+        #[clap(long, default_value_t = BENCH_DEPTH)]
+        depth: u16,
+    },
 }
 
 #[tokio::main]
@@ -51,8 +94,18 @@ async fn main() -> Result<()> {
         );
         return Ok(());
     }
+    if let Some(Command::Bench { depth }) = args.command {
+        println!("{}", bench(depth));
+        return Ok(());
+    }
 
-    let level_filter = if args.debug {
+    let mut config = match &args.config {
+        Some(path) => ConfigFile::from_file(path)?,
+        None => ConfigFile::default(),
+    };
+    args.override_config(&mut config);
+
+    let level_filter = if config.logging.debug == Some(true) {
         LevelFilter::Debug
     } else {
         LevelFilter::Info
@@ -67,16 +120,47 @@ async fn main() -> Result<()> {
 
     periodically_flush_logger(Duration::from_secs(1));
 
+    if let Some(path) = &config.logging.search_log {
+        search_log::init(path)?;
+        info!("Logging searches to {}", path.display());
+    }
+
     if args.lichess {
-        lichess().await
+        lichess(config.lichess_config(LichessConfig::default())?).await
+    } else if args.xboard {
+        xboard(config.engine_options(EngineOptions::default())?).await;
+        Ok(())
     } else {
-        uci().await;
+        uci(config.engine_options(EngineOptions::default())?).await;
         Ok(())
     }
 }
 
-async fn lichess() -> Result<()> {
-    let token = get_lichess_token().await?;
+impl Args {
+    fn override_config(&self, config: &mut ConfigFile) {
+        if self.debug {
+            config.logging.debug = Some(true);
+        }
+        if let Some(search_log) = &self.search_log {
+            config.logging.search_log = Some(search_log.clone());
+        }
+        if let Some(hash) = self.hash {
+            config.engine.hash = Some(hash);
+        }
+        if let Some(move_overhead_ms) = self.move_overhead_ms {
+            config.engine.move_overhead_ms = Some(move_overhead_ms);
+        }
+        if let Some(base_url) = &self.lichess_base_url {
+            config.lichess.base_url = Some(base_url.clone());
+        }
+        if let Some(token_path) = &self.lichess_token_path {
+            config.lichess.token_path = Some(token_path.clone());
+        }
+    }
+}
+
+async fn lichess(config: LichessConfig) -> Result<()> {
+    let token = get_lichess_token(&config.token_path).await?;
 
     let auth_value = format!("Bearer {}", token);
 
@@ -88,10 +172,10 @@ async fn lichess() -> Result<()> {
         .default_headers(headers)
         .build()?;
 
-    let client = LichessClient::new(client, "https://lichess.org".to_owned());
+    let client = LichessClient::new(client, config.base_url.clone());
 
     let account_client = AccountClient::new(client.clone());
-    let event_handler = AccountEventHandler::new(account_client.clone());
+    let event_handler = AccountEventHandler::new(account_client.clone(), config);
     let account_stream = account_client.get_account_stream().await?;
 
     info!("Ready for events!");
@@ -119,7 +203,7 @@ async fn lichess() -> Result<()> {
     Ok(())
 }
 
-async fn get_lichess_token() -> Result<String> {
+async fn get_lichess_token(path: &Path) -> Result<String> {
     match std::env::var("LICHESS_API_TOKEN") {
         Ok(var) => {
             info!("Got token from env var!");
@@ -130,9 +214,9 @@ async fn get_lichess_token() -> Result<String> {
         }
     }
     let mut buf = String::with_capacity(512);
-    File::open(LICHESS_API_TOKEN_PATH)
+    File::open(path)
         .await
-        .map_err(|_| anyhow::Error::msg("Could not find lichess API token file"))?
+        .map_err(|_| anyhow!("Could not find lichess API token file {}", path.display()))?
         .read_to_string(&mut buf)
         .await?;
 
@@ -43,7 +43,10 @@ async fn search_depth(depth: u16) {
     let mut tt = TranspositionTable::default();
     let stats = StatisticsHolder::new();
     let (_stop_tx, stop_rx) = watch::channel(());
-    let config = SearcherConfig { depth: Some(depth) };
+    let config = SearcherConfig {
+        depth: Some(depth),
+        ..SearcherConfig::default()
+    };
     let (result_tx, mut result_rx) = mpsc::unbounded_channel();
     let _search_task = std::thread::spawn(move || {
         let mut searcher = Searcher::new(history, position, stop_rx, config, &stats, &mut tt);
@@ -0,0 +1,124 @@
+//! The GUI side of a conversation with an engine running on in-memory pipes, for the protocol
+//! tests.
+
+use crate::uci::options::EngineOptions;
+use crate::uci::run_uci;
+use crate::xboard::run_xboard;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+pub const PROMPT: Duration = Duration::from_millis(500);
+pub const PATIENT: Duration = Duration::from_secs(10);
+
+pub trait Protocol {
+    /// What a GUI sends first.
+    const GREETING: &'static [&'static str];
+    /// The start of the engine's last line in answer to the greeting.
+    const GREETING_ANSWER: &'static str;
+
+    fn spawn(input: BufReader<DuplexStream>, output: DuplexStream) -> JoinHandle<()>;
+}
+
+pub struct Uci;
+
+impl Protocol for Uci {
+    const GREETING: &'static [&'static str] = &["uci"];
+    const GREETING_ANSWER: &'static str = "uciok";
+
+    fn spawn(input: BufReader<DuplexStream>, output: DuplexStream) -> JoinHandle<()> {
+        tokio::spawn(run_uci(input, output, EngineOptions::default()))
+    }
+}
+
+pub struct Xboard;
+
+impl Protocol for Xboard {
+    const GREETING: &'static [&'static str] = &["xboard", "protover 2"];
+    const GREETING_ANSWER: &'static str = "feature";
+
+    fn spawn(input: BufReader<DuplexStream>, output: DuplexStream) -> JoinHandle<()> {
+        tokio::spawn(run_xboard(input, output, EngineOptions::default()))
+    }
+}
+
+pub struct ScriptedGui {
+    to_engine: Option<DuplexStream>,
+    from_engine: Lines<BufReader<DuplexStream>>,
+    engine: JoinHandle<()>,
+    /// The engine's answer to the greeting.
+    pub greeting: Vec<String>,
+}
+
+impl ScriptedGui {
+    /// Starts an engine speaking `P` and greets it.
+    pub async fn start<P: Protocol>() -> Self {
+        let (to_engine, engine_input) = tokio::io::duplex(BUFFER_SIZE);
+        let (engine_output, from_engine) = tokio::io::duplex(BUFFER_SIZE);
+        let mut gui = Self {
+            to_engine: Some(to_engine),
+            from_engine: BufReader::new(from_engine).lines(),
+            engine: P::spawn(BufReader::new(engine_input), engine_output),
+            greeting: Vec::new(),
+        };
+        for line in P::GREETING {
+            gui.send(line).await;
+        }
+        gui.greeting = gui.expect(P::GREETING_ANSWER, PATIENT).await;
+        gui
+    }
+
+    pub async fn send(&mut self, line: &str) {
+        let to_engine = self.to_engine.as_mut().expect("Input was closed");
+        to_engine
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    /// Every line up to and including the first that starts with `prefix`.
+    pub async fn expect(&mut self, prefix: &str, within: Duration) -> Vec<String> {
+        let mut lines = Vec::new();
+        let result = timeout(within, async {
+            while let Some(line) = self.from_engine.next_line().await.unwrap() {
+                let found = line.starts_with(prefix);
+                lines.push(line);
+                if found {
+                    return;
+                }
+            }
+            panic!("Engine stopped talking while waiting for '{prefix}', got {lines:?}");
+        })
+        .await;
+        assert!(
+            result.is_ok(),
+            "No '{prefix}' within {within:?}, got {lines:?}"
+        );
+        lines
+    }
+
+    pub async fn expect_none(&mut self, prefix: &str, during: Duration) {
+        let _ = timeout(during, async {
+            while let Some(line) = self.from_engine.next_line().await.unwrap() {
+                assert!(!line.starts_with(prefix), "Unexpected '{line}'");
+            }
+        })
+        .await;
+    }
+
+    pub fn close_input(&mut self) {
+        self.to_engine = None;
+    }
+
+    pub async fn expect_exit(self, within: Duration) {
+        let result = timeout(within, self.engine).await;
+        assert!(result.is_ok(), "Engine still running after {within:?}");
+    }
+
+    pub async fn quit(mut self) {
+        self.send("quit").await;
+        self.expect_exit(PATIENT).await;
+    }
+}
@@ -0,0 +1,247 @@
+use crate::uci::Score;
+use brain::{EngineUpdate, RemainingTime};
+use guts::{Color, Position};
+use log::error;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+static SEARCH_LOG: OnceLock<Arc<SearchLog>> = OnceLock::new();
+
+/// Starts appending a JSON line for every finished iteration and every best move to `path`.
+/// Without it searches aren't traced at all.
+pub fn init(path: &Path) -> io::Result<()> {
+    let log = SearchLog::open(path)?;
+    if SEARCH_LOG.set(Arc::new(log)).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "The search log was already initialized",
+        ));
+    }
+    Ok(())
+}
+
+/// Logs the updates of a search on their way to `updates`' reader, if the search log is enabled.
+pub fn trace(
+    context: SearchContext,
+    updates: UnboundedReceiver<EngineUpdate>,
+) -> UnboundedReceiver<EngineUpdate> {
+    match SEARCH_LOG.get() {
+        Some(log) => log.clone().trace(context, updates),
+        None => updates,
+    }
+}
+
+/// Names the `number`th game of this process, unique among engines sharing a log.
+pub fn local_game_id(number: u32) -> String {
+    format!("{}-{number}", std::process::id())
+}
+
+/// What a search was for, so the lines of one game can be picked out afterwards.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SearchContext {
+    pub game: String,
+    pub ply: u16,
+    /// The time we had left, for the game or for this move
+    pub clock: Option<Duration>,
+}
+
+impl SearchContext {
+    pub fn new(game: String, position: &Position, remaining_time: Option<&RemainingTime>) -> Self {
+        let ply = 2 * position.fullmove_number().saturating_sub(1)
+            + u16::from(position.active_color() == Color::Black);
+        let clock = remaining_time.map(|remaining_time| match remaining_time {
+            RemainingTime::ForGame { remaining, .. } => *remaining,
+            RemainingTime::ForMove(time) => *time,
+        });
+        Self { game, ply, clock }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record<'a> {
+    Iteration {
+        game: &'a str,
+        ply: u16,
+        depth: u16,
+        seldepth: u16,
+        score: Score,
+        nodes: u64,
+        nps: u64,
+        time_ms: u64,
+        hashfull: u64,
+        pv: Vec<String>,
+    },
+    BestMove {
+        game: &'a str,
+        ply: u16,
+        best_move: Option<String>,
+        ponder_move: Option<String>,
+        depth: u16,
+        score: Score,
+        /// From the start of the search until the move was known
+        time_ms: u64,
+        clock_ms: Option<u64>,
+    },
+}
+
+struct SearchLog {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl SearchLog {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    fn trace(
+        self: Arc<Self>,
+        context: SearchContext,
+        mut updates: UnboundedReceiver<EngineUpdate>,
+    ) -> UnboundedReceiver<EngineUpdate> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        tokio::spawn(async move {
+            while let Some(update) = updates.recv().await {
+                if let Err(e) = self.record(&context, &update, start.elapsed()) {
+                    error!("Could not write to the search log: {e}");
+                }
+                if tx.send(update).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    fn record(
+        &self,
+        context: &SearchContext,
+        update: &EngineUpdate,
+        elapsed: Duration,
+    ) -> io::Result<()> {
+        let game = context.game.as_str();
+        let ply = context.ply;
+        let record = match update {
+            EngineUpdate::MultiPv {
+                lines,
+                nodes,
+                nps,
+                time,
+                hashfull,
+            } => match lines.first() {
+                Some(best) => Record::Iteration {
+                    game,
+                    ply,
+                    depth: best.depth(),
+                    seldepth: best.seldepth(),
+                    score: best.score().into(),
+                    nodes: *nodes,
+                    nps: *nps,
+                    time_ms: time.as_millis() as u64,
+                    hashfull: *hashfull,
+                    pv: best.pv().map(|m| m.as_uci()).collect(),
+                },
+                None => return Ok(()),
+            },
+            EngineUpdate::BestMove(result) => Record::BestMove {
+                game,
+                ply,
+                best_move: result.first_move().map(|m| m.as_uci()),
+                ponder_move: result.ponder_move().map(|m| m.as_uci()),
+                depth: result.depth(),
+                score: result.score().into(),
+                time_ms: elapsed.as_millis() as u64,
+                clock_ms: context.clock.map(|clock| clock.as_millis() as u64),
+            },
+            _ => return Ok(()),
+        };
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::to_writer(&mut *writer, &record)?;
+        writeln!(writer)?;
+        // Games can end with the process being killed, so every search is complete on disk
+        if matches!(record, Record::BestMove { .. }) {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use brain::EngineHandle;
+    use brain::SearchConfiguration;
+    use std::str::FromStr;
+    use tokio::sync::watch;
+
+    #[test]
+    fn context_counts_plies() {
+        let position =
+            Position::from_str("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2")
+                .unwrap();
+        let context = SearchContext::new(
+            "abc".to_owned(),
+            &position,
+            Some(&RemainingTime::ForMove(Duration::from_secs(1))),
+        );
+        assert_eq!(context.ply, 2);
+        assert_eq!(context.clock, Some(Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn searches_are_logged_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("search-log-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = Arc::new(SearchLog::open(&path).unwrap());
+
+        let (_cancellation_tx, cancellation_rx) = watch::channel(());
+        let engine = EngineHandle::new(cancellation_rx);
+        let updates = engine
+            .go(SearchConfiguration {
+                depth: Some(3),
+                ..SearchConfiguration::default()
+            })
+            .await
+            .unwrap();
+        let context = SearchContext::new("game1".to_owned(), &Position::default(), None);
+        let mut updates = log.trace(context, updates);
+        let mut forwarded = 0;
+        while let Some(update) = updates.recv().await {
+            forwarded += 1;
+            if matches!(update, EngineUpdate::BestMove(_)) {
+                break;
+            }
+        }
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let records = lines
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert!(forwarded >= records.len());
+        let depths = records
+            .iter()
+            .filter(|r| r["event"] == "iteration")
+            .map(|r| r["depth"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(depths, [1, 2, 3]);
+        let best_move = records.last().unwrap();
+        assert_eq!(best_move["event"], "best_move");
+        assert_eq!(best_move["game"], "game1");
+        assert_eq!(best_move["ply"], 0);
+        assert!(best_move["best_move"].is_string());
+        assert!(best_move["score"]["cp"].is_i64());
+    }
+}
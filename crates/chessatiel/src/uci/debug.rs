@@ -0,0 +1,94 @@
+use brain::evaluator::main_evaluator::EvalTrace;
+use guts::{Color, File, MoveGenerator, Position, Rank, Square};
+use itertools::Itertools;
+
+const SEPARATOR: &str = " +---+---+---+---+---+---+---+---+";
+
+/// The board as seen from white, followed by the FEN and the Zobrist hash.
+pub fn board(position: &Position) -> Vec<String> {
+    let mut lines = vec![SEPARATOR.to_owned()];
+    for rank in Rank::ALL.into_iter().rev() {
+        let squares = File::ALL
+            .into_iter()
+            .map(
+                |file| match position.board().piece_and_color_at(Square::new(file, rank)) {
+                    Some((piece, Color::White)) => piece.to_string(),
+                    Some((piece, Color::Black)) => piece.to_string().to_ascii_lowercase(),
+                    None => " ".to_owned(),
+                },
+            )
+            .join(" | ");
+        lines.push(format!(" | {squares} | {rank}"));
+        lines.push(SEPARATOR.to_owned());
+    }
+    lines.push(format!("   {}", File::ALL.iter().join("   ")));
+    lines.push(String::new());
+    lines.push(format!("Fen: {position}"));
+    lines.push(format!("Key: {:016X}", position.hash().0));
+    lines
+}
+
+/// One line per term with its count and its midgame, endgame and tapered score.
+pub fn eval(trace: &EvalTrace) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<24} {:>6} {:>8} {:>8} {:>8}",
+        "Term", "Count", "MG", "EG", "Score"
+    )];
+    for t in &trace.terms {
+        lines.push(format!(
+            "{:<24} {:>6} {:>8} {:>8} {:>8}",
+            format!("{:?}", t.term),
+            t.count,
+            t.midgame,
+            t.endgame,
+            t.score
+        ));
+    }
+    lines.push(format!("{:<24} {:>33}", "PST", trace.pst));
+    lines.push(String::new());
+    lines.push(format!("Phase: {}", trace.phase));
+    lines.push(format!("Total evaluation: {} (white side)", trace.total));
+    lines
+}
+
+/// The leaf count below every legal move, like `divide`, followed by the total.
+pub fn perft(move_generator: &MoveGenerator, position: &Position, depth: usize) -> Vec<String> {
+    let mut position = position.clone();
+    let divided = move_generator.divide(&mut position, depth.max(1));
+    let total: usize = divided.iter().map(|(_, nodes)| nodes).sum();
+    divided
+        .into_iter()
+        .map(|(m, nodes)| format!("{}: {nodes}", m.as_uci()))
+        .chain([String::new(), format!("Nodes searched: {total}")])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn board_diagram() {
+        let lines = board(&Position::default());
+        assert_eq!(lines[1], " | r | n | b | q | k | b | n | r | 8");
+        assert_eq!(lines[5], " |   |   |   |   |   |   |   |   | 6");
+        assert_eq!(lines[17], "   a   b   c   d   e   f   g   h");
+        assert_eq!(
+            lines[19],
+            "Fen: rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn perft_divides_by_move() {
+        let position = Position::from_str(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let lines = perft(&MoveGenerator::new(), &position, 2);
+        assert_eq!(lines.len(), 48 + 2);
+        assert!(lines.contains(&"e1g1: 43".to_owned()));
+        assert_eq!(lines.last().unwrap(), "Nodes searched: 2039");
+    }
+}
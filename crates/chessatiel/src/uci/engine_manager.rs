@@ -1,7 +1,13 @@
-use crate::uci::protocol::{GoPayload, IncomingCommand, InfoPayload, OutgoingCommand};
+use crate::search_log::{self, SearchContext};
+use crate::uci::debug;
+use crate::uci::options::{EngineAction, EngineOptions, OPTIONS};
+use crate::uci::protocol::{GoPayload, IncomingCommand, InfoPayload, OutgoingCommand, Score};
+use brain::bench::{self, BENCH_DEPTH};
+use brain::evaluator::MainEvaluator;
+use brain::transposition_table::TranspositionTable;
 use brain::{EngineHandle, EngineUpdate, RemainingTime, SearchConfiguration};
 use futures::StreamExt;
-use guts::Color;
+use guts::{Color, MoveGenerator};
 use log::debug;
 use std::time::Duration;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
@@ -13,12 +19,16 @@ pub struct EngineManager {
     tx: UnboundedSender<OutgoingCommand>,
     cancellation_tx: watch::Sender<()>,
     engine_handle: EngineHandle,
+    options: EngineOptions,
+    /// Counts `ucinewgame`, to tell games apart in the search log
+    game: u32,
 }
 
 impl EngineManager {
     pub fn new(
         rx: UnboundedReceiver<IncomingCommand>,
         tx: UnboundedSender<OutgoingCommand>,
+        options: EngineOptions,
     ) -> Self {
         let (cancellation_tx, cancellation_rx) = watch::channel(());
         let engine_handle = EngineHandle::new(cancellation_rx);
@@ -27,10 +37,18 @@ impl EngineManager {
             tx,
             cancellation_tx,
             engine_handle,
+            options,
+            game: 0,
         }
     }
 
     pub async fn run(mut self) {
+        if self.options.hash_size_mib != TranspositionTable::DEFAULT_SIZE_MIB {
+            let _ = self
+                .engine_handle
+                .set_hash_size(self.options.hash_size_mib)
+                .await;
+        }
         self.tx
             .send(OutgoingCommand::Info(InfoPayload {
                 string: Some("Ready for commands".to_string()),
@@ -50,6 +68,11 @@ impl EngineManager {
                             "Tim E (https://lichess.org/@/Dragnmn)",
                         ))
                         .unwrap();
+                    for option in OPTIONS.iter() {
+                        self.tx
+                            .send(OutgoingCommand::Option(option.to_string()))
+                            .unwrap();
+                    }
                     self.tx.send(OutgoingCommand::UciOk).unwrap();
                 }
                 IncomingCommand::Debug(_) => {}
@@ -57,62 +80,34 @@ impl EngineManager {
                     self.tx.send(OutgoingCommand::ReadyOk).unwrap();
                 }
                 IncomingCommand::UciNewGame => {
-                    let _ = self.cancellation_tx.send(());
-                    let (cancellation_tx, cancellation_rx) = watch::channel(());
-                    let engine_handle = EngineHandle::new(cancellation_rx);
-                    self.cancellation_tx = cancellation_tx;
-                    self.engine_handle = engine_handle;
+                    self.game += 1;
+                    let _ = self.engine_handle.stop().await;
+                    let _ = self.engine_handle.clear_hash().await;
                 }
+                IncomingCommand::SetOption(name, value) => self.set_option(&name, value).await,
                 IncomingCommand::Position(pos, moves) => {
-                    self.engine_handle.set_initial_values(pos, moves).await
+                    if let Err(e) = self.engine_handle.set_initial_values(pos, moves).await {
+                        let _ = self.tx.send(OutgoingCommand::Info(InfoPayload {
+                            string: Some(format!("Ignored the position command: {e}")),
+                            ..InfoPayload::default()
+                        }));
+                    }
                 }
                 IncomingCommand::Go(go_payload) => {
                     let tx = self.tx.clone();
-                    let current_color = self.engine_handle.current_color().await;
-                    match self
-                        .engine_handle
-                        .go(self.build_configuration(go_payload, current_color))
-                        .await
-                    {
+                    let position = self.engine_handle.current_position().await;
+                    let config = self.build_configuration(go_payload, position.active_color());
+                    let context = SearchContext::new(
+                        search_log::local_game_id(self.game),
+                        &position,
+                        config.remaining_time.as_ref(),
+                    );
+                    match self.engine_handle.go(config).await {
                         Ok(updates_rx) => {
+                            let updates_rx = search_log::trace(context, updates_rx);
                             tokio::task::spawn(async move {
                                 UnboundedReceiverStream::new(updates_rx)
-                                    .for_each(|update| async {
-                                        match update {
-                                            EngineUpdate::BestMove(m) => {
-                                                if let Some(m) = m.first_move() {
-                                                    tx.send(OutgoingCommand::BestMove(m.as_uci()))
-                                                        .unwrap()
-                                                } else {
-                                                    tx.send(OutgoingCommand::Info(InfoPayload {
-                                                        string: Some(
-                                                            "Did not find best move!".to_string(),
-                                                        ),
-                                                        ..InfoPayload::default()
-                                                    }))
-                                                    .unwrap()
-                                                }
-                                            }
-                                            EngineUpdate::Info {
-                                                nps,
-                                                depth,
-                                                nodes,
-                                                tt_hits,
-                                                score,
-                                            } => {
-                                                let _ =
-                                                    tx.send(OutgoingCommand::Info(InfoPayload {
-                                                        nps,
-                                                        depth,
-                                                        nodes,
-                                                        tt_hits,
-                                                        score,
-                                                        ..InfoPayload::default()
-                                                    }));
-                                            }
-                                            _ => (),
-                                        }
-                                    })
+                                    .for_each(|update| async { send_update(&tx, update) })
                                     .await;
                             });
                         }
@@ -126,26 +121,86 @@ impl EngineManager {
                         }
                     };
                 }
+                IncomingCommand::PonderHit => {
+                    let _ = self.engine_handle.ponder_hit().await;
+                }
                 IncomingCommand::Stop => {
                     let _ = self.engine_handle.stop().await;
                 }
-                IncomingCommand::Quit => break,
+                IncomingCommand::Quit => {
+                    let _ = self.cancellation_tx.send(());
+                    break;
+                }
+                IncomingCommand::Board => {
+                    let position = self.engine_handle.current_position().await;
+                    send_lines(&self.tx, debug::board(&position));
+                }
+                IncomingCommand::Eval => {
+                    let position = self.engine_handle.current_position().await;
+                    let trace = MainEvaluator::new().trace(&position);
+                    send_lines(&self.tx, debug::eval(&trace));
+                }
+                // Perft and bench use their own move generator and transposition table on a
+                // separate thread, a running search carries on undisturbed
+                IncomingCommand::Perft(depth) => {
+                    let position = self.engine_handle.current_position().await;
+                    let tx = self.tx.clone();
+                    tokio::task::spawn_blocking(move || {
+                        send_lines(&tx, debug::perft(&MoveGenerator::new(), &position, depth))
+                    });
+                }
+                IncomingCommand::Bench(depth) => {
+                    let tx = self.tx.clone();
+                    let depth = depth.unwrap_or(BENCH_DEPTH);
+                    tokio::task::spawn_blocking(move || {
+                        let result = bench::bench(depth).to_string();
+                        send_lines(&tx, result.lines().map(str::to_owned).collect())
+                    });
+                }
+            }
+        }
+    }
+
+    /// Options the engine refuses, like resizing the hash during a search, keep their old value.
+    async fn set_option(&mut self, name: &str, value: Option<String>) {
+        let previous = self.options.clone();
+        let applied = match self.options.set(name, value.as_deref()) {
+            Ok(Some(EngineAction::ResizeHash(size_mib))) => {
+                self.engine_handle.set_hash_size(size_mib).await
             }
+            Ok(Some(EngineAction::ClearHash)) => self.engine_handle.clear_hash().await,
+            Ok(None) => Ok(()),
+            Err(e) => return self.send_info_string(e.to_string()),
+        };
+        if let Err(e) = applied {
+            self.options = previous;
+            self.send_info_string(format!("Ignored option {name}: {e}"));
         }
     }
 
+    fn send_info_string(&self, string: String) {
+        let _ = self.tx.send(OutgoingCommand::Info(InfoPayload {
+            string: Some(string),
+            ..InfoPayload::default()
+        }));
+    }
+
     fn build_configuration(&self, go_payload: GoPayload, color: Color) -> SearchConfiguration {
-        let remaining_time = if let Some(movetime) = go_payload.move_time {
+        let remaining_time = if go_payload.infinite {
+            None
+        } else if let Some(movetime) = go_payload.move_time {
             Some(RemainingTime::ForMove(movetime))
         } else {
             match color {
                 Color::White => go_payload.wtime.map(|d| RemainingTime::ForGame {
                     remaining: d,
                     increment: go_payload.winc.unwrap_or(Duration::from_secs(0)),
+                    moves_to_go: go_payload.moves_to_go,
                 }),
                 Color::Black => go_payload.btime.map(|d| RemainingTime::ForGame {
                     remaining: d,
                     increment: go_payload.binc.unwrap_or(Duration::from_secs(0)),
+                    moves_to_go: go_payload.moves_to_go,
                 }),
             }
         };
@@ -153,6 +208,235 @@ impl EngineManager {
         SearchConfiguration {
             depth: go_payload.depth,
             remaining_time,
+            ponder: go_payload.ponder,
+            nodes: go_payload.nodes,
+            mate: go_payload.mate,
+            infinite: go_payload.infinite,
+            search_moves: go_payload.search_moves,
+            multi_pv: self.options.multi_pv,
+            move_overhead: Some(self.options.move_overhead),
+            evaluator: self.options.evaluator,
+        }
+    }
+}
+
+fn send_lines(tx: &UnboundedSender<OutgoingCommand>, lines: Vec<String>) {
+    for line in lines {
+        let _ = tx.send(OutgoingCommand::Text(line));
+    }
+}
+
+fn send_update(tx: &UnboundedSender<OutgoingCommand>, update: EngineUpdate) {
+    match update {
+        // Only a position without legal moves has no best move, the GUI still needs an answer
+        EngineUpdate::BestMove(m) => tx
+            .send(OutgoingCommand::BestMove(
+                m.first_move()
+                    .map_or_else(|| "0000".to_owned(), |b| b.as_uci()),
+                m.ponder_move().map(|p| p.as_uci()),
+            ))
+            .unwrap(),
+        EngineUpdate::MultiPv {
+            lines,
+            nodes,
+            nps,
+            time,
+            hashfull,
+        } => {
+            for (k, line) in lines.iter().enumerate() {
+                let _ = tx.send(OutgoingCommand::Info(InfoPayload {
+                    depth: Some(line.depth() as u64),
+                    seldepth: Some(line.seldepth() as u64),
+                    multipv: Some(k + 1),
+                    score: Some(Score::from(line.score())),
+                    nodes: Some(nodes),
+                    nps: Some(nps),
+                    time: Some(time),
+                    hashfull: Some(hashfull),
+                    pv: Some(line.pv().map(|m| m.as_uci()).collect()),
+                    ..InfoPayload::default()
+                }));
+            }
+        }
+        EngineUpdate::Info {
+            nps,
+            depth,
+            nodes,
+            tt_hits,
+            time,
+            hashfull,
+            current_move,
+            current_move_number,
+        } => {
+            let _ = tx.send(OutgoingCommand::Info(InfoPayload {
+                nps,
+                depth,
+                nodes,
+                tt_hits,
+                time,
+                hashfull,
+                currmove: current_move.map(|m| m.as_uci()),
+                currmovenumber: current_move_number,
+                ..InfoPayload::default()
+            }));
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::protocol::UciParser;
+    use quickcheck::{Arbitrary, Gen, QuickCheck};
+    use tokio::sync::mpsc;
+
+    const WORDS: [&str; 14] = [
+        "position", "startpos", "fen", "moves", "e2e4", "e7e5", "g1f3", "e1g1", "a7a8q", "h2h1n",
+        "0000", "w", "-", "KQkq",
+    ];
+
+    /// A `position` command built from UCI words and made-up FEN fields, mostly not quite valid.
+    #[derive(Debug, Clone)]
+    struct PositionLine(String);
+
+    impl Arbitrary for PositionLine {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let mut words = vec!["position".to_owned()];
+            if bool::arbitrary(g) {
+                words.push("fen".to_owned());
+                // Mostly eight full ranks, with or without kings
+                let rank_count = *g.choose(&[8, 8, 8, 7, 9]).unwrap();
+                let ranks = (0..rank_count)
+                    .map(|_| arbitrary_rank(g))
+                    .collect::<Vec<_>>();
+                words.push(ranks.join("/"));
+                words.push(g.choose(&["w", "b", "-"]).unwrap().to_string());
+                words.push(
+                    g.choose(&["KQkq", "Kq", "-", "KQkqKQ"])
+                        .unwrap()
+                        .to_string(),
+                );
+                words.push(g.choose(&["-", "-", "e3", "d6", "a1"]).unwrap().to_string());
+                words.push((u8::arbitrary(g) % 120).to_string());
+                words.push((u16::arbitrary(g) % 300).to_string());
+            } else {
+                words.push("startpos".to_owned());
+            }
+            for _ in 0..u8::arbitrary(g) % 8 {
+                let word = if bool::arbitrary(g) {
+                    g.choose(&WORDS).unwrap().to_string()
+                } else {
+                    String::arbitrary(g)
+                };
+                words.push(word);
+            }
+            Self(words.join(" "))
+        }
+    }
+
+    fn arbitrary_rank(g: &mut Gen) -> String {
+        let mut rank = String::new();
+        let mut empties = 0;
+        for _ in 0..*g.choose(&[8, 8, 8, 7, 9]).unwrap() {
+            match g.choose(b"rnbqkpRNBQKP........") {
+                Some(b'.') => empties += 1,
+                piece => {
+                    if empties > 0 {
+                        rank.push_str(&empties.to_string());
+                        empties = 0;
+                    }
+                    rank.push(*piece.unwrap() as char);
+                }
+            }
+        }
+        if empties > 0 {
+            rank.push_str(&empties.to_string());
+        }
+        rank
+    }
+
+    fn position_lines_never_crash_the_engine(lines: Vec<PositionLine>) -> bool {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+            let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+            tokio::spawn(
+                EngineManager::new(incoming_rx, outgoing_tx, EngineOptions::default()).run(),
+            );
+            let parser = UciParser::new();
+            for PositionLine(line) in lines {
+                if let Ok(command) = parser.parse(&line) {
+                    incoming_tx.send(command).unwrap();
+                }
+            }
+            incoming_tx.send(IncomingCommand::IsReady).unwrap();
+            while let Some(command) = outgoing_rx.recv().await {
+                if command == OutgoingCommand::ReadyOk {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    #[test]
+    fn fuzz_position_commands() {
+        QuickCheck::new()
+            .tests(200)
+            .quickcheck(position_lines_never_crash_the_engine as fn(Vec<PositionLine>) -> bool);
+    }
+
+    #[tokio::test]
+    async fn illegal_moves_are_reported() {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        tokio::spawn(EngineManager::new(incoming_rx, outgoing_tx, EngineOptions::default()).run());
+        let command = UciParser::new()
+            .parse("position startpos moves e2e4 e2e4")
+            .unwrap();
+        incoming_tx.send(command).unwrap();
+        let _ready = outgoing_rx.recv().await;
+        assert_eq!(
+            outgoing_rx.recv().await,
+            Some(OutgoingCommand::Info(InfoPayload {
+                string: Some("Ignored the position command: illegal move e2e4".to_owned()),
+                ..InfoPayload::default()
+            }))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn debug_commands_leave_the_search_running() {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        tokio::spawn(EngineManager::new(incoming_rx, outgoing_tx, EngineOptions::default()).run());
+        let parser = UciParser::new();
+        for line in ["position startpos moves e2e4", "go infinite", "d", "eval"] {
+            incoming_tx.send(parser.parse(line).unwrap()).unwrap();
+        }
+        let mut text = Vec::new();
+        while !text
+            .iter()
+            .any(|l: &String| l.starts_with("Total evaluation"))
+        {
+            match outgoing_rx.recv().await.unwrap() {
+                OutgoingCommand::Text(line) => text.push(line),
+                OutgoingCommand::BestMove(..) => panic!("The search stopped early"),
+                _ => {}
+            }
         }
+        assert!(text.contains(
+            &"Fen: rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1".to_owned()
+        ));
+
+        incoming_tx.send(IncomingCommand::Stop).unwrap();
+        while !matches!(
+            outgoing_rx.recv().await.unwrap(),
+            OutgoingCommand::BestMove(..)
+        ) {}
     }
 }
@@ -1,31 +1,30 @@
 use crate::uci::protocol::{IncomingCommand, InfoPayload, OutgoingCommand, UciParser};
-use std::io::BufRead;
-use std::io::Write;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
 
 use log::warn;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-pub struct InputHandler<'a, I>
+pub struct InputHandler<I>
 where
-    I: BufRead,
+    I: AsyncBufRead + Unpin,
 {
-    stdin: &'a mut I,
+    input: I,
     tx: UnboundedSender<IncomingCommand>,
     tx_err: UnboundedSender<OutgoingCommand>,
     uci_parser: UciParser,
     buf: String,
 }
-impl<'a, I> InputHandler<'a, I>
+impl<I> InputHandler<I>
 where
-    I: BufRead,
+    I: AsyncBufRead + Unpin,
 {
     pub fn new(
-        stdin: &'a mut I,
+        input: I,
         tx: UnboundedSender<IncomingCommand>,
         tx_err: UnboundedSender<OutgoingCommand>,
     ) -> Self {
         Self {
-            stdin,
+            input,
             tx,
             tx_err,
             uci_parser: UciParser::new(),
@@ -33,75 +32,95 @@ where
         }
     }
 
-    pub fn handle_one(&mut self) {
+    /// Returns false once the input is closed, after sending a final `quit`.
+    pub async fn handle_one(&mut self) -> bool {
         self.buf.clear();
-        let read = self.stdin.read_line(&mut self.buf).unwrap();
-        if read != 0 {
-            let parsed = self.uci_parser.parse(self.buf.trim_end());
-            match parsed {
-                Ok(cmd) => self.tx.send(cmd).unwrap(),
-                Err(err) => {
-                    let error_text =
-                        format!("Could not parse UCI input '{}': {err}", self.buf.trim_end());
-                    warn!("{}", error_text);
-                    self.tx_err
-                        .send(OutgoingCommand::Info(InfoPayload {
-                            string: Some(error_text),
-                            ..InfoPayload::default()
-                        }))
-                        .unwrap();
-                }
+        // A broken pipe means the GUI is gone, just like the end of the input
+        let read = self.input.read_line(&mut self.buf).await.unwrap_or(0);
+        if read == 0 {
+            let _ = self.tx.send(IncomingCommand::Quit);
+            return false;
+        }
+        let line = self.buf.trim();
+        if line.is_empty() {
+            return true;
+        }
+        match self.uci_parser.parse(line) {
+            Ok(cmd) => {
+                let _ = self.tx.send(cmd);
+            }
+            Err(err) => {
+                let error_text = format!("Could not parse UCI input '{line}': {err}");
+                warn!("{}", error_text);
+                let _ = self.tx_err.send(OutgoingCommand::Info(InfoPayload {
+                    string: Some(error_text),
+                    ..InfoPayload::default()
+                }));
             }
         }
+        true
     }
 }
-pub struct OutputHandler<'a, O>
+pub struct OutputHandler<O>
 where
-    O: Write,
+    O: AsyncWrite + Unpin,
 {
-    o: &'a mut O,
+    o: O,
     rx: UnboundedReceiver<OutgoingCommand>,
 }
 
-impl<'a, O> OutputHandler<'a, O>
+impl<O> OutputHandler<O>
 where
-    O: Write,
+    O: AsyncWrite + Unpin,
 {
-    pub fn new(o: &'a mut O, rx: UnboundedReceiver<OutgoingCommand>) -> Self {
+    pub fn new(o: O, rx: UnboundedReceiver<OutgoingCommand>) -> Self {
         Self { o, rx }
     }
 
-    pub fn handle_one(&mut self) {
-        let received = self.rx.blocking_recv().unwrap();
-        writeln!(self.o, "{}", received).unwrap();
+    /// Returns false once every sender is gone or the output is closed.
+    pub async fn handle_one(&mut self) -> bool {
+        let Some(received) = self.rx.recv().await else {
+            return false;
+        };
+        let line = format!("{received}\n");
+        self.o.write_all(line.as_bytes()).await.is_ok() && self.o.flush().await.is_ok()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::BufReader;
     use tokio::sync::mpsc;
     use tokio::sync::mpsc::error::TryRecvError;
 
-    #[test]
-    fn parse_and_send_from_stdin() {
+    #[tokio::test]
+    async fn parse_and_send_from_stdin() {
         let (stdin_tx, mut stdin_rx) = mpsc::unbounded_channel();
         let (stdout_tx, mut stdout_rx) = mpsc::unbounded_channel();
 
         let stdin = "uci\n";
 
-        let mut reader = BufReader::new(stdin.as_bytes());
-        let mut input_handler = InputHandler::new(&mut reader, stdin_tx, stdout_tx);
+        let mut input_handler = InputHandler::new(stdin.as_bytes(), stdin_tx, stdout_tx);
 
-        input_handler.handle_one();
+        assert!(input_handler.handle_one().await);
 
-        assert_eq!(stdin_rx.blocking_recv().unwrap(), IncomingCommand::Uci);
+        assert_eq!(stdin_rx.recv().await.unwrap(), IncomingCommand::Uci);
         assert_eq!(stdout_rx.try_recv().err().unwrap(), TryRecvError::Empty);
     }
 
-    #[test]
-    fn send_to_stdout() {
+    #[tokio::test]
+    async fn end_of_input_quits() {
+        let (stdin_tx, mut stdin_rx) = mpsc::unbounded_channel();
+        let (stdout_tx, _stdout_rx) = mpsc::unbounded_channel();
+
+        let mut input_handler = InputHandler::new("".as_bytes(), stdin_tx, stdout_tx);
+
+        assert!(!input_handler.handle_one().await);
+        assert_eq!(stdin_rx.recv().await.unwrap(), IncomingCommand::Quit);
+    }
+
+    #[tokio::test]
+    async fn send_to_stdout() {
         let (stdout_tx, stdout_rx) = mpsc::unbounded_channel();
 
         let mut stdout = Vec::new();
@@ -115,7 +134,7 @@ mod tests {
             }))
             .unwrap();
 
-        output_handler.handle_one();
+        assert!(output_handler.handle_one().await);
 
         assert_eq!("info string payload \n", String::from_utf8(stdout).unwrap());
     }
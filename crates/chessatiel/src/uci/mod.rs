@@ -1,54 +1,226 @@
+mod debug;
 mod engine_manager;
 mod io_handlers;
+pub mod options;
 mod protocol;
 
 use crate::uci::engine_manager::EngineManager;
 use crate::uci::io_handlers::{InputHandler, OutputHandler};
-use crate::uci::protocol::{IncomingCommand, OutgoingCommand};
+use crate::uci::options::EngineOptions;
+pub(crate) use crate::uci::protocol::Score;
+use std::io::BufRead;
 use std::thread;
 use std::thread::JoinHandle;
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-pub async fn uci() {
-    let (stdin_tx, stdin_rx) = mpsc::unbounded_channel();
-    let (stdout_tx, stdout_rx) = mpsc::unbounded_channel();
+const STDIN_BUFFER_SIZE: usize = 64 * 1024;
 
-    let _ = start_stdin_thread(stdin_tx, stdout_tx.clone());
-    let _ = start_stdout_thread(stdout_rx);
+/// Speaks UCI over stdin and stdout until `quit` or the end of the input.
+pub async fn uci(options: EngineOptions) {
+    run_uci(stdin(), tokio::io::stdout(), options).await
+}
+
+/// Stdin as an async reader, it has to be called from within the runtime.
+pub(crate) fn stdin() -> BufReader<DuplexStream> {
+    let (stdin_reader, stdin_writer) = tokio::io::duplex(STDIN_BUFFER_SIZE);
+    let _ = start_stdin_thread(stdin_writer, Handle::current());
+    BufReader::new(stdin_reader)
+}
 
-    let engine_manager = EngineManager::new(stdin_rx, stdout_tx);
+/// Speaks UCI with a GUI on the other end of `input` and `output`, returns after `quit` or the end
+/// of the input once everything has been written.
+pub async fn run_uci<I, O>(input: I, output: O, options: EngineOptions)
+where
+    I: AsyncBufRead + Unpin + Send + 'static,
+    O: AsyncWrite + Unpin + Send + 'static,
+{
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+    let (output_tx, output_rx) = mpsc::unbounded_channel();
 
-    engine_manager.run().await
+    let mut input_handler = InputHandler::new(input, input_tx, output_tx.clone());
+    let input_task = tokio::spawn(async move { while input_handler.handle_one().await {} });
+    let mut output_handler = OutputHandler::new(output, output_rx);
+    let output_task = tokio::spawn(async move { while output_handler.handle_one().await {} });
+
+    EngineManager::new(input_rx, output_tx, options).run().await;
+
+    // Output still on its way comes from tasks that end with the engine
+    input_task.abort();
+    let _ = output_task.await;
 }
 
-fn start_stdin_thread(
-    tx: UnboundedSender<IncomingCommand>,
-    tx_err: UnboundedSender<OutgoingCommand>,
-) -> JoinHandle<()> {
+/// Blocking reads from stdin can't be cancelled, so they get a thread of their own that doesn't
+/// keep the runtime from shutting down.
+fn start_stdin_thread(mut tx: DuplexStream, runtime: Handle) -> JoinHandle<()> {
     thread::Builder::new()
         .name("stdin".to_string())
         .spawn(move || {
             let stdin = std::io::stdin();
             let mut stdin_lock = stdin.lock();
-            let mut input_handler = InputHandler::new(&mut stdin_lock, tx, tx_err);
+            let mut buf = String::new();
             loop {
-                input_handler.handle_one();
+                buf.clear();
+                match stdin_lock.read_line(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        if runtime.block_on(tx.write_all(buf.as_bytes())).is_err() {
+                            break;
+                        }
+                    }
+                }
             }
         })
         .unwrap()
 }
 
-fn start_stdout_thread(rx: UnboundedReceiver<OutgoingCommand>) -> JoinHandle<()> {
-    thread::Builder::new()
-        .name("stdout".to_owned())
-        .spawn(move || {
-            let stdout = std::io::stdout();
-            let mut stdout_lock = stdout.lock();
-            let mut output_handler = OutputHandler::new(&mut stdout_lock, rx);
-            loop {
-                output_handler.handle_one();
-            }
-        })
-        .unwrap()
+#[cfg(test)]
+mod tests {
+    use crate::scripted_gui::{ScriptedGui, Uci, PATIENT, PROMPT};
+
+    fn count_bestmoves(lines: &[String]) -> usize {
+        lines.iter().filter(|l| l.starts_with("bestmove")).count()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn handshake() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        assert!(gui.greeting.iter().any(|l| l.starts_with("id name")));
+        assert!(gui
+            .greeting
+            .iter()
+            .any(|l| l.starts_with("option name Hash")));
+        gui.send("isready").await;
+        gui.expect("readyok", PROMPT).await;
+        gui.send("quit").await;
+        gui.expect_exit(PROMPT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stop_yields_exactly_one_bestmove() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("ucinewgame").await;
+        gui.send("position startpos moves e2e4").await;
+        gui.send("go wtime 600000 btime 600000").await;
+        gui.expect("info nps", PATIENT).await;
+        gui.send("stop").await;
+        let lines = gui.expect("bestmove", PROMPT).await;
+        assert_eq!(count_bestmoves(&lines), 1);
+        gui.send("stop").await;
+        gui.expect_none("bestmove", PROMPT).await;
+        gui.send("quit").await;
+        gui.expect_exit(PROMPT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn immediate_stop_still_yields_a_bestmove() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        for _ in 0..5 {
+            gui.send("position startpos moves e2e4 e7e5").await;
+            gui.send("go infinite").await;
+            gui.send("stop").await;
+            let lines = gui.expect("bestmove", PATIENT).await;
+            assert_eq!(count_bestmoves(&lines), 1);
+            assert_ne!(lines.last().unwrap(), "bestmove 0000");
+        }
+        gui.expect_none("bestmove", PROMPT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_moves_keep_the_previous_position() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("position startpos moves d2d4").await;
+        gui.send("position startpos moves e2e4 e7e9 g1f3").await;
+        gui.expect("info string", PATIENT).await;
+        gui.send("d").await;
+        let lines = gui.expect("Fen:", PATIENT).await;
+        assert_eq!(
+            lines.last().unwrap(),
+            "Fen: rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 1"
+        );
+        gui.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn terminal_positions_yield_the_null_move() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("position startpos moves f2f3 e7e5 g2g4 d8h4")
+            .await;
+        gui.send("go depth 5").await;
+        let lines = gui.expect("bestmove", PATIENT).await;
+        assert_eq!(lines.last().unwrap(), "bestmove 0000");
+        gui.send("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1")
+            .await;
+        gui.send("go wtime 1000 btime 1000").await;
+        let lines = gui.expect("bestmove", PATIENT).await;
+        assert_eq!(lines.last().unwrap(), "bestmove 0000");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mates_are_reported_in_moves() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("position fen 8/8/k1K5/8/8/8/8/1R6 w - - 0 1")
+            .await;
+        gui.send("go depth 3").await;
+        let lines = gui.expect("bestmove", PATIENT).await;
+        let iterations = lines
+            .iter()
+            .filter(|l| l.contains(" score "))
+            .collect::<Vec<_>>();
+        assert_eq!(iterations.len(), 3, "{lines:?}");
+        assert!(iterations.iter().all(|l| l.contains(" score mate 1 ")));
+        assert_eq!(lines.last().unwrap(), "bestmove b1a1");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn isready_answers_during_search() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("position startpos").await;
+        gui.send("go infinite").await;
+        gui.expect("info nps", PATIENT).await;
+        gui.send("isready").await;
+        let lines = gui.expect("readyok", PROMPT).await;
+        assert_eq!(count_bestmoves(&lines), 0);
+        gui.send("stop").await;
+        gui.expect("bestmove", PROMPT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hash_options_during_search_are_refused() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("position startpos").await;
+        gui.send("go infinite").await;
+        gui.send("setoption name Hash value 32").await;
+        gui.expect("info string Ignored option Hash", PATIENT).await;
+        gui.send("setoption name Clear Hash").await;
+        gui.expect("info string Ignored option Clear Hash", PROMPT)
+            .await;
+        gui.send("stop").await;
+        let lines = gui.expect("bestmove", PROMPT).await;
+        assert_eq!(count_bestmoves(&lines), 1);
+        gui.send("setoption name Hash value 32").await;
+        gui.send("isready").await;
+        let lines = gui.expect("readyok", PATIENT).await;
+        assert!(lines.iter().all(|l| !l.starts_with("info string")));
+        gui.expect_none("bestmove", PROMPT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quit_during_search_terminates() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("go infinite").await;
+        gui.expect("info nps", PATIENT).await;
+        gui.send("quit").await;
+        gui.expect_exit(PATIENT).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn end_of_input_terminates() {
+        let mut gui = ScriptedGui::start::<Uci>().await;
+        gui.send("isready").await;
+        gui.expect("readyok", PATIENT).await;
+        gui.close_input();
+        gui.expect_exit(PATIENT).await;
+    }
 }
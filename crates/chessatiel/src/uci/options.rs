@@ -0,0 +1,317 @@
+use brain::transposition_table::TranspositionTable;
+use brain::{EvaluatorKind, DEFAULT_MOVE_OVERHEAD};
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+const MAX_HASH_SIZE_MIB: i64 = 1024;
+const MAX_MULTI_PV: i64 = 256;
+const MAX_MOVE_OVERHEAD_MS: i64 = 5000;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OptionType {
+    Spin {
+        default: i64,
+        min: i64,
+        max: i64,
+    },
+    Check {
+        default: bool,
+    },
+    Combo {
+        default: &'static str,
+        vars: &'static [&'static str],
+    },
+    String {
+        default: &'static str,
+    },
+    Button,
+}
+
+/// An option as advertised to the GUI.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UciOption {
+    pub name: &'static str,
+    pub option_type: OptionType,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OptionValue {
+    Spin(i64),
+    Check(bool),
+    Combo(&'static str),
+    String(String),
+    Button,
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum OptionError {
+    #[error("Unknown option {0}")]
+    Unknown(String),
+    #[error("Invalid {name} value '{value}', expected {expected}")]
+    Invalid {
+        name: &'static str,
+        value: String,
+        expected: String,
+    },
+}
+
+impl UciOption {
+    /// Checks `value` against the type and bounds of the option.
+    pub fn parse(&self, value: Option<&str>) -> Result<OptionValue, OptionError> {
+        let invalid = |expected: String| OptionError::Invalid {
+            name: self.name,
+            value: value.unwrap_or_default().to_owned(),
+            expected,
+        };
+        match &self.option_type {
+            OptionType::Spin { min, max, .. } => match value.map(str::parse::<i64>) {
+                Some(Ok(v)) if (*min..=*max).contains(&v) => Ok(OptionValue::Spin(v)),
+                _ => Err(invalid(format!("{min} to {max}"))),
+            },
+            OptionType::Check { .. } => match value.map(str::parse::<bool>) {
+                Some(Ok(v)) => Ok(OptionValue::Check(v)),
+                _ => Err(invalid("true or false".to_owned())),
+            },
+            // Combo values are case insensitive, like option names
+            OptionType::Combo { vars, .. } => value
+                .and_then(|v| vars.iter().find(|var| var.eq_ignore_ascii_case(v)))
+                .map(|var| OptionValue::Combo(var))
+                .ok_or_else(|| invalid(format!("one of {}", vars.join(", ")))),
+            OptionType::String { .. } => {
+                Ok(OptionValue::String(value.unwrap_or_default().to_owned()))
+            }
+            OptionType::Button => Ok(OptionValue::Button),
+        }
+    }
+}
+
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "name {} type ", self.name)?;
+        match &self.option_type {
+            OptionType::Spin { default, min, max } => {
+                write!(f, "spin default {default} min {min} max {max}")
+            }
+            OptionType::Check { default } => write!(f, "check default {default}"),
+            OptionType::Combo { default, vars } => {
+                write!(f, "combo default {default}")?;
+                for var in vars.iter() {
+                    write!(f, " var {var}")?;
+                }
+                Ok(())
+            }
+            OptionType::String { default } => {
+                let default = if default.is_empty() {
+                    "<empty>"
+                } else {
+                    default
+                };
+                write!(f, "string default {default}")
+            }
+            OptionType::Button => write!(f, "button"),
+        }
+    }
+}
+
+pub const HASH: UciOption = UciOption {
+    name: "Hash",
+    option_type: OptionType::Spin {
+        default: TranspositionTable::DEFAULT_SIZE_MIB as i64,
+        min: 1,
+        max: MAX_HASH_SIZE_MIB,
+    },
+};
+pub const CLEAR_HASH: UciOption = UciOption {
+    name: "Clear Hash",
+    option_type: OptionType::Button,
+};
+pub const PONDER: UciOption = UciOption {
+    name: "Ponder",
+    option_type: OptionType::Check { default: false },
+};
+pub const MULTI_PV: UciOption = UciOption {
+    name: "MultiPV",
+    option_type: OptionType::Spin {
+        default: 1,
+        min: 1,
+        max: MAX_MULTI_PV,
+    },
+};
+pub const MOVE_OVERHEAD: UciOption = UciOption {
+    name: "Move Overhead",
+    option_type: OptionType::Spin {
+        default: DEFAULT_MOVE_OVERHEAD.as_millis() as i64,
+        min: 0,
+        max: MAX_MOVE_OVERHEAD_MS,
+    },
+};
+/// Off by default: the embedded network only mimics material and the PST until a trained network
+/// replaces it.
+pub const USE_NNUE: UciOption = UciOption {
+    name: "Use NNUE",
+    option_type: OptionType::Check { default: false },
+};
+
+/// Every option the engine understands, in the order they're advertised.
+pub const OPTIONS: [UciOption; 6] = [HASH, CLEAR_HASH, PONDER, MULTI_PV, MOVE_OVERHEAD, USE_NNUE];
+
+/// Changes that have to be passed on to the engine instead of only being remembered.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EngineAction {
+    ResizeHash(usize),
+    ClearHash,
+}
+
+/// The current value of every option.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EngineOptions {
+    pub hash_size_mib: usize,
+    /// Only tells us the GUI may send `go ponder`
+    pub ponder: bool,
+    pub multi_pv: usize,
+    pub move_overhead: Duration,
+    pub evaluator: EvaluatorKind,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            hash_size_mib: TranspositionTable::DEFAULT_SIZE_MIB,
+            ponder: false,
+            multi_pv: 1,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            evaluator: EvaluatorKind::default(),
+        }
+    }
+}
+
+impl EngineOptions {
+    /// Validates and stores the value of the option called `name`, ignoring case.
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<Option<EngineAction>, OptionError> {
+        let option = OPTIONS
+            .iter()
+            .find(|o| o.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| OptionError::Unknown(name.to_owned()))?;
+        let value = option.parse(value)?;
+        match (option.name, value) {
+            (name, OptionValue::Spin(size)) if name == HASH.name => {
+                self.hash_size_mib = size as usize;
+                return Ok(Some(EngineAction::ResizeHash(self.hash_size_mib)));
+            }
+            (name, OptionValue::Button) if name == CLEAR_HASH.name => {
+                return Ok(Some(EngineAction::ClearHash));
+            }
+            (name, OptionValue::Check(ponder)) if name == PONDER.name => self.ponder = ponder,
+            (name, OptionValue::Spin(lines)) if name == MULTI_PV.name => {
+                self.multi_pv = lines as usize
+            }
+            (name, OptionValue::Spin(ms)) if name == MOVE_OVERHEAD.name => {
+                self.move_overhead = Duration::from_millis(ms as u64)
+            }
+            (name, OptionValue::Check(nnue)) if name == USE_NNUE.name => {
+                self.evaluator = if nnue {
+                    EvaluatorKind::Nnue
+                } else {
+                    EvaluatorKind::Classic
+                }
+            }
+            (name, value) => unreachable!("Option {name} parsed to unexpected {value:?}"),
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertised_options() {
+        let lines = OPTIONS.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "name Hash type spin default 16 min 1 max 1024",
+                "name Clear Hash type button",
+                "name Ponder type check default false",
+                "name MultiPV type spin default 1 min 1 max 256",
+                "name Move Overhead type spin default 30 min 0 max 5000",
+                "name Use NNUE type check default false",
+            ]
+        );
+    }
+
+    #[test]
+    fn combo_and_string_options() {
+        let combo = UciOption {
+            name: "Style",
+            option_type: OptionType::Combo {
+                default: "Normal",
+                vars: &["Solid", "Normal", "Risky"],
+            },
+        };
+        assert_eq!(
+            combo.to_string(),
+            "name Style type combo default Normal var Solid var Normal var Risky"
+        );
+        assert_eq!(combo.parse(Some("risky")), Ok(OptionValue::Combo("Risky")));
+        assert!(combo.parse(Some("Wild")).is_err());
+
+        let string = UciOption {
+            name: "Book File",
+            option_type: OptionType::String { default: "" },
+        };
+        assert_eq!(
+            string.to_string(),
+            "name Book File type string default <empty>"
+        );
+        assert_eq!(
+            string.parse(Some("book.bin")),
+            Ok(OptionValue::String("book.bin".to_owned()))
+        );
+    }
+
+    #[test]
+    fn set_validates_values() {
+        let mut options = EngineOptions::default();
+        assert_eq!(
+            options.set("hash", Some("64")),
+            Ok(Some(EngineAction::ResizeHash(64)))
+        );
+        assert_eq!(
+            options.set("Clear Hash", None),
+            Ok(Some(EngineAction::ClearHash))
+        );
+        assert_eq!(options.set("Move Overhead", Some("100")), Ok(None));
+        assert_eq!(options.set("use nnue", Some("true")), Ok(None));
+        assert_eq!(
+            options,
+            EngineOptions {
+                hash_size_mib: 64,
+                move_overhead: Duration::from_millis(100),
+                evaluator: EvaluatorKind::Nnue,
+                ..EngineOptions::default()
+            }
+        );
+
+        assert_eq!(
+            options.set("MultiPV", Some("0")),
+            Err(OptionError::Invalid {
+                name: "MultiPV",
+                value: "0".to_owned(),
+                expected: "1 to 256".to_owned(),
+            })
+        );
+        assert!(options.set("Ponder", Some("yes")).is_err());
+        assert_eq!(
+            options.set("Threads", Some("4")),
+            Err(OptionError::Unknown("Threads".to_owned()))
+        );
+        assert_eq!(options.multi_pv, 1);
+    }
+}
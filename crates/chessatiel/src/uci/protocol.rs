@@ -1,13 +1,15 @@
+use brain::evaluator::CentipawnScore;
 use guts::Position;
 use itertools::Itertools;
 use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::character::complete::{alphanumeric1, digit1, one_of, space0, space1};
-use nom::combinator::{map, map_res, opt};
+use nom::bytes::complete::{tag, take_until};
+use nom::character::complete::{digit1, one_of, space0, space1};
+use nom::combinator::{eof, map, map_res, opt, peek, recognize, rest};
 use nom::error::{context, VerboseError};
 use nom::multi::{count, many0, many1, separated_list1};
 use nom::sequence::{preceded, separated_pair, terminated, tuple};
 use nom::{Finish, IResult};
+use serde::Serialize;
 use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
@@ -30,8 +32,18 @@ pub enum IncomingCommand {
     UciNewGame,
     Position(Position, Vec<String>),
     Go(GoPayload),
+    SetOption(String, Option<String>),
+    PonderHit,
     Stop,
     Quit,
+    /// `d`, prints the current position
+    Board,
+    /// Prints the evaluation of the current position term by term
+    Eval,
+    /// `go perft N`, counts the leaf nodes below every legal move
+    Perft(usize),
+    /// Searches the bench positions, to the given depth or the default one
+    Bench(Option<u16>),
 }
 
 impl fmt::Display for IncomingCommand {
@@ -51,9 +63,22 @@ impl fmt::Display for IncomingCommand {
                     format!(" {}", mvs.join(" "))
                 }
             ),
+            IncomingCommand::PonderHit => write!(f, "ponderhit"),
             IncomingCommand::Stop => write!(f, "stop"),
             IncomingCommand::Quit => write!(f, "quit"),
+            IncomingCommand::Board => write!(f, "d"),
+            IncomingCommand::Eval => write!(f, "eval"),
+            IncomingCommand::Perft(depth) => write!(f, "go perft {depth}"),
+            IncomingCommand::Bench(None) => write!(f, "bench"),
+            IncomingCommand::Bench(Some(depth)) => write!(f, "bench {depth}"),
             IncomingCommand::Go(payload) => write!(f, "go {}", payload),
+            IncomingCommand::SetOption(name, value) => {
+                write!(f, "setoption name {name}")?;
+                if let Some(value) = value {
+                    write!(f, " value {value}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -66,6 +91,12 @@ pub struct GoPayload {
     pub winc: Option<Duration>,
     pub btime: Option<Duration>,
     pub binc: Option<Duration>,
+    pub ponder: bool,
+    pub nodes: Option<u64>,
+    pub mate: Option<u16>,
+    pub infinite: bool,
+    pub moves_to_go: Option<u16>,
+    pub search_moves: Vec<String>,
 }
 
 impl fmt::Display for GoPayload {
@@ -88,21 +119,44 @@ impl fmt::Display for GoPayload {
         if let Some(binc) = self.binc {
             write!(f, "binc {} ", binc.as_millis())?
         };
+        if self.ponder {
+            write!(f, "ponder ")?
+        };
+        if let Some(nodes) = self.nodes {
+            write!(f, "nodes {nodes} ")?
+        };
+        if let Some(mate) = self.mate {
+            write!(f, "mate {mate} ")?
+        };
+        if self.infinite {
+            write!(f, "infinite ")?
+        };
+        if let Some(moves_to_go) = self.moves_to_go {
+            write!(f, "movestogo {moves_to_go} ")?
+        };
+        if !self.search_moves.is_empty() {
+            write!(f, "searchmoves {} ", self.search_moves.join(" "))?
+        };
 
         Ok(())
     }
 }
 
-fn parse_uci(s: &str) -> Res<IncomingCommand> {
-    context("uci", map(tag("uci"), |_| IncomingCommand::Uci))(s)
+/// `word` as a whole token, so `stopper` isn't read as `stop`.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> Res<'a, &'a str> {
+    terminated(tag(word), peek(alt((space1, eof))))
+}
+
+fn parse_uci(s: &str) -> Res<'_, IncomingCommand> {
+    context("uci", map(keyword("uci"), |_| IncomingCommand::Uci))(s)
 }
 
-fn parse_debug(s: &str) -> Res<IncomingCommand> {
+fn parse_debug(s: &str) -> Res<'_, IncomingCommand> {
     context(
         "debug",
         map(
             preceded(
-                tuple((tag("debug"), space1)),
+                tuple((keyword("debug"), space1)),
                 alt((map(tag("on"), |_| true), map(tag("off"), |_| false))),
             ),
             IncomingCommand::Debug,
@@ -110,41 +164,112 @@ fn parse_debug(s: &str) -> Res<IncomingCommand> {
     )(s)
 }
 
-fn parse_isready(s: &str) -> Res<IncomingCommand> {
-    context("isready", map(tag("isready"), |_| IncomingCommand::IsReady))(s)
+fn parse_isready(s: &str) -> Res<'_, IncomingCommand> {
+    context(
+        "isready",
+        map(keyword("isready"), |_| IncomingCommand::IsReady),
+    )(s)
 }
 
-fn parse_ucinewgame(s: &str) -> Res<IncomingCommand> {
+fn parse_ucinewgame(s: &str) -> Res<'_, IncomingCommand> {
     context(
         "ucinewgame",
-        map(tag("ucinewgame"), |_| IncomingCommand::UciNewGame),
+        map(keyword("ucinewgame"), |_| IncomingCommand::UciNewGame),
     )(s)
 }
 
-fn parse_stop(s: &str) -> Res<IncomingCommand> {
-    context("stop", map(tag("stop"), |_| IncomingCommand::Stop))(s)
+fn parse_setoption(s: &str) -> Res<'_, IncomingCommand> {
+    context(
+        "setoption",
+        map(
+            preceded(
+                tuple((keyword("setoption"), space1, keyword("name"), space1)),
+                alt((
+                    map(
+                        separated_pair(
+                            take_until(" value "),
+                            tuple((space1, tag("value"), space1)),
+                            rest,
+                        ),
+                        |(name, value): (&str, &str)| (name, Some(value)),
+                    ),
+                    map(rest, |name| (name, None)),
+                )),
+            ),
+            |(name, value)| {
+                IncomingCommand::SetOption(
+                    name.trim().to_owned(),
+                    value.map(|v| v.trim().to_owned()),
+                )
+            },
+        ),
+    )(s)
 }
 
-fn parse_quit(s: &str) -> Res<IncomingCommand> {
+fn parse_ponderhit(s: &str) -> Res<'_, IncomingCommand> {
+    context(
+        "ponderhit",
+        map(keyword("ponderhit"), |_| IncomingCommand::PonderHit),
+    )(s)
+}
+
+fn parse_stop(s: &str) -> Res<'_, IncomingCommand> {
+    context("stop", map(keyword("stop"), |_| IncomingCommand::Stop))(s)
+}
+
+fn parse_quit(s: &str) -> Res<'_, IncomingCommand> {
     context(
         "quit",
-        map(alt((tag("quit"), tag("exit"))), |_| IncomingCommand::Quit),
+        map(alt((keyword("quit"), keyword("exit"))), |_| {
+            IncomingCommand::Quit
+        }),
+    )(s)
+}
+
+fn parse_board(s: &str) -> Res<'_, IncomingCommand> {
+    context("board", map(keyword("d"), |_| IncomingCommand::Board))(s)
+}
+
+fn parse_eval(s: &str) -> Res<'_, IncomingCommand> {
+    context("eval", map(keyword("eval"), |_| IncomingCommand::Eval))(s)
+}
+
+fn parse_perft(s: &str) -> Res<'_, IncomingCommand> {
+    context(
+        "perft",
+        map_res(
+            preceded(
+                tuple((keyword("go"), space1, keyword("perft"), space1)),
+                digit1,
+            ),
+            |d: &str| d.parse().map(IncomingCommand::Perft),
+        ),
+    )(s)
+}
+
+fn parse_bench(s: &str) -> Res<'_, IncomingCommand> {
+    context(
+        "bench",
+        map_res(
+            preceded(keyword("bench"), opt(preceded(space1, digit1))),
+            |d: Option<&str>| d.map(str::parse).transpose().map(IncomingCommand::Bench),
+        ),
     )(s)
 }
 
-fn parse_position(s: &str) -> Res<IncomingCommand> {
+fn parse_position(s: &str) -> Res<'_, IncomingCommand> {
     context(
         "position",
         map(
             preceded(
-                tuple((tag("position"), space1)),
+                tuple((keyword("position"), space1)),
                 tuple((
                     alt((
-                        preceded(preceded(tag("fen"), space1), parse_fen),
-                        map(tag("startpos"), |_| Position::default()),
+                        preceded(preceded(keyword("fen"), space1), parse_fen),
+                        map(keyword("startpos"), |_| Position::default()),
                     )),
                     opt(preceded(
-                        tuple((space0, tag("moves"), space1)),
+                        tuple((space0, keyword("moves"), space1)),
                         many0(parse_move),
                     )),
                 )),
@@ -159,7 +284,7 @@ fn parse_position(s: &str) -> Res<IncomingCommand> {
     )(s)
 }
 
-fn parse_fen(s: &str) -> Res<Position> {
+fn parse_fen(s: &str) -> Res<'_, Position> {
     context(
         "fen",
         map_res(
@@ -171,7 +296,10 @@ fn parse_fen(s: &str) -> Res<Position> {
                         terminated(
                             context(
                                 "fen_many1",
-                                many1(context("fen_one_of", one_of("/1234567890rnbqkpRNBQKPbw-"))),
+                                many1(context(
+                                    "fen_one_of",
+                                    one_of("/1234567890rnbqkpRNBQKPabcdefghw-"),
+                                )),
                             ),
                             space0,
                         ),
@@ -191,15 +319,29 @@ fn parse_fen(s: &str) -> Res<Position> {
     )(s)
 }
 
-fn parse_move(s: &str) -> Res<&str> {
-    context("move", terminated(alphanumeric1, space0))(s)
+fn parse_move(s: &str) -> Res<'_, &str> {
+    context("move", terminated(parse_uci_move, alt((space1, eof))))(s)
 }
 
-fn parse_go(s: &str) -> Res<IncomingCommand> {
+/// A move in long algebraic notation, without consuming anything after it.
+fn parse_uci_move(s: &str) -> Res<'_, &str> {
+    context(
+        "uci_move",
+        recognize(tuple((
+            one_of("abcdefgh"),
+            one_of("12345678"),
+            one_of("abcdefgh"),
+            one_of("12345678"),
+            opt(one_of("qrbn")),
+        ))),
+    )(s)
+}
+
+fn parse_go(s: &str) -> Res<'_, IncomingCommand> {
     context(
         "go",
         map(
-            preceded(tag("go"), preceded(space1, parse_go_payload)),
+            preceded(keyword("go"), preceded(space1, parse_go_payload)),
             IncomingCommand::Go,
         ),
     )(s)
@@ -213,10 +355,16 @@ enum GoPayloadOption {
     BTime(Duration),
     WInc(Duration),
     BInc(Duration),
+    Ponder,
+    Nodes(u64),
+    Mate(u16),
+    Infinite,
+    MovesToGo(u16),
+    SearchMoves(Vec<String>),
 }
 
 // TODO if times are set they are not independent
-fn parse_go_payload(s: &str) -> Res<GoPayload> {
+fn parse_go_payload(s: &str) -> Res<'_, GoPayload> {
     use GoPayloadOption::*;
     context(
         "go_payload",
@@ -248,6 +396,27 @@ fn parse_go_payload(s: &str) -> Res<GoPayload> {
                         map(separated_pair(tag("binc"), space1, digit1), |(_, s)| s),
                         |d: &str| d.parse().map(Duration::from_millis).map(BInc),
                     ),
+                    map(tag("ponder"), |_| Ponder),
+                    map_res(
+                        map(separated_pair(tag("nodes"), space1, digit1), |(_, s)| s),
+                        |d: &str| d.parse().map(Nodes),
+                    ),
+                    map_res(
+                        map(separated_pair(tag("mate"), space1, digit1), |(_, s)| s),
+                        |d: &str| d.parse().map(Mate),
+                    ),
+                    map(tag("infinite"), |_| Infinite),
+                    map_res(
+                        map(separated_pair(tag("movestogo"), space1, digit1), |(_, s)| s),
+                        |d: &str| d.parse().map(MovesToGo),
+                    ),
+                    map(
+                        preceded(
+                            tuple((tag("searchmoves"), space1)),
+                            separated_list1(space1, parse_uci_move),
+                        ),
+                        |ms| SearchMoves(ms.into_iter().map(|m| m.to_owned()).collect()),
+                    ),
                 )),
             ),
             |gpos| {
@@ -290,6 +459,37 @@ fn parse_go_payload(s: &str) -> Res<GoPayload> {
                                 ..gp
                             }
                         }
+                        Ponder => gp = GoPayload { ponder: true, ..gp },
+                        Nodes(n) => {
+                            gp = GoPayload {
+                                nodes: Some(n),
+                                ..gp
+                            }
+                        }
+                        Mate(n) => {
+                            gp = GoPayload {
+                                mate: Some(n),
+                                ..gp
+                            }
+                        }
+                        Infinite => {
+                            gp = GoPayload {
+                                infinite: true,
+                                ..gp
+                            }
+                        }
+                        MovesToGo(n) => {
+                            gp = GoPayload {
+                                moves_to_go: Some(n),
+                                ..gp
+                            }
+                        }
+                        SearchMoves(ms) => {
+                            gp = GoPayload {
+                                search_moves: ms,
+                                ..gp
+                            }
+                        }
                     }
                 }
                 gp
@@ -303,8 +503,11 @@ pub enum OutgoingCommand {
     Id(&'static str, &'static str),
     UciOk,
     ReadyOk,
-    BestMove(String),
+    BestMove(String, Option<String>),
     Info(InfoPayload),
+    Option(String),
+    /// Free-form output of the debug commands
+    Text(String),
 }
 
 impl fmt::Display for OutgoingCommand {
@@ -313,8 +516,38 @@ impl fmt::Display for OutgoingCommand {
             OutgoingCommand::Id(k, v) => write!(f, "id {} {}", k, v),
             OutgoingCommand::UciOk => write!(f, "uciok"),
             OutgoingCommand::ReadyOk => write!(f, "readyok"),
-            OutgoingCommand::BestMove(m) => write!(f, "bestmove {}", m),
+            OutgoingCommand::BestMove(m, None) => write!(f, "bestmove {}", m),
+            OutgoingCommand::BestMove(m, Some(p)) => write!(f, "bestmove {} ponder {}", m, p),
             OutgoingCommand::Info(s) => write!(f, "info {}", s),
+            OutgoingCommand::Option(s) => write!(f, "option {}", s),
+            OutgoingCommand::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A score from the point of view of the side to move, mates in full moves like UCI counts them.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Score {
+    Cp(i32),
+    /// Negative when getting mated
+    Mate(i32),
+}
+
+impl From<CentipawnScore> for Score {
+    fn from(score: CentipawnScore) -> Self {
+        match score.mate_moves() {
+            Some(moves) => Self::Mate(moves),
+            None => Self::Cp(score.0),
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Cp(cp) => write!(f, "cp {cp}"),
+            Score::Mate(moves) => write!(f, "mate {moves}"),
         }
     }
 }
@@ -326,7 +559,14 @@ pub struct InfoPayload {
     pub depth: Option<u64>,
     pub nodes: Option<u64>,
     pub tt_hits: Option<u64>,
-    pub score: Option<i32>,
+    pub score: Option<Score>,
+    pub multipv: Option<usize>,
+    pub pv: Option<Vec<String>>,
+    pub seldepth: Option<u64>,
+    pub time: Option<Duration>,
+    pub hashfull: Option<u64>,
+    pub currmove: Option<String>,
+    pub currmovenumber: Option<u64>,
 }
 
 impl fmt::Display for InfoPayload {
@@ -339,6 +579,14 @@ impl fmt::Display for InfoPayload {
             write!(f, "depth {} ", depth)?
         }
 
+        if let Some(seldepth) = self.seldepth {
+            write!(f, "seldepth {} ", seldepth)?
+        }
+
+        if let Some(multipv) = self.multipv {
+            write!(f, "multipv {} ", multipv)?
+        }
+
         if let Some(nodes) = self.nodes {
             write!(f, "nodes {} ", nodes)?
         }
@@ -347,12 +595,40 @@ impl fmt::Display for InfoPayload {
             write!(f, "tt_hits {} ", tt_hits)?
         }
 
-        if let Some(ref string) = self.string {
-            write!(f, "string {} ", string)?
+        if let Some(time) = self.time {
+            write!(f, "time {} ", time.as_millis())?
+        }
+
+        if let Some(hashfull) = self.hashfull {
+            write!(f, "hashfull {} ", hashfull)?
         }
 
         if let Some(score) = self.score {
-            write!(f, "score cp {} ", score)?
+            write!(f, "score {} ", score)?
+        }
+
+        if let Some(ref currmove) = self.currmove {
+            write!(f, "currmove {} ", currmove)?
+        }
+
+        if let Some(currmovenumber) = self.currmovenumber {
+            write!(f, "currmovenumber {} ", currmovenumber)?
+        }
+
+        if let Some(ref pv) = self.pv {
+            write!(f, "pv {} ", pv.join(" "))?
+        }
+
+        // Both take the rest of the line, so a string next to anything else gets its own line
+        if let Some(ref string) = self.string {
+            let others = InfoPayload {
+                string: None,
+                ..self.clone()
+            };
+            if others != InfoPayload::default() {
+                write!(f, "\ninfo ")?
+            }
+            write!(f, "string {} ", string)?
         }
 
         Ok(())
@@ -366,17 +642,27 @@ impl UciParser {
         Self()
     }
 
+    /// Fails unless the whole line is a command, a valid prefix followed by junk is an error too.
     pub fn parse(&self, s: &str) -> Result<IncomingCommand, UciParseError> {
-        alt((
-            parse_ucinewgame,
-            parse_uci,
-            parse_debug,
-            parse_isready,
-            parse_position,
-            parse_stop,
-            parse_quit,
-            parse_go,
-        ))(s)
+        terminated(
+            alt((
+                parse_ucinewgame,
+                parse_ponderhit,
+                parse_uci,
+                parse_debug,
+                parse_isready,
+                parse_position,
+                parse_stop,
+                parse_quit,
+                parse_perft,
+                parse_go,
+                parse_setoption,
+                parse_board,
+                parse_eval,
+                parse_bench,
+            )),
+            eof,
+        )(s)
         .finish()
         .map(|(_, o)| o)
         .map_err(|e| UciParseError::Error(format!("{:?}", e)))
@@ -393,6 +679,14 @@ impl Default for UciParser {
 mod tests {
     use super::*;
     use nom::Finish;
+    use quickcheck::quickcheck;
+
+    quickcheck! {
+        fn parse_never_panics(line: String) -> bool {
+            let _ = UciParser::new().parse(&line);
+            true
+        }
+    }
 
     #[test]
     fn uci() {
@@ -529,6 +823,27 @@ mod tests {
         assert_eq!(parse_move(input).finish().map(|(_, res)| res), Ok("e2e4"));
     }
 
+    #[test]
+    fn move_must_be_on_the_board() {
+        assert!(parse_move("e9e4").finish().is_err());
+        assert!(parse_move("abc").finish().is_err());
+    }
+
+    #[test]
+    fn junk_after_a_command_is_an_error() {
+        let parser = UciParser::new();
+        for line in [
+            "stopper",
+            "isreadyxyz",
+            "uci now",
+            "go depth 5x",
+            "position startpos moves e2e4 e7e9 g1f3",
+            "position startpos moves e2e4x",
+        ] {
+            assert!(parser.parse(line).is_err(), "Parsed '{line}'");
+        }
+    }
+
     #[test]
     fn just_move_promotion() {
         let input = "e2e4q";
@@ -590,6 +905,149 @@ mod tests {
         );
     }
 
+    #[test]
+    fn setoption_with_value() {
+        let input = "setoption name Hash value 128";
+        assert_eq!(
+            parse_setoption(input).finish().map(|(_, res)| res),
+            Ok(IncomingCommand::SetOption(
+                "Hash".to_owned(),
+                Some("128".to_owned())
+            ))
+        )
+    }
+
+    #[test]
+    fn setoption_button_with_spaces() {
+        let input = "setoption name Clear Hash";
+        assert_eq!(
+            parse_setoption(input).finish().map(|(_, res)| res),
+            Ok(IncomingCommand::SetOption("Clear Hash".to_owned(), None))
+        )
+    }
+
+    #[test]
+    fn go_ponder() {
+        let input = "go ponder wtime 1000 btime 2000";
+        assert_eq!(
+            parse_go(input).finish().map(|(_, res)| res),
+            Ok(IncomingCommand::Go(GoPayload {
+                wtime: Some(Duration::from_millis(1000)),
+                btime: Some(Duration::from_millis(2000)),
+                ponder: true,
+                ..GoPayload::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn go_limits() {
+        let input = "go nodes 10000 mate 3 movestogo 12 wtime 60000";
+        assert_eq!(
+            parse_go(input).finish().map(|(_, res)| res),
+            Ok(IncomingCommand::Go(GoPayload {
+                nodes: Some(10000),
+                mate: Some(3),
+                moves_to_go: Some(12),
+                wtime: Some(Duration::from_millis(60000)),
+                ..GoPayload::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn go_infinite_searchmoves() {
+        let input = "go infinite searchmoves e2e4 d2d4 a7a8q depth 5";
+        assert_eq!(
+            parse_go(input).finish().map(|(_, res)| res),
+            Ok(IncomingCommand::Go(GoPayload {
+                infinite: true,
+                search_moves: vec!["e2e4".to_owned(), "d2d4".to_owned(), "a7a8q".to_owned()],
+                depth: Some(5),
+                ..GoPayload::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn ponderhit() {
+        let input = "ponderhit";
+        assert_eq!(
+            UciParser::new().parse(input).unwrap(),
+            IncomingCommand::PonderHit
+        )
+    }
+
+    #[test]
+    fn info_multipv() {
+        let command = OutgoingCommand::Info(InfoPayload {
+            depth: Some(3),
+            multipv: Some(2),
+            score: Some(Score::Cp(-15)),
+            pv: Some(vec!["d2d4".to_owned(), "d7d5".to_owned()]),
+            ..InfoPayload::default()
+        });
+        assert_eq!(
+            command.to_string(),
+            "info depth 3 multipv 2 score cp -15 pv d2d4 d7d5 "
+        )
+    }
+
+    #[test]
+    fn info_full_iteration() {
+        let command = OutgoingCommand::Info(InfoPayload {
+            depth: Some(6),
+            seldepth: Some(11),
+            multipv: Some(1),
+            score: Some(Score::Cp(32)),
+            nodes: Some(120000),
+            nps: Some(400000),
+            time: Some(Duration::from_millis(300)),
+            hashfull: Some(12),
+            pv: Some(vec!["e2e4".to_owned(), "e7e5".to_owned()]),
+            ..InfoPayload::default()
+        });
+        assert_eq!(
+            command.to_string(),
+            "info nps 400000 depth 6 seldepth 11 multipv 1 nodes 120000 time 300 hashfull 12 \
+             score cp 32 pv e2e4 e7e5 "
+        )
+    }
+
+    #[test]
+    fn info_mate_scores() {
+        let info = |score| {
+            OutgoingCommand::Info(InfoPayload {
+                score: Some(Score::from(score)),
+                ..InfoPayload::default()
+            })
+            .to_string()
+        };
+        assert_eq!(info(-CentipawnScore::mated_in(3)), "info score mate 2 ");
+        assert_eq!(info(CentipawnScore::mated_in(2)), "info score mate -1 ");
+        assert_eq!(info(CentipawnScore(-40)), "info score cp -40 ");
+    }
+
+    #[test]
+    fn info_string_gets_its_own_line() {
+        let command = OutgoingCommand::Info(InfoPayload {
+            depth: Some(3),
+            pv: Some(vec!["e2e4".to_owned()]),
+            string: Some("book move".to_owned()),
+            ..InfoPayload::default()
+        });
+        assert_eq!(
+            command.to_string(),
+            "info depth 3 pv e2e4 \ninfo string book move "
+        );
+    }
+
+    #[test]
+    fn bestmove_with_ponder() {
+        let command = OutgoingCommand::BestMove("e2e4".to_owned(), Some("e7e5".to_owned()));
+        assert_eq!(command.to_string(), "bestmove e2e4 ponder e7e5")
+    }
+
     #[test]
     fn stop() {
         let input = "stop";
@@ -616,4 +1074,25 @@ mod tests {
             Ok(IncomingCommand::Quit)
         )
     }
+
+    #[test]
+    fn debug_commands() {
+        let parser = UciParser::new();
+        assert_eq!(parser.parse("d").unwrap(), IncomingCommand::Board);
+        assert_eq!(parser.parse("eval").unwrap(), IncomingCommand::Eval);
+        assert_eq!(parser.parse("bench").unwrap(), IncomingCommand::Bench(None));
+        assert_eq!(
+            parser.parse("bench 4").unwrap(),
+            IncomingCommand::Bench(Some(4))
+        );
+        assert_eq!(
+            parser.parse("go perft 5").unwrap(),
+            IncomingCommand::Perft(5)
+        );
+        assert_eq!(
+            parser.parse("debug on").unwrap(),
+            IncomingCommand::Debug(true)
+        );
+        assert!(parser.parse("dance").is_err());
+    }
 }
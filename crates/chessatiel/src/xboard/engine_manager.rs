@@ -0,0 +1,336 @@
+use crate::search_log::{self, SearchContext};
+use crate::uci::options::EngineOptions;
+use crate::xboard::protocol::XboardCommand;
+use brain::evaluator::CentipawnScore;
+use brain::transposition_table::TranspositionTable;
+use brain::{EngineHandle, EngineUpdate, MoveResult, RemainingTime, SearchConfiguration};
+use guts::{Color, Position};
+use log::debug;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+/// xboard shows mates as this plus the moves to the mate, and getting mated as the negation.
+const MATE_SCORE: i32 = 100_000;
+
+const FEATURES: &str = "feature myname=\"Chessatiel\" ping=1 setboard=1 usermove=1 playother=0 \
+    san=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 done=1";
+
+#[derive(Debug, Default, Clone)]
+struct TimeControl {
+    moves_per_session: Option<u16>,
+    base: Option<Duration>,
+    increment: Duration,
+    move_time: Option<Duration>,
+    /// Our clock as last sent by `time`
+    remaining: Option<Duration>,
+}
+
+impl TimeControl {
+    fn remaining_time(&self, fullmove_number: u16) -> Option<RemainingTime> {
+        if let Some(move_time) = self.move_time {
+            return Some(RemainingTime::ForMove(move_time));
+        }
+        self.remaining
+            .or(self.base)
+            .map(|remaining| RemainingTime::ForGame {
+                remaining,
+                increment: self.increment,
+                moves_to_go: self
+                    .moves_per_session
+                    .map(|mps| mps - (fullmove_number.saturating_sub(1) % mps)),
+            })
+    }
+}
+
+/// Plays a game over the xboard protocol, keeping the moves so they can be taken back.
+pub struct XboardManager {
+    rx: UnboundedReceiver<XboardCommand>,
+    tx: UnboundedSender<String>,
+    cancellation_tx: watch::Sender<()>,
+    engine_handle: EngineHandle,
+    options: EngineOptions,
+    initial_position: Position,
+    moves: Vec<String>,
+    /// None in force mode
+    engine_color: Option<Color>,
+    time_control: TimeControl,
+    depth: Option<u16>,
+    post: bool,
+    search: Option<UnboundedReceiver<EngineUpdate>>,
+    /// Counts `new`, to tell games apart in the search log
+    game: u32,
+}
+
+impl XboardManager {
+    pub fn new(
+        rx: UnboundedReceiver<XboardCommand>,
+        tx: UnboundedSender<String>,
+        options: EngineOptions,
+    ) -> Self {
+        let (cancellation_tx, cancellation_rx) = watch::channel(());
+        let engine_handle = EngineHandle::new(cancellation_rx);
+        Self {
+            rx,
+            tx,
+            cancellation_tx,
+            engine_handle,
+            options,
+            initial_position: Position::default(),
+            moves: Vec::new(),
+            engine_color: Some(Color::Black),
+            time_control: TimeControl::default(),
+            depth: None,
+            post: false,
+            search: None,
+            game: 0,
+        }
+    }
+
+    pub async fn run(mut self) {
+        if self.options.hash_size_mib != TranspositionTable::DEFAULT_SIZE_MIB {
+            let _ = self
+                .engine_handle
+                .set_hash_size(self.options.hash_size_mib)
+                .await;
+        }
+        loop {
+            select! {
+                command = self.rx.recv() => match command {
+                    Some(XboardCommand::Quit) | None => break,
+                    Some(command) => self.handle_command(command).await,
+                },
+                update = next_update(&mut self.search) => match update {
+                    Some(update) => self.handle_update(update).await,
+                    None => self.search = None,
+                },
+            }
+        }
+        let _ = self.cancellation_tx.send(());
+    }
+
+    async fn handle_command(&mut self, command: XboardCommand) {
+        debug!("Got xboard command {command:?}");
+        match command {
+            XboardCommand::Xboard | XboardCommand::Ignored | XboardCommand::Quit => {}
+            XboardCommand::Protover(_) => self.send(FEATURES.to_owned()),
+            XboardCommand::New => {
+                self.game += 1;
+                self.abandon_search().await;
+                let _ = self.engine_handle.clear_hash().await;
+                self.initial_position = Position::default();
+                self.moves.clear();
+                self.engine_color = Some(Color::Black);
+                self.time_control.remaining = None;
+                self.depth = None;
+                self.sync_position().await;
+            }
+            XboardCommand::Force => {
+                self.abandon_search().await;
+                self.engine_color = None;
+            }
+            XboardCommand::Go => {
+                self.engine_color = Some(self.engine_handle.current_color().await);
+                if self.search.is_none() {
+                    self.start_search().await;
+                }
+            }
+            XboardCommand::UserMove(m) => {
+                self.abandon_search().await;
+                self.moves.push(m);
+                if !self.sync_position().await {
+                    let m = self.moves.pop().unwrap_or_default();
+                    self.send(format!("Illegal move: {m}"));
+                    return;
+                }
+                if self.engine_color == Some(self.engine_handle.current_color().await) {
+                    self.start_search().await;
+                }
+            }
+            XboardCommand::Level {
+                moves_per_session,
+                base,
+                increment,
+            } => {
+                self.time_control = TimeControl {
+                    moves_per_session: (moves_per_session > 0).then_some(moves_per_session),
+                    base: Some(base),
+                    increment,
+                    ..TimeControl::default()
+                };
+            }
+            XboardCommand::MoveTime(move_time) => self.time_control.move_time = Some(move_time),
+            XboardCommand::Depth(depth) => self.depth = Some(depth),
+            XboardCommand::Time(remaining) => self.time_control.remaining = Some(remaining),
+            XboardCommand::OpponentTime(_) => {}
+            // Everything before it has been handled, even if we're still thinking
+            XboardCommand::Ping(n) => self.send(format!("pong {n}")),
+            XboardCommand::SetBoard(position) => {
+                self.abandon_search().await;
+                self.initial_position = position;
+                self.moves.clear();
+                self.sync_position().await;
+            }
+            XboardCommand::Undo => self.take_back(1).await,
+            XboardCommand::Remove => self.take_back(2).await,
+            XboardCommand::MoveNow => {
+                // The search ends with its best move, which we then play
+                let _ = self.engine_handle.stop().await;
+            }
+            XboardCommand::Post => self.post = true,
+            XboardCommand::NoPost => self.post = false,
+        }
+    }
+
+    async fn handle_update(&mut self, update: EngineUpdate) {
+        match update {
+            EngineUpdate::BestMove(result) => {
+                self.search = None;
+                self.play(result).await;
+            }
+            EngineUpdate::MultiPv {
+                lines, nodes, time, ..
+            } if self.post => {
+                if let Some(best) = lines.first() {
+                    self.send(format!(
+                        "{} {} {} {nodes} {}",
+                        best.depth(),
+                        shown_score(best.score()),
+                        time.as_millis() / 10,
+                        best.pv().map(|m| m.as_uci()).collect::<Vec<_>>().join(" ")
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn play(&mut self, result: MoveResult) {
+        match result.first_move() {
+            Some(m) => {
+                let m = m.as_uci();
+                self.moves.push(m.clone());
+                self.sync_position().await;
+                self.send(format!("move {m}"));
+            }
+            // Only happens without legal moves, so the game is over
+            None => {
+                let loser = self.engine_handle.current_color().await;
+                let result = match (result.score() < CentipawnScore::ZERO, loser) {
+                    (true, Color::White) => "0-1 {Black mates}",
+                    (true, Color::Black) => "1-0 {White mates}",
+                    (false, _) => "1/2-1/2 {Stalemate}",
+                };
+                self.send(result.to_owned());
+            }
+        }
+    }
+
+    async fn start_search(&mut self) {
+        let position = self.engine_handle.current_position().await;
+        let config = SearchConfiguration {
+            depth: self.depth,
+            remaining_time: self.time_control.remaining_time(position.fullmove_number()),
+            multi_pv: 1,
+            move_overhead: Some(self.options.move_overhead),
+            evaluator: self.options.evaluator,
+            ..SearchConfiguration::default()
+        };
+        let context = SearchContext::new(
+            search_log::local_game_id(self.game),
+            &position,
+            config.remaining_time.as_ref(),
+        );
+        match self.engine_handle.go(config).await {
+            Ok(updates) => self.search = Some(search_log::trace(context, updates)),
+            Err(e) => self.send(format!("Error (could not start thinking): {e}")),
+        }
+    }
+
+    /// Stops thinking without playing the move that was found.
+    async fn abandon_search(&mut self) {
+        if self.search.take().is_some() {
+            let _ = self.engine_handle.stop().await;
+        }
+    }
+
+    async fn take_back(&mut self, moves: usize) {
+        self.abandon_search().await;
+        let remaining = self.moves.len().saturating_sub(moves);
+        self.moves.truncate(remaining);
+        self.sync_position().await;
+    }
+
+    /// Returns whether all moves were legal, otherwise the engine keeps its previous position.
+    async fn sync_position(&mut self) -> bool {
+        self.engine_handle
+            .set_initial_values(self.initial_position.clone(), self.moves.clone())
+            .await
+            .is_ok()
+    }
+
+    fn send(&self, line: String) {
+        let _ = self.tx.send(line);
+    }
+}
+
+async fn next_update(search: &mut Option<UnboundedReceiver<EngineUpdate>>) -> Option<EngineUpdate> {
+    match search {
+        Some(updates) => updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The score as the `post` output shows it.
+fn shown_score(score: CentipawnScore) -> i32 {
+    match score.mate_moves() {
+        Some(moves) if score > CentipawnScore::ZERO => MATE_SCORE + moves,
+        Some(moves) => -MATE_SCORE + moves,
+        None => score.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls() {
+        let mut time_control = TimeControl {
+            moves_per_session: Some(40),
+            base: Some(Duration::from_secs(300)),
+            ..TimeControl::default()
+        };
+        assert!(matches!(
+            time_control.remaining_time(1),
+            Some(RemainingTime::ForGame {
+                remaining,
+                moves_to_go: Some(40),
+                ..
+            }) if remaining == Duration::from_secs(300)
+        ));
+        time_control.remaining = Some(Duration::from_secs(12));
+        assert!(matches!(
+            time_control.remaining_time(45),
+            Some(RemainingTime::ForGame {
+                remaining,
+                moves_to_go: Some(36),
+                ..
+            }) if remaining == Duration::from_secs(12)
+        ));
+        time_control.move_time = Some(Duration::from_secs(2));
+        assert!(matches!(
+            time_control.remaining_time(45),
+            Some(RemainingTime::ForMove(d)) if d == Duration::from_secs(2)
+        ));
+        assert!(TimeControl::default().remaining_time(1).is_none());
+    }
+
+    #[test]
+    fn mates_are_shown_in_moves() {
+        assert_eq!(shown_score(-CentipawnScore::mated_in(3)), 100_002);
+        assert_eq!(shown_score(CentipawnScore::mated_in(4)), -100_002);
+        assert_eq!(shown_score(CentipawnScore(-35)), -35);
+    }
+}
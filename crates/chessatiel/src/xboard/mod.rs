@@ -0,0 +1,160 @@
+mod engine_manager;
+mod protocol;
+
+use crate::uci::options::EngineOptions;
+use crate::uci::stdin;
+use crate::xboard::engine_manager::XboardManager;
+use crate::xboard::protocol::{parse, XboardCommand, XboardParseError};
+use log::{debug, info};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Speaks the xboard protocol over stdin and stdout until `quit` or the end of the input.
+pub async fn xboard(options: EngineOptions) {
+    run_xboard(stdin(), tokio::io::stdout(), options).await
+}
+
+/// Speaks the xboard protocol with a GUI on the other end of `input` and `output`, returns after
+/// `quit` or the end of the input once everything has been written.
+pub async fn run_xboard<I, O>(input: I, mut output: O, options: EngineOptions)
+where
+    I: AsyncBufRead + Unpin + Send + 'static,
+    O: AsyncWrite + Unpin + Send + 'static,
+{
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+    let (output_tx, mut output_rx) = mpsc::unbounded_channel::<String>();
+
+    let error_tx = output_tx.clone();
+    let input_task = tokio::spawn(async move {
+        let mut lines = input.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            info!("> {line}");
+            let command = match parse(&line) {
+                Ok(command) => command,
+                Err(XboardParseError::UnknownCommand) => {
+                    let _ = error_tx.send(format!("Error (unknown command): {line}"));
+                    continue;
+                }
+                Err(e) => {
+                    let _ = error_tx.send(format!("Error ({e}): {line}"));
+                    continue;
+                }
+            };
+            if input_tx.send(command).is_err() {
+                return;
+            }
+        }
+        debug!("End of input");
+        let _ = input_tx.send(XboardCommand::Quit);
+    });
+    let output_task = tokio::spawn(async move {
+        while let Some(line) = output_rx.recv().await {
+            info!("< {line}");
+            let written = async {
+                output.write_all(format!("{line}\n").as_bytes()).await?;
+                output.flush().await
+            };
+            if written.await.is_err() {
+                break;
+            }
+        }
+    });
+
+    XboardManager::new(input_rx, output_tx, options).run().await;
+
+    input_task.abort();
+    let _ = output_task.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripted_gui::{ScriptedGui, Xboard, PATIENT, PROMPT};
+
+    /// Pings the engine, nothing before the pong may start with `prefix`.
+    async fn expect_none_before_pong(gui: &mut ScriptedGui, prefix: &str, n: u32) {
+        gui.send(&format!("ping {n}")).await;
+        let lines = gui.expect(&format!("pong {n}"), PATIENT).await;
+        assert!(
+            lines.iter().all(|l| !l.starts_with(prefix)),
+            "Unexpected '{prefix}' in {lines:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn handshake_and_ping() {
+        let mut gui = ScriptedGui::start::<Xboard>().await;
+        gui.send("accepted usermove").await;
+        gui.send("ping 3").await;
+        assert_eq!(gui.expect("pong", PROMPT).await, ["pong 3"]);
+        gui.send("sing").await;
+        gui.expect("Error (unknown command): sing", PROMPT).await;
+        gui.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn plays_black_after_a_user_move() {
+        let mut gui = ScriptedGui::start::<Xboard>().await;
+        gui.send("new").await;
+        gui.send("sd 3").await;
+        gui.send("usermove e2e4").await;
+        gui.expect("move", PATIENT).await;
+        gui.send("usermove e2e4").await;
+        gui.expect("Illegal move: e2e4", PROMPT).await;
+        gui.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn force_mode_only_follows_the_moves() {
+        let mut gui = ScriptedGui::start::<Xboard>().await;
+        gui.send("new").await;
+        gui.send("force").await;
+        gui.send("usermove e2e4").await;
+        gui.send("usermove e7e5").await;
+        expect_none_before_pong(&mut gui, "move", 1).await;
+        gui.send("remove").await;
+        gui.send("undo").await;
+        // Both moves are taken back and there is nothing left to undo
+        gui.send("usermove d2d4").await;
+        gui.send("usermove d7d5").await;
+        expect_none_before_pong(&mut gui, "Illegal", 2).await;
+        gui.send("sd 2").await;
+        gui.send("go").await;
+        gui.expect("move", PATIENT).await;
+        gui.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn move_now_plays_the_best_move_so_far() {
+        let mut gui = ScriptedGui::start::<Xboard>().await;
+        gui.send("new").await;
+        gui.send("post").await;
+        gui.send("level 0 60 0").await;
+        gui.send("time 600000").await;
+        gui.send("otim 600000").await;
+        gui.send("go").await;
+        let lines = gui.expect("1 ", PATIENT).await;
+        assert!(lines.iter().all(|l| !l.starts_with("move")));
+        gui.send("?").await;
+        gui.expect("move", PATIENT).await;
+        gui.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mated_positions_end_the_game() {
+        let mut gui = ScriptedGui::start::<Xboard>().await;
+        gui.send("setboard 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").await;
+        gui.send("go").await;
+        gui.expect("1/2-1/2 {Stalemate}", PATIENT).await;
+        gui.send("new").await;
+        gui.send("force").await;
+        for m in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            gui.send(&format!("usermove {m}")).await;
+        }
+        gui.send("go").await;
+        gui.expect("0-1 {Black mates}", PATIENT).await;
+        gui.quit().await;
+    }
+}
@@ -0,0 +1,212 @@
+use guts::Position;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum XboardParseError {
+    #[error("unknown command")]
+    UnknownCommand,
+    #[error("invalid argument {0}")]
+    InvalidArgument(String),
+    #[error("missing argument")]
+    MissingArgument,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum XboardCommand {
+    Xboard,
+    Protover(u32),
+    New,
+    /// Play neither side, only keep track of the moves
+    Force,
+    /// Play the side to move and start thinking
+    Go,
+    UserMove(String),
+    Level {
+        /// 0 means the whole game has to be played in the base time
+        moves_per_session: u16,
+        base: Duration,
+        increment: Duration,
+    },
+    /// `st`, a fixed time for every move
+    MoveTime(Duration),
+    /// `sd`
+    Depth(u16),
+    /// Our own clock
+    Time(Duration),
+    /// The opponent's clock
+    OpponentTime(Duration),
+    Ping(String),
+    SetBoard(Position),
+    /// Take back one move
+    Undo,
+    /// Take back one move of each side
+    Remove,
+    /// `?`, play the best move found so far
+    MoveNow,
+    Post,
+    NoPost,
+    Quit,
+    /// Commands we understand but have nothing to do for
+    Ignored,
+}
+
+/// Every command with an argument has it after the first space.
+pub fn parse(line: &str) -> Result<XboardCommand, XboardParseError> {
+    let line = line.trim();
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+    let argument = argument.trim();
+    let required = || {
+        if argument.is_empty() {
+            Err(XboardParseError::MissingArgument)
+        } else {
+            Ok(argument)
+        }
+    };
+    let invalid = || XboardParseError::InvalidArgument(argument.to_owned());
+    Ok(match command {
+        "xboard" => XboardCommand::Xboard,
+        "protover" => XboardCommand::Protover(required()?.parse().map_err(|_| invalid())?),
+        "new" => XboardCommand::New,
+        "force" => XboardCommand::Force,
+        "go" => XboardCommand::Go,
+        "usermove" => XboardCommand::UserMove(required()?.to_owned()),
+        "level" => parse_level(required()?).ok_or_else(invalid)?,
+        "st" => XboardCommand::MoveTime(parse_seconds(required()?).ok_or_else(invalid)?),
+        "sd" => XboardCommand::Depth(required()?.parse().map_err(|_| invalid())?),
+        "time" => XboardCommand::Time(parse_centiseconds(required()?).ok_or_else(invalid)?),
+        "otim" => XboardCommand::OpponentTime(parse_centiseconds(required()?).ok_or_else(invalid)?),
+        "ping" => XboardCommand::Ping(required()?.to_owned()),
+        "setboard" => {
+            XboardCommand::SetBoard(Position::from_str(required()?).map_err(|_| invalid())?)
+        }
+        "undo" => XboardCommand::Undo,
+        "remove" => XboardCommand::Remove,
+        "?" => XboardCommand::MoveNow,
+        "post" => XboardCommand::Post,
+        "nopost" => XboardCommand::NoPost,
+        "quit" => XboardCommand::Quit,
+        "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating"
+        | "result" | "ics" | "draw" | "white" | "black" | "variant" | "." => XboardCommand::Ignored,
+        // Without the usermove feature moves come on their own
+        m if is_coordinate_move(m) && argument.is_empty() => XboardCommand::UserMove(m.to_owned()),
+        _ => return Err(XboardParseError::UnknownCommand),
+    })
+}
+
+/// `level 40 5 0` or `level 0 2:30 1.5`, the base time in minutes with optional seconds.
+fn parse_level(s: &str) -> Option<XboardCommand> {
+    let mut parts = s.split_whitespace();
+    let moves_per_session = parts.next()?.parse().ok()?;
+    let base = parts.next()?;
+    let base = match base.split_once(':') {
+        Some((minutes, seconds)) => {
+            60 * minutes.parse::<u64>().ok()? + seconds.parse::<u64>().ok()?
+        }
+        None => 60 * base.parse::<u64>().ok()?,
+    };
+    let base = Duration::from_secs(base);
+    let increment = parse_seconds(parts.next()?)?;
+    parts.next().is_none().then_some(XboardCommand::Level {
+        moves_per_session,
+        base,
+        increment,
+    })
+}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite() && *s >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+fn parse_centiseconds(s: &str) -> Option<Duration> {
+    // Clocks can run below zero when the flag isn't checked
+    s.parse::<i64>()
+        .ok()
+        .map(|cs| Duration::from_millis(10 * cs.max(0) as u64))
+}
+
+fn is_coordinate_move(s: &str) -> bool {
+    let b = s.as_bytes();
+    (b.len() == 4 || b.len() == 5)
+        && (b'a'..=b'h').contains(&b[0])
+        && (b'1'..=b'8').contains(&b[1])
+        && (b'a'..=b'h').contains(&b[2])
+        && (b'1'..=b'8').contains(&b[3])
+        && b.get(4).is_none_or(|p| b"qrbn".contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_commands() {
+        assert_eq!(parse("new"), Ok(XboardCommand::New));
+        assert_eq!(parse("protover 2"), Ok(XboardCommand::Protover(2)));
+        assert_eq!(parse("ping 17"), Ok(XboardCommand::Ping("17".to_owned())));
+        assert_eq!(parse("sd 6"), Ok(XboardCommand::Depth(6)));
+        assert_eq!(
+            parse("usermove e7e8q"),
+            Ok(XboardCommand::UserMove("e7e8q".to_owned()))
+        );
+        assert_eq!(
+            parse("e2e4"),
+            Ok(XboardCommand::UserMove("e2e4".to_owned()))
+        );
+        assert_eq!(parse("?"), Ok(XboardCommand::MoveNow));
+        assert_eq!(parse("accepted usermove"), Ok(XboardCommand::Ignored));
+        assert_eq!(parse("sing"), Err(XboardParseError::UnknownCommand));
+        assert_eq!(parse("sd"), Err(XboardParseError::MissingArgument));
+    }
+
+    #[test]
+    fn clocks() {
+        assert_eq!(
+            parse("time 12345"),
+            Ok(XboardCommand::Time(Duration::from_millis(123450)))
+        );
+        assert_eq!(
+            parse("otim -20"),
+            Ok(XboardCommand::OpponentTime(Duration::ZERO))
+        );
+        assert_eq!(
+            parse("st 2.5"),
+            Ok(XboardCommand::MoveTime(Duration::from_millis(2500)))
+        );
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(
+            parse("level 40 5 0"),
+            Ok(XboardCommand::Level {
+                moves_per_session: 40,
+                base: Duration::from_secs(300),
+                increment: Duration::ZERO,
+            })
+        );
+        assert_eq!(
+            parse("level 0 2:30 1.5"),
+            Ok(XboardCommand::Level {
+                moves_per_session: 0,
+                base: Duration::from_secs(150),
+                increment: Duration::from_millis(1500),
+            })
+        );
+        assert!(parse("level 40 5").is_err());
+    }
+
+    #[test]
+    fn setboard() {
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        assert_eq!(
+            parse(&format!("setboard {fen}")),
+            Ok(XboardCommand::SetBoard(Position::from_str(fen).unwrap()))
+        );
+        assert!(parse("setboard 4k3/8/8 w - - 0 1").is_err());
+    }
+}
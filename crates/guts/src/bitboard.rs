@@ -404,9 +404,8 @@ impl Iterator for BitboardIterator {
     type Item = Square;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.bitboard.first_set_square().map(|s| {
+        self.bitboard.first_set_square().inspect(|&s| {
             self.bitboard ^= Bitboard::from_square(s);
-            s
         })
     }
 
@@ -507,45 +506,36 @@ mod tests {
 
     #[test]
     fn krogge_stone_cardinal() {
-        let rooks = Bitboard::from_iter(
-            vec![
-                Square::new(File::E, Rank::R5),
-                Square::new(File::C, Rank::R8),
-            ]
-            .into_iter(),
-        );
-
-        let blockers = Bitboard::from_iter(
-            vec![
-                Square::new(File::B, Rank::R5),
-                Square::new(File::G, Rank::R5),
-                Square::new(File::E, Rank::R8),
-                Square::new(File::E, Rank::R3),
-                Square::new(File::C, Rank::R7),
-            ]
-            .into_iter(),
-        );
+        let rooks = Bitboard::from_iter(vec![
+            Square::new(File::E, Rank::R5),
+            Square::new(File::C, Rank::R8),
+        ]);
+
+        let blockers = Bitboard::from_iter(vec![
+            Square::new(File::B, Rank::R5),
+            Square::new(File::G, Rank::R5),
+            Square::new(File::E, Rank::R8),
+            Square::new(File::E, Rank::R3),
+            Square::new(File::C, Rank::R7),
+        ]);
         let empty = !blockers;
 
-        let expected_result = Bitboard::from_iter(
-            vec![
-                Square::new(File::B, Rank::R5),
-                Square::new(File::C, Rank::R5),
-                Square::new(File::D, Rank::R5),
-                Square::new(File::F, Rank::R5),
-                Square::new(File::G, Rank::R5),
-                Square::new(File::E, Rank::R3),
-                Square::new(File::E, Rank::R4),
-                Square::new(File::E, Rank::R6),
-                Square::new(File::E, Rank::R7),
-                Square::new(File::E, Rank::R8),
-                Square::new(File::A, Rank::R8),
-                Square::new(File::B, Rank::R8),
-                Square::new(File::C, Rank::R7),
-                Square::new(File::D, Rank::R8),
-            ]
-            .into_iter(),
-        );
+        let expected_result = Bitboard::from_iter(vec![
+            Square::new(File::B, Rank::R5),
+            Square::new(File::C, Rank::R5),
+            Square::new(File::D, Rank::R5),
+            Square::new(File::F, Rank::R5),
+            Square::new(File::G, Rank::R5),
+            Square::new(File::E, Rank::R3),
+            Square::new(File::E, Rank::R4),
+            Square::new(File::E, Rank::R6),
+            Square::new(File::E, Rank::R7),
+            Square::new(File::E, Rank::R8),
+            Square::new(File::A, Rank::R8),
+            Square::new(File::B, Rank::R8),
+            Square::new(File::C, Rank::R7),
+            Square::new(File::D, Rank::R8),
+        ]);
 
         assert_eq!(rooks.cardinal_attackers(empty), expected_result);
     }
@@ -554,32 +544,26 @@ mod tests {
     fn krogge_stone_diagonal() {
         let bishops = Bitboard::from_square(Square::new(File::E, Rank::R5));
 
-        let blockers = Bitboard::from_iter(
-            vec![
-                Square::new(File::C, Rank::R7),
-                Square::new(File::F, Rank::R6),
-                Square::new(File::E, Rank::R2),
-                Square::new(File::H, Rank::R2),
-            ]
-            .into_iter(),
-        );
+        let blockers = Bitboard::from_iter(vec![
+            Square::new(File::C, Rank::R7),
+            Square::new(File::F, Rank::R6),
+            Square::new(File::E, Rank::R2),
+            Square::new(File::H, Rank::R2),
+        ]);
         let empty = !blockers;
 
-        let expected_result = Bitboard::from_iter(
-            vec![
-                Square::new(File::C, Rank::R7),
-                Square::new(File::D, Rank::R6),
-                Square::new(File::F, Rank::R6),
-                Square::new(File::H, Rank::R2),
-                Square::new(File::G, Rank::R3),
-                Square::new(File::F, Rank::R4),
-                Square::new(File::A, Rank::R1),
-                Square::new(File::B, Rank::R2),
-                Square::new(File::C, Rank::R3),
-                Square::new(File::D, Rank::R4),
-            ]
-            .into_iter(),
-        );
+        let expected_result = Bitboard::from_iter(vec![
+            Square::new(File::C, Rank::R7),
+            Square::new(File::D, Rank::R6),
+            Square::new(File::F, Rank::R6),
+            Square::new(File::H, Rank::R2),
+            Square::new(File::G, Rank::R3),
+            Square::new(File::F, Rank::R4),
+            Square::new(File::A, Rank::R1),
+            Square::new(File::B, Rank::R2),
+            Square::new(File::C, Rank::R3),
+            Square::new(File::D, Rank::R4),
+        ]);
 
         assert_eq!(bishops.diagonal_attackers(empty), expected_result)
     }
@@ -591,14 +575,11 @@ mod tests {
         let empty =
             Bitboard(0b00000000_00001000_11111111_11110111_11111111_11111101_00000010_00000000);
 
-        let expected = Bitboard::from_iter(
-            vec![
-                Square::new(File::C, Rank::R1),
-                Square::new(File::B, Rank::R2),
-                Square::new(File::A, Rank::R3),
-            ]
-            .into_iter(),
-        );
+        let expected = Bitboard::from_iter(vec![
+            Square::new(File::C, Rank::R1),
+            Square::new(File::B, Rank::R2),
+            Square::new(File::A, Rank::R3),
+        ]);
 
         assert_eq!(from.nw_occluded(empty), expected)
     }
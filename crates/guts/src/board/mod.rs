@@ -168,7 +168,8 @@ impl FromStr for Board {
                     } else {
                         Color::Black
                     };
-                    ps[idx] = Some((piece, color));
+                    *ps.get_mut(idx)
+                        .ok_or(FenParseError::WrongNumberOfFiles(idx + 1))? = Some((piece, color));
                     idx += 1;
                 }
             }
@@ -261,4 +262,16 @@ mod tests {
 
         assert_eq!(Board::from_piece_array(&expected).piece_array(), expected)
     }
+
+    #[test]
+    fn overfull_rank_is_an_error() {
+        assert!(matches!(
+            Board::from_str("86Qb3q8P/8/8/8/8/8/8/8"),
+            Err(FenParseError::WrongNumberOfFiles(_))
+        ));
+        assert!(matches!(
+            Board::from_str("ppppppppp/8/8/8/8/8/8/8"),
+            Err(FenParseError::WrongNumberOfFiles(9))
+        ));
+    }
 }
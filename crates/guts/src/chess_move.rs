@@ -52,7 +52,7 @@ impl Move {
         let promotion_str = self
             .promotion
             .map(|p| p.to_string().to_ascii_lowercase())
-            .unwrap_or_else(|| "".to_string());
+            .unwrap_or_default();
         format!("{}{}{}", self.from, self.to, promotion_str)
     }
 
@@ -301,7 +301,7 @@ impl MoveGenerator {
                 {
                     let ep_square = ep_pawn.first_set_square().unwrap();
                     let mut all_pieces = position.board().all_pieces();
-                    all_pieces &= !Bitboard::from_iter([s, ep_square].into_iter());
+                    all_pieces &= !Bitboard::from_iter([s, ep_square]);
                     all_pieces |= target_bb;
                     let own_king = position.board()[position.active_color()][Piece::King];
                     let cardinal_attackers = position.board()[!position.active_color()]
@@ -514,6 +514,59 @@ impl MoveGenerator {
         KingSurroundings::new(checkers, Pins::new(pins), self.king_danger(position))
     }
 
+    /// The squares a knight on `s` attacks, regardless of what stands on them.
+    pub fn knight_attacks(&self, s: Square) -> Bitboard {
+        self.knight_patterns.get_move(s)
+    }
+
+    /// Pieces of both colors attacking `s`. Only pieces in `occupied` count and block, so pieces
+    /// can be taken off one by one to reveal the ones behind them.
+    pub fn attackers_to(&self, position: &Position, s: Square, occupied: Bitboard) -> Bitboard {
+        let target = Bitboard::from_square(s);
+        let empty = !occupied;
+        let cardinal_rays = target.cardinal_attackers(empty);
+        let diagonal_rays = target.diagonal_attackers(empty);
+        let mut attackers = Bitboard::EMPTY;
+        for color in Color::ALL {
+            let pieces = &position.board()[color];
+            // A pawn attacks `s` from where an opposing pawn on `s` would attack
+            let pawn_squares = target.forward_left_one(!color) | target.forward_right_one(!color);
+            attackers |= pawn_squares & pieces[Piece::Pawn];
+            attackers |= self.knight_attacks(s) & pieces[Piece::Knight];
+            attackers |= target.surrounding() & pieces[Piece::King];
+            attackers |= cardinal_rays & (pieces[Piece::Rook] | pieces[Piece::Queen]);
+            attackers |= diagonal_rays & (pieces[Piece::Bishop] | pieces[Piece::Queen]);
+        }
+        attackers & occupied
+    }
+
+    /// Whether the side to move is in check, without the cost of generating moves.
+    pub fn is_in_check(&self, position: &Position) -> bool {
+        let color = position.active_color();
+        let own_king = position.board()[color][Piece::King];
+        let Some(own_king_sq) = own_king.first_set_square() else {
+            return false;
+        };
+        let enemy_pieceboard = &position.board()[!color];
+
+        let knight_check =
+            self.knight_patterns.get_moves(own_king) & enemy_pieceboard[Piece::Knight];
+        let pawn_check = (own_king.forward_left_one(color) | own_king.forward_right_one(color))
+            & enemy_pieceboard[Piece::Pawn];
+        if (knight_check | pawn_check) != Bitboard::EMPTY {
+            return true;
+        }
+
+        let enemy_cardinal = enemy_pieceboard[Piece::Rook] | enemy_pieceboard[Piece::Queen];
+        let enemy_diagonal = enemy_pieceboard[Piece::Bishop] | enemy_pieceboard[Piece::Queen];
+        let attackers = (own_king.cardinal_attackers(Bitboard::FULL) & enemy_cardinal)
+            | (own_king.diagonal_attackers(Bitboard::FULL) & enemy_diagonal);
+        let occupied = position.board().all_pieces();
+        attackers
+            .into_iter()
+            .any(|s| self.squares_between.between(s, own_king_sq) & occupied == Bitboard::EMPTY)
+    }
+
     fn king_danger(&self, position: &Position) -> Bitboard {
         let opponent = !position.active_color();
 
@@ -821,6 +874,51 @@ mod tests {
         assert_eq!(generator.king_danger(&position), expected)
     }
 
+    #[test]
+    fn is_in_check_matches_move_generation() {
+        let generator = MoveGenerator::new();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+            "4k3/8/8/8/8/8/3n4/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/3p4/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/4p3/4K3 w - - 0 1",
+            "4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1",
+            "4k3/4r3/8/8/8/8/8/4K3 w - - 0 1",
+            "1r6/8/5q2/4P3/8/8/1K1B1r2/b4r2 w - - 0 1",
+        ] {
+            let position = Position::from_str(fen).unwrap();
+            let mut buf = BasicMoveBuffer::new();
+            let in_check = generator.generate_legal_moves_for(&position, &mut buf);
+            assert_eq!(generator.is_in_check(&position), in_check, "{fen}");
+        }
+    }
+
+    #[test]
+    fn attackers_to_sees_through_removed_pieces() {
+        let generator = MoveGenerator::new();
+        let position = Position::from_str("3qk3/8/3r4/2p5/3P4/4N3/8/3QK3 w - - 0 1").unwrap();
+        let d4 = Square::from_str("d4").unwrap();
+        let d5 = Square::from_str("d5").unwrap();
+        let squares = |names: &[&str]| {
+            Bitboard::from_iter(names.iter().map(|n| Square::from_str(n).unwrap()))
+        };
+        let occupied = position.board().all_pieces();
+        assert_eq!(
+            generator.attackers_to(&position, d5, occupied),
+            squares(&["d6", "e3"])
+        );
+        assert_eq!(
+            generator.attackers_to(&position, d4, occupied),
+            squares(&["c5", "d6", "d1"])
+        );
+        let without_rook = occupied & !squares(&["d6"]);
+        assert_eq!(
+            generator.attackers_to(&position, d5, without_rook),
+            squares(&["d8", "e3"])
+        );
+    }
+
     #[test]
     fn test_king_surroundings() {
         let generator = MoveGenerator::new();
@@ -828,13 +926,10 @@ mod tests {
         let position = Position::from_str("1r6/8/5q2/4P3/8/8/1K1B1r2/b4r2 w - - 0 1").unwrap();
         let surroundings = generator.king_surroundings(&position);
 
-        let expected_checkers = Bitboard::from_iter(
-            vec![
-                Square::new(File::B, Rank::R8),
-                Square::new(File::A, Rank::R1),
-            ]
-            .into_iter(),
-        );
+        let expected_checkers = Bitboard::from_iter(vec![
+            Square::new(File::B, Rank::R8),
+            Square::new(File::A, Rank::R1),
+        ]);
 
         let expected_pins = Pins::new(vec![
             Pin {
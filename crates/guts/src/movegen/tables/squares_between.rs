@@ -95,14 +95,11 @@ mod tests {
         let from = Square::new(File::F, Rank::R2);
         let to = Square::new(File::B, Rank::R2);
 
-        let expected = Bitboard::from_iter(
-            vec![
-                Square::new(File::C, Rank::R2),
-                Square::new(File::D, Rank::R2),
-                Square::new(File::E, Rank::R2),
-            ]
-            .into_iter(),
-        );
+        let expected = Bitboard::from_iter(vec![
+            Square::new(File::C, Rank::R2),
+            Square::new(File::D, Rank::R2),
+            Square::new(File::E, Rank::R2),
+        ]);
 
         assert_eq!(sb.between(from, to), expected)
     }
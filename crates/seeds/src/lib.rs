@@ -1,12 +1,25 @@
+use anyhow::{ensure, Result};
 use guts::Position;
 use itertools::Itertools;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 pub mod generate_tournament_openings;
+pub mod match_runner;
+pub mod nnue_training;
 pub mod pgn;
-pub mod pst_optimization;
 pub mod run_tournament;
+pub mod texel_tuning;
+
+/// How many of `len` positions to hold out for validation, `fraction` has to be in `0.0..1.0` so
+/// there is always something left to train on.
+pub(crate) fn validation_count(len: usize, fraction: f64) -> Result<usize> {
+    ensure!(
+        (0.0..1.0).contains(&fraction),
+        "Validation fraction {fraction} is not in 0.0..1.0"
+    );
+    Ok((len as f64 * fraction) as usize)
+}
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum GameResult {
@@ -65,11 +78,16 @@ impl FromStr for GameResult {
 pub struct AnnotatedPosition {
     pub pos: Position,
     pub result: GameResult,
+    /// A search score in centipawns for white, if one was recorded after the result.
+    pub score: Option<i32>,
 }
 
 impl Display for AnnotatedPosition {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{} {}", self.pos, self.result)
+        match self.score {
+            Some(score) => writeln!(f, "{} {} {score}", self.pos, self.result),
+            None => writeln!(f, "{} {}", self.pos, self.result),
+        }
     }
 }
 
@@ -77,14 +95,21 @@ impl FromStr for AnnotatedPosition {
     type Err = String;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let last = value
-            .split_whitespace()
-            .last()
-            .ok_or_else(|| "Got empty string when parsing annotated position".to_owned())?;
-        let result = GameResult::from_str(last)?;
+        let mut annotations = value.split_whitespace().skip(6);
+        let result = annotations
+            .next()
+            .ok_or_else(|| format!("Missing game result in annotated position {value}"))?;
+        let result = GameResult::from_str(result)?;
+        let score = annotations
+            .next()
+            .map(|s| {
+                s.parse::<i32>()
+                    .map_err(|e| format!("Invalid score in annotated position {value}: {e}"))
+            })
+            .transpose()?;
         let rest = value.split_whitespace().take(6).join(" ");
         let pos =
             Position::from_str(&rest).map_err(|e| format!("Fen parse error on {value}: {e}"))?;
-        Ok(Self { pos, result })
+        Ok(Self { pos, result, score })
     }
 }
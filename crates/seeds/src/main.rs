@@ -3,13 +3,18 @@ use clap::Parser;
 use clap::Subcommand;
 use rayon::ThreadPoolBuilder;
 use seeds::generate_tournament_openings::generate_tournament_openings;
+use seeds::match_runner::engine::EngineCommand;
+use seeds::match_runner::game::{GameSettings, ResignRule, TimeControl};
+use seeds::match_runner::{read_openings, run_match, MatchConfig, Standings};
+use seeds::nnue_training::TrainingConfig;
 use seeds::pgn::pgn_to_annotated_fen;
-use seeds::pst_optimization::train;
 use seeds::run_tournament::{run_tournament, IdAndFilename};
+use seeds::texel_tuning::TuningConfig;
 use seeds::AnnotatedPosition;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
@@ -33,13 +38,19 @@ enum Commands {
         #[clap(long, default_value_t = 0)]
         dropped_positions_end_of_game: usize,
     },
-    OptimizePST {
+    Tune {
         #[clap(short = 'i', long)]
         input_folder: PathBuf,
         #[clap(short = 'o', long)]
-        output_file: PathBuf,
-        #[clap(long)]
+        output_folder: PathBuf,
+        #[clap(long, default_value_t = 20)]
+        epochs: usize,
+        #[clap(long, default_value_t = 16384)]
+        batch_size: usize,
+        #[clap(long, default_value_t = 1.0)]
         learning_rate: f64,
+        #[clap(long, default_value_t = 0.1)]
+        validation_fraction: f64,
     },
     GenerateTournamentOpenings {
         #[clap(short = 'i', long)]
@@ -49,11 +60,59 @@ enum Commands {
         #[clap(long, default_value_t = 10)]
         number: usize,
     },
+    TrainNetwork {
+        #[clap(short = 'i', long)]
+        input_folder: PathBuf,
+        #[clap(short = 'o', long)]
+        output_folder: PathBuf,
+        #[clap(long, default_value_t = 10)]
+        epochs: usize,
+        #[clap(long, default_value_t = 16384)]
+        batch_size: usize,
+        #[clap(long, default_value_t = 0.001)]
+        learning_rate: f32,
+        #[clap(long, default_value_t = 0.05)]
+        validation_fraction: f64,
+        #[clap(long, default_value_t = 0.5)]
+        score_weight: f32,
+        #[clap(long)]
+        resume: Option<PathBuf>,
+    },
     RunTournament {
         hashes: Vec<IdAndFilename>,
         #[clap(short = 'o', long)]
         output_folder: PathBuf,
     },
+    PlayMatch {
+        /// `name=path` or only the path, a single engine plays against itself
+        #[clap(required = true)]
+        engines: Vec<EngineCommand>,
+        /// EPD, or PGN when the file ends in `.pgn`
+        #[clap(long)]
+        openings: PathBuf,
+        #[clap(long, default_value_t = 10)]
+        plies: usize,
+        #[clap(long, default_value = "40/10+0.1")]
+        tc: TimeControl,
+        #[clap(long, default_value_t = 10)]
+        rounds: usize,
+        #[clap(long, default_value_t = 8)]
+        concurrency: usize,
+        #[clap(long)]
+        max_moves: Option<u32>,
+        /// Centipawns a side has to be behind by for `resign_moves` moves in a row to lose
+        #[clap(long)]
+        resign_score: Option<i32>,
+        #[clap(long, default_value_t = 3)]
+        resign_moves: u32,
+        #[clap(long, default_value_t = 1)]
+        seed: u64,
+        #[clap(short = 'o', long, default_value = "match.pgn")]
+        pgn_out: PathBuf,
+        /// Where the engines' stderr goes
+        #[clap(long)]
+        log_folder: Option<PathBuf>,
+    },
 }
 
 fn main() -> Result<()> {
@@ -78,20 +137,86 @@ fn main() -> Result<()> {
             dropped_positions_start_of_game,
             dropped_positions_end_of_game,
         ),
-        Commands::OptimizePST {
+        Commands::Tune {
             input_folder,
-            output_file,
+            output_folder,
+            epochs,
+            batch_size,
             learning_rate,
-        } => optimize(input_folder, output_file, learning_rate),
+            validation_fraction,
+        } => tune(
+            input_folder,
+            TuningConfig {
+                epochs,
+                batch_size,
+                learning_rate,
+                validation_fraction,
+                output_folder,
+            },
+        ),
         Commands::GenerateTournamentOpenings {
             input_folder,
             output_file,
             number,
         } => generate_openings(input_folder, output_file, number),
+        Commands::TrainNetwork {
+            input_folder,
+            output_folder,
+            epochs,
+            batch_size,
+            learning_rate,
+            validation_fraction,
+            score_weight,
+            resume,
+        } => train_network(
+            input_folder,
+            TrainingConfig {
+                epochs,
+                batch_size,
+                learning_rate,
+                validation_fraction,
+                score_weight,
+                output_folder,
+                resume,
+            },
+        ),
         Commands::RunTournament {
             hashes,
             output_folder,
         } => do_run_tournament(hashes, output_folder),
+        Commands::PlayMatch {
+            engines,
+            openings,
+            plies,
+            tc,
+            rounds,
+            concurrency,
+            max_moves,
+            resign_score,
+            resign_moves,
+            seed,
+            pgn_out,
+            log_folder,
+        } => play_match(
+            MatchConfig {
+                engines,
+                openings: read_openings(&openings, plies)?,
+                rounds,
+                concurrency,
+                game: GameSettings {
+                    time_control: tc,
+                    max_moves,
+                    resign: resign_score.map(|score| ResignRule {
+                        move_count: resign_moves,
+                        score,
+                    }),
+                    time_margin: Duration::from_millis(100),
+                },
+                seed,
+                log_folder,
+            },
+            pgn_out,
+        ),
     }
 }
 
@@ -136,7 +261,7 @@ fn convert(
     Ok(())
 }
 
-fn optimize(input_folder: PathBuf, output_file: PathBuf, learning_rate: f64) -> Result<()> {
+fn tune(input_folder: PathBuf, config: TuningConfig) -> Result<()> {
     println!("Loading annotated FENs...");
     let files = std::fs::read_dir(input_folder)?;
     let mut training_set = Vec::new();
@@ -153,15 +278,12 @@ fn optimize(input_folder: PathBuf, output_file: PathBuf, learning_rate: f64) ->
             .lines()
             .map(|s| AnnotatedPosition::from_str(s).map_err(|s| anyhow!("{}", s)))
             .collect::<Result<Vec<_>>>()?;
-        training_set.extend(fens.into_iter());
+        training_set.extend(fens);
     }
 
-    println!("Training...");
-    let coefficients = train(learning_rate, training_set);
-
-    let serialized = bincode::serialize(&coefficients)?;
-    std::fs::File::create(output_file)?.write_all(&serialized)?;
-    println!("Done optimizing, data written");
+    println!("Tuning...");
+    seeds::texel_tuning::tune(&config, training_set)?;
+    println!("Done tuning, data written");
 
     Ok(())
 }
@@ -198,6 +320,33 @@ fn generate_openings(input_folder: PathBuf, output_file: PathBuf, number: usize)
     Ok(())
 }
 
+fn train_network(input_folder: PathBuf, config: TrainingConfig) -> Result<()> {
+    println!("Loading annotated FENs...");
+    let files = std::fs::read_dir(input_folder)?;
+    let mut positions = Vec::new();
+    for dir_entry in files {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        let mut fens = String::new();
+        let mut source = std::fs::File::open(path)?;
+        let _ = source.read_to_string(&mut fens);
+        let fens = fens
+            .lines()
+            .map(|s| AnnotatedPosition::from_str(s).map_err(|s| anyhow!("{}", s)))
+            .collect::<Result<Vec<_>>>()?;
+        positions.extend(fens);
+    }
+
+    println!("Training...");
+    seeds::nnue_training::train(&config, positions)?;
+    println!("Done training, network written");
+
+    Ok(())
+}
+
 fn do_run_tournament(mut hashes: Vec<IdAndFilename>, output_folder: PathBuf) -> Result<()> {
     if !hashes.iter().any(|i| i.name == "main") {
         hashes.push(IdAndFilename {
@@ -208,3 +357,9 @@ fn do_run_tournament(mut hashes: Vec<IdAndFilename>, output_folder: PathBuf) ->
     run_tournament(&hashes, output_folder)?;
     Ok(())
 }
+
+fn play_match(config: MatchConfig, pgn_out: PathBuf) -> Result<()> {
+    let games = run_match(&config, "Match", &pgn_out)?;
+    println!("{}", Standings::new(&games));
+    Ok(())
+}
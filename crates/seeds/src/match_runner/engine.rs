@@ -0,0 +1,303 @@
+use crate::match_runner::game::{MoveRequest, Player, PlayerMove};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Engines get this long to start up and to answer `isready`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Mate scores are reported as centipawns beyond any real evaluation.
+const MATE_SCORE: i32 = 100_000;
+
+/// How to start an engine, `name=command` or only the command, then its file name is the name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EngineCommand {
+    pub name: String,
+    pub command: PathBuf,
+    /// UCI options sent after the handshake
+    pub options: Vec<(String, String)>,
+}
+
+impl FromStr for EngineCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, command) = match s.split_once('=') {
+            Some((name, command)) => (name.to_owned(), PathBuf::from(command)),
+            None => {
+                let command = PathBuf::from(s);
+                let name = command
+                    .file_name()
+                    .ok_or_else(|| format!("Could not find an engine name in {s}"))?
+                    .to_string_lossy()
+                    .into_owned();
+                (name, command)
+            }
+        };
+        Ok(Self {
+            name,
+            command,
+            options: Vec::new(),
+        })
+    }
+}
+
+/// An engine process spoken to over UCI. It's told to quit, or killed, when dropped.
+pub struct UciEngine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    /// Starts the engine and waits until it's ready, its stderr is appended to `stderr_log`.
+    pub fn start(command: &EngineCommand, stderr_log: Option<&Path>) -> Result<Self> {
+        let stderr = match stderr_log {
+            Some(path) => Stdio::from(OpenOptions::new().create(true).append(true).open(path)?),
+            None => Stdio::null(),
+        };
+        let mut child = Command::new(&command.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr)
+            .spawn()
+            .map_err(|e| anyhow!("Could not start {}: {e}", command.command.display()))?;
+        let stdin = child.stdin.take().expect("Stdin is piped");
+        let stdout = child.stdout.take().expect("Stdout is piped");
+
+        // Reads can't time out, so they happen on a thread of their own that ends with the engine
+        let (tx, lines) = mpsc::channel();
+        thread::Builder::new()
+            .name(format!("{}-stdout", command.name))
+            .spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(|l| l.ok()) {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        let mut engine = Self {
+            name: command.name.clone(),
+            child,
+            stdin,
+            lines,
+        };
+        engine.send("uci")?;
+        engine.wait_for("uciok", HANDSHAKE_TIMEOUT)?;
+        for (name, value) in &command.options {
+            engine.send(&format!("setoption name {name} value {value}"))?;
+        }
+        engine.ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        writeln!(self.stdin, "{line}")
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| anyhow!("{} stopped listening: {e}", self.name))
+    }
+
+    /// Skips lines until one starts with `prefix`.
+    fn wait_for(&mut self, prefix: &str, timeout: Duration) -> Result<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let line = self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|e| anyhow!("No {prefix} from {}: {e}", self.name))?;
+            if line.starts_with(prefix) {
+                return Ok(line);
+            }
+        }
+    }
+
+    fn ready(&mut self) -> Result<()> {
+        self.send("isready")?;
+        self.wait_for("readyok", HANDSHAKE_TIMEOUT).map(|_| ())
+    }
+}
+
+impl Player for UciEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<()> {
+        self.send("ucinewgame")?;
+        self.ready()
+    }
+
+    fn best_move(&mut self, request: &MoveRequest) -> Result<Option<PlayerMove>> {
+        let deadline = Instant::now() + request.deadline;
+        self.send(&position_command(request))?;
+        self.send(&go_command(request))?;
+        let mut score = None;
+        let mut depth = None;
+        loop {
+            let line = match self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("{} exited while thinking", self.name))
+                }
+            };
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    let (info_score, info_depth) = parse_info(&line);
+                    score = info_score.or(score);
+                    depth = info_depth.or(depth);
+                }
+                Some("bestmove") => {
+                    let uci = tokens
+                        .next()
+                        .ok_or_else(|| anyhow!("{} sent an empty bestmove", self.name))?;
+                    return Ok(Some(PlayerMove {
+                        uci: uci.to_owned(),
+                        score,
+                        depth,
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn position_command(request: &MoveRequest) -> String {
+    let mut command = format!("position fen {}", request.start);
+    if !request.moves.is_empty() {
+        command.push_str(" moves ");
+        command.push_str(&request.moves.iter().join(" "));
+    }
+    command
+}
+
+fn go_command(request: &MoveRequest) -> String {
+    let increment = request.increment.as_millis();
+    let mut command = format!(
+        "go wtime {} btime {} winc {increment} binc {increment}",
+        request.white_time.as_millis(),
+        request.black_time.as_millis(),
+    );
+    if let Some(moves_to_go) = request.moves_to_go {
+        command.push_str(&format!(" movestogo {moves_to_go}"));
+    }
+    command
+}
+
+/// The score in centipawns and the depth of an `info` line, mates count as huge scores.
+fn parse_info(line: &str) -> (Option<i32>, Option<u32>) {
+    let tokens = line.split_whitespace().collect_vec();
+    let value_after = |key: &str| {
+        tokens
+            .iter()
+            .position(|&t| t == key)
+            .and_then(|i| tokens.get(i + 1))
+    };
+    let depth = value_after("depth").and_then(|d| d.parse().ok());
+    let score = tokens.iter().position(|&t| t == "score").and_then(|i| {
+        match (tokens.get(i + 1), tokens.get(i + 2)) {
+            (Some(&"cp"), Some(cp)) => cp.parse().ok(),
+            (Some(&"mate"), Some(moves)) => moves.parse::<i32>().ok().map(|moves| {
+                if moves > 0 {
+                    MATE_SCORE - moves
+                } else {
+                    -MATE_SCORE - moves
+                }
+            }),
+            _ => None,
+        }
+    });
+    (score, depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guts::Position;
+
+    #[test]
+    fn engine_commands() {
+        assert_eq!(
+            EngineCommand::from_str("new=./target/release/chessatiel"),
+            Ok(EngineCommand {
+                name: "new".to_owned(),
+                command: PathBuf::from("./target/release/chessatiel"),
+                options: Vec::new(),
+            })
+        );
+        assert_eq!(
+            EngineCommand::from_str("bin/main").unwrap().name,
+            "main".to_owned()
+        );
+    }
+
+    #[test]
+    fn uci_commands() {
+        let start = Position::default();
+        let moves = ["e2e4".to_owned(), "e7e5".to_owned()];
+        let request = MoveRequest {
+            start: &start,
+            moves: &moves,
+            white_time: Duration::from_millis(1500),
+            black_time: Duration::from_secs(2),
+            increment: Duration::from_millis(100),
+            moves_to_go: Some(39),
+            deadline: Duration::from_secs(2),
+        };
+        assert_eq!(
+            position_command(&request),
+            "position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves e2e4 e7e5"
+        );
+        assert_eq!(
+            go_command(&request),
+            "go wtime 1500 btime 2000 winc 100 binc 100 movestogo 39"
+        );
+    }
+
+    #[test]
+    fn info_lines() {
+        assert_eq!(
+            parse_info("info depth 7 seldepth 12 multipv 1 score cp -35 nodes 1000 pv e2e4"),
+            (Some(-35), Some(7))
+        );
+        assert_eq!(
+            parse_info("info depth 9 score mate 3 pv h1h8"),
+            (Some(MATE_SCORE - 3), Some(9))
+        );
+        assert_eq!(
+            parse_info("info depth 9 score mate -2"),
+            (Some(-MATE_SCORE + 2), Some(9))
+        );
+        assert_eq!(parse_info("info nps 12345"), (None, None));
+    }
+}
@@ -0,0 +1,512 @@
+use crate::match_runner::record::{GameRecord, MoveRecord, Termination};
+use crate::GameResult;
+use anyhow::{anyhow, Result};
+use guts::{BasicMoveBuffer, Color, MoveGenerator, Piece, Position};
+use itertools::Itertools;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// What an engine reported with its move, scores are from its own point of view.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PlayerMove {
+    pub uci: String,
+    pub score: Option<i32>,
+    pub depth: Option<u32>,
+}
+
+/// Everything a player needs to pick a move.
+#[derive(Debug)]
+pub struct MoveRequest<'a> {
+    pub start: &'a Position,
+    pub moves: &'a [String],
+    pub white_time: Duration,
+    pub black_time: Duration,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    /// After this the move is too late anyway
+    pub deadline: Duration,
+}
+
+pub trait Player {
+    fn name(&self) -> &str;
+
+    fn new_game(&mut self) -> Result<()>;
+
+    /// `None` when no move came before the deadline, errors mean the player is gone.
+    fn best_move(&mut self, request: &MoveRequest) -> Result<Option<PlayerMove>>;
+}
+
+/// `moves/time+increment` like cutechess, the time in seconds or as `minutes:seconds`. The moves
+/// are optional and reset the clock by adding the base time again, the increment defaults to 0.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimeControl {
+    pub moves: Option<u32>,
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time control {s}, expected moves/time+increment");
+        let (moves, rest) = match s.split_once('/') {
+            Some((moves, rest)) => (Some(moves.parse().map_err(|_| invalid())?), rest),
+            None => (None, s),
+        };
+        let (base, increment) = rest.split_once('+').unwrap_or((rest, "0"));
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) => {
+                60.0 * minutes.parse::<f64>().map_err(|_| invalid())?
+                    + seconds.parse::<f64>().map_err(|_| invalid())?
+            }
+            None => base.parse().map_err(|_| invalid())?,
+        };
+        let increment = increment.parse::<f64>().map_err(|_| invalid())?;
+        let to_duration =
+            |seconds: f64| Duration::try_from_secs_f64(seconds).map_err(|_| invalid());
+        Ok(Self {
+            moves: moves.filter(|&m| m > 0),
+            base: to_duration(base)?,
+            increment: to_duration(increment)?,
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(moves) = self.moves {
+            write!(f, "{moves}/")?;
+        }
+        write!(
+            f,
+            "{}+{}",
+            self.base.as_secs_f64(),
+            self.increment.as_secs_f64()
+        )
+    }
+}
+
+/// Adjudicates a loss for a side whose own score stays at or below `-score` for `move_count`
+/// consecutive moves.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResignRule {
+    pub move_count: u32,
+    pub score: i32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GameSettings {
+    pub time_control: TimeControl,
+    /// Moves by each side after which the game is a draw
+    pub max_moves: Option<u32>,
+    pub resign: Option<ResignRule>,
+    /// How late a move may be before it loses on time
+    pub time_margin: Duration,
+}
+
+struct Clock {
+    remaining: Duration,
+    moves: u32,
+}
+
+/// Plays one game from `start`, adjudicating by the rules and `settings`. Players that fail or
+/// send illegal moves lose the game instead of failing it.
+pub fn play_game(
+    white: &mut dyn Player,
+    black: &mut dyn Player,
+    start: &Position,
+    settings: &GameSettings,
+) -> GameRecord {
+    let ready = white
+        .new_game()
+        .map_err(|e| (Color::White, e))
+        .and_then(|_| black.new_game().map_err(|e| (Color::Black, e)));
+    if let Err((loser, e)) = ready {
+        return GameRecord::abandoned(white.name(), black.name(), start, loser, e.to_string());
+    }
+
+    let move_generator = MoveGenerator::new();
+    let time_control = settings.time_control;
+    let mut position = start.clone();
+    let mut hashes = vec![position.hash()];
+    let mut uci_moves = Vec::new();
+    let mut moves = Vec::new();
+    let mut clocks = [Color::White, Color::Black].map(|_| Clock {
+        remaining: time_control.base,
+        moves: 0,
+    });
+    let mut losing_streaks = [0, 0];
+
+    let (result, termination) = loop {
+        let mover = position.active_color();
+        let side = usize::from(mover);
+        let mut legal_moves = BasicMoveBuffer::new();
+        let in_check = move_generator.generate_legal_moves_for(&position, &mut legal_moves);
+        if legal_moves.is_empty() {
+            break if in_check {
+                (win_for(!mover), Termination::Checkmate)
+            } else {
+                (GameResult::Draw, Termination::Stalemate)
+            };
+        }
+        if position.halfmove_clock() >= 100 {
+            break (GameResult::Draw, Termination::FiftyMoves);
+        }
+        if hashes.iter().filter(|&&h| h == position.hash()).count() >= 3 {
+            break (GameResult::Draw, Termination::Repetition);
+        }
+        if insufficient_material(&position) {
+            break (GameResult::Draw, Termination::InsufficientMaterial);
+        }
+        if settings
+            .max_moves
+            .is_some_and(|max| clocks.iter().all(|c| c.moves >= max))
+        {
+            break (GameResult::Draw, Termination::MaxMoves);
+        }
+
+        let player: &mut dyn Player = match mover {
+            Color::White => &mut *white,
+            Color::Black => &mut *black,
+        };
+        let remaining = clocks[side].remaining;
+        let request = MoveRequest {
+            start,
+            moves: &uci_moves,
+            white_time: clocks[usize::from(Color::White)].remaining,
+            black_time: clocks[usize::from(Color::Black)].remaining,
+            increment: time_control.increment,
+            moves_to_go: time_control
+                .moves
+                .map(|moves| moves - clocks[side].moves % moves),
+            deadline: remaining + settings.time_margin,
+        };
+        let started = Instant::now();
+        let player_move = match player.best_move(&request) {
+            Ok(Some(player_move)) => player_move,
+            Ok(None) => break (win_for(!mover), Termination::TimeForfeit),
+            Err(e) => break (win_for(!mover), Termination::Abandoned(e.to_string())),
+        };
+        let elapsed = started.elapsed();
+        if elapsed > request.deadline {
+            break (win_for(!mover), Termination::TimeForfeit);
+        }
+
+        let clock = &mut clocks[side];
+        clock.remaining = remaining.saturating_sub(elapsed) + time_control.increment;
+        clock.moves += 1;
+        if time_control.moves.is_some_and(|m| clock.moves % m == 0) {
+            clock.remaining += time_control.base;
+        }
+
+        let Some(m) = legal_moves
+            .iter()
+            .find(|m| m.as_uci() == player_move.uci)
+            .cloned()
+        else {
+            break (win_for(!mover), Termination::IllegalMove(player_move.uci));
+        };
+        moves.push(MoveRecord::new(
+            &position,
+            &m,
+            &move_generator,
+            player_move.score,
+            player_move.depth,
+            elapsed,
+        ));
+        uci_moves.push(player_move.uci);
+        position.make_move(&m);
+        hashes.push(position.hash());
+
+        if let Some(rule) = settings.resign {
+            let streak = &mut losing_streaks[side];
+            *streak = match player_move.score {
+                Some(score) if score <= -rule.score => *streak + 1,
+                _ => 0,
+            };
+            if *streak >= rule.move_count {
+                break (win_for(!mover), Termination::Resignation);
+            }
+        }
+    };
+
+    GameRecord {
+        white: white.name().to_owned(),
+        black: black.name().to_owned(),
+        start: start.clone(),
+        moves,
+        result,
+        termination,
+        final_position: position,
+    }
+}
+
+pub(crate) fn win_for(color: Color) -> GameResult {
+    match color {
+        Color::White => GameResult::White,
+        Color::Black => GameResult::Black,
+    }
+}
+
+/// Bare kings, or a single knight or bishop against a bare king.
+fn insufficient_material(position: &Position) -> bool {
+    let board = position.board();
+    let count = |piece| {
+        [Color::White, Color::Black]
+            .iter()
+            .map(|&c| board[c][piece].count_ones())
+            .sum::<u32>()
+    };
+    let heavy = [Piece::Pawn, Piece::Rook, Piece::Queen]
+        .into_iter()
+        .map(count)
+        .sum::<u32>();
+    heavy == 0 && count(Piece::Knight) + count(Piece::Bishop) <= 1
+}
+
+/// A player that doesn't think, for tests and for playing out scripted lines.
+pub struct ScriptedPlayer {
+    name: String,
+    moves: Vec<String>,
+    score: Option<i32>,
+    move_generator: MoveGenerator,
+}
+
+impl ScriptedPlayer {
+    /// Plays `moves` in order as long as they last, then the first legal move.
+    pub fn new(name: &str, moves: &[&str]) -> Self {
+        Self {
+            name: name.to_owned(),
+            moves: moves.iter().rev().map(|m| m.to_string()).collect_vec(),
+            score: None,
+            move_generator: MoveGenerator::new(),
+        }
+    }
+
+    pub fn with_score(mut self, score: i32) -> Self {
+        self.score = Some(score);
+        self
+    }
+}
+
+impl Player for ScriptedPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_move(&mut self, request: &MoveRequest) -> Result<Option<PlayerMove>> {
+        let uci = match self.moves.pop() {
+            Some(m) => m,
+            None => {
+                let mut position = request.start.clone();
+                for m in request.moves {
+                    let mut buf = BasicMoveBuffer::new();
+                    self.move_generator
+                        .generate_legal_moves_for(&position, &mut buf);
+                    let m = buf
+                        .iter()
+                        .find(|l| &l.as_uci() == m)
+                        .ok_or_else(|| anyhow!("Illegal move {m} in the request"))?
+                        .clone();
+                    position.make_move(&m);
+                }
+                let mut buf = BasicMoveBuffer::new();
+                self.move_generator
+                    .generate_legal_moves_for(&position, &mut buf);
+                let first = buf
+                    .iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("Asked for a move without legal moves"))?
+                    .as_uci();
+                first
+            }
+        };
+        Ok(Some(PlayerMove {
+            uci,
+            score: self.score,
+            depth: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOOLS_MATE_WHITE: [&str; 2] = ["f2f3", "g2g4"];
+    const FOOLS_MATE_BLACK: [&str; 2] = ["e7e5", "d8h4"];
+
+    fn settings() -> GameSettings {
+        GameSettings {
+            time_control: TimeControl::from_str("40/10+0.1").unwrap(),
+            max_moves: None,
+            resign: None,
+            time_margin: Duration::from_millis(50),
+        }
+    }
+
+    /// Never answers in time.
+    struct Sleeper;
+
+    impl Player for Sleeper {
+        fn name(&self) -> &str {
+            "sleeper"
+        }
+
+        fn new_game(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn best_move(&mut self, request: &MoveRequest) -> Result<Option<PlayerMove>> {
+            std::thread::sleep(request.deadline);
+            Ok(None)
+        }
+    }
+
+    /// Gone before the game starts.
+    struct Crashed;
+
+    impl Player for Crashed {
+        fn name(&self) -> &str {
+            "crashed"
+        }
+
+        fn new_game(&mut self) -> Result<()> {
+            Err(anyhow!("Engine exited"))
+        }
+
+        fn best_move(&mut self, _: &MoveRequest) -> Result<Option<PlayerMove>> {
+            Err(anyhow!("Engine exited"))
+        }
+    }
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(
+            TimeControl::from_str("150/1+1"),
+            Ok(TimeControl {
+                moves: Some(150),
+                base: Duration::from_secs(1),
+                increment: Duration::from_secs(1),
+            })
+        );
+        let tc = TimeControl::from_str("1:30+0.5").unwrap();
+        assert_eq!(tc.base, Duration::from_secs(90));
+        assert_eq!(tc.increment, Duration::from_millis(500));
+        assert_eq!(tc.to_string(), "90+0.5");
+        assert_eq!(
+            TimeControl::from_str("10").unwrap().increment,
+            Duration::ZERO
+        );
+        assert!(TimeControl::from_str("x/10+1").is_err());
+        assert!(TimeControl::from_str("-5+1").is_err());
+    }
+
+    #[test]
+    fn checkmate_ends_the_game() {
+        let mut white = ScriptedPlayer::new("white", &FOOLS_MATE_WHITE);
+        let mut black = ScriptedPlayer::new("black", &FOOLS_MATE_BLACK);
+        let record = play_game(&mut white, &mut black, &Position::default(), &settings());
+        assert_eq!(record.result, GameResult::Black);
+        assert_eq!(record.termination, Termination::Checkmate);
+        assert_eq!(record.moves.len(), 4);
+    }
+
+    #[test]
+    fn rule_draws() {
+        let kings_and_knight = Position::from_str("8/8/4k3/8/8/3NK3/8/8 b - - 0 1").unwrap();
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[]),
+            &mut ScriptedPlayer::new("b", &[]),
+            &kings_and_knight,
+            &settings(),
+        );
+        assert_eq!(record.termination, Termination::InsufficientMaterial);
+        assert!(record.moves.is_empty());
+
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[shuffle[0], shuffle[2], shuffle[0], shuffle[2]]),
+            &mut ScriptedPlayer::new("b", &[shuffle[1], shuffle[3], shuffle[1], shuffle[3]]),
+            &Position::default(),
+            &settings(),
+        );
+        assert_eq!(record.result, GameResult::Draw);
+        assert_eq!(record.termination, Termination::Repetition);
+        assert_eq!(record.moves.len(), 8);
+
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[]),
+            &mut ScriptedPlayer::new("b", &[]),
+            &Position::default(),
+            &GameSettings {
+                max_moves: Some(5),
+                ..settings()
+            },
+        );
+        assert_eq!(record.termination, Termination::MaxMoves);
+        assert_eq!(record.moves.len(), 10);
+    }
+
+    #[test]
+    fn losses_by_adjudication() {
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &["e2e5"]),
+            &mut ScriptedPlayer::new("b", &[]),
+            &Position::default(),
+            &settings(),
+        );
+        assert_eq!(record.result, GameResult::Black);
+        assert_eq!(
+            record.termination,
+            Termination::IllegalMove("e2e5".to_owned())
+        );
+
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[]),
+            &mut Sleeper,
+            &Position::default(),
+            &GameSettings {
+                time_control: TimeControl::from_str("0.05").unwrap(),
+                ..settings()
+            },
+        );
+        assert_eq!(record.result, GameResult::White);
+        assert_eq!(record.termination, Termination::TimeForfeit);
+
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[]).with_score(50),
+            &mut ScriptedPlayer::new("b", &[]).with_score(-1200),
+            &Position::default(),
+            &GameSettings {
+                resign: Some(ResignRule {
+                    move_count: 3,
+                    score: 1000,
+                }),
+                ..settings()
+            },
+        );
+        assert_eq!(record.result, GameResult::White);
+        assert_eq!(record.termination, Termination::Resignation);
+        assert_eq!(record.moves.len(), 6);
+
+        let record = play_game(
+            &mut ScriptedPlayer::new("a", &[]),
+            &mut Crashed,
+            &Position::default(),
+            &settings(),
+        );
+        assert_eq!(record.result, GameResult::White);
+        assert_eq!(
+            record.termination,
+            Termination::Abandoned("Engine exited".to_owned())
+        );
+        assert!(record.moves.is_empty());
+    }
+}
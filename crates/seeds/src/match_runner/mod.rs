@@ -0,0 +1,415 @@
+use crate::match_runner::engine::{EngineCommand, UciEngine};
+use crate::match_runner::game::{play_game, GameSettings};
+use crate::match_runner::record::GameRecord;
+use crate::pgn::pgn_to_openings;
+use crate::GameResult;
+use anyhow::{anyhow, Result};
+use guts::{Color, Position};
+use itertools::Itertools;
+use rand::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub mod engine;
+pub mod game;
+pub mod record;
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    pub engines: Vec<EngineCommand>,
+    pub openings: Vec<Position>,
+    /// Every round plays one opening twice with reversed colors for each pair of engines
+    pub rounds: usize,
+    pub concurrency: usize,
+    pub game: GameSettings,
+    /// Shuffles the openings
+    pub seed: u64,
+    /// Engine stderr goes to `<name>.log` in here
+    pub log_folder: Option<PathBuf>,
+}
+
+/// One game of the schedule, as indices into the engines and openings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Pairing {
+    pub round: usize,
+    pub white: usize,
+    pub black: usize,
+    pub opening: usize,
+}
+
+/// Round robin games where every pair plays the round's opening with both colors. A single
+/// engine plays against itself.
+pub fn schedule(
+    engine_count: usize,
+    opening_count: usize,
+    rounds: usize,
+    seed: u64,
+) -> Vec<Pairing> {
+    let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(seed);
+    let mut openings = (0..opening_count).collect_vec();
+    openings.shuffle(&mut rng);
+    let pairs = if engine_count == 1 {
+        vec![(0, 0)]
+    } else {
+        (0..engine_count).tuple_combinations().collect_vec()
+    };
+    (0..rounds)
+        .flat_map(|round| {
+            let opening = openings[round % opening_count];
+            pairs.iter().flat_map(move |&(a, b)| {
+                [(a, b), (b, a)].map(|(white, black)| Pairing {
+                    round,
+                    white,
+                    black,
+                    opening,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Plays the whole schedule, `concurrency` games at a time with fresh engine processes for every
+/// game. Every game is appended to `pgn_out` as soon as it ends, so an interrupted match keeps the
+/// games it finished. The games are returned in schedule order.
+pub fn run_match(config: &MatchConfig, event: &str, pgn_out: &Path) -> Result<Vec<GameRecord>> {
+    if config.engines.is_empty() {
+        return Err(anyhow!("A match needs at least one engine"));
+    }
+    if config.openings.is_empty() {
+        return Err(anyhow!("A match needs at least one opening"));
+    }
+    let pairings = schedule(
+        config.engines.len(),
+        config.openings.len(),
+        config.rounds,
+        config.seed,
+    );
+    let total = pairings.len();
+    let games_per_round = total.div_ceil(config.rounds.max(1)).max(1);
+    let pgn = Mutex::new(File::create(pgn_out)?);
+    println!(
+        "Playing {total} games between {} at {}",
+        config.engines.iter().map(|e| &e.name).join(", "),
+        config.game.time_control
+    );
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(config.concurrency)
+        .thread_name(|idx| format!("match-{idx}"))
+        .build()?;
+    pool.install(|| {
+        pairings
+            .par_iter()
+            .enumerate()
+            .map(|(number, pairing)| {
+                let record = play_pairing(config, pairing);
+                println!(
+                    "Game {} of {total}: {} vs {}, {} ({})",
+                    number + 1,
+                    record.white,
+                    record.black,
+                    record.result,
+                    record.termination
+                );
+                let game = game_pgn(event, &record, number, games_per_round);
+                pgn.lock()
+                    .expect("Only PGN writes hold the lock")
+                    .write_all(game.as_bytes())?;
+                Ok(record)
+            })
+            .collect()
+    })
+}
+
+/// An engine that fails to start loses the game, like one that crashes during it.
+fn play_pairing(config: &MatchConfig, pairing: &Pairing) -> GameRecord {
+    let start = |index: usize| {
+        let command = &config.engines[index];
+        let log = config
+            .log_folder
+            .as_ref()
+            .map(|folder| folder.join(format!("{}.log", command.name)));
+        UciEngine::start(command, log.as_deref())
+    };
+    let opening = &config.openings[pairing.opening];
+    let abandoned = |loser, e: anyhow::Error| {
+        GameRecord::abandoned(
+            &config.engines[pairing.white].name,
+            &config.engines[pairing.black].name,
+            opening,
+            loser,
+            e.to_string(),
+        )
+    };
+    let mut white = match start(pairing.white) {
+        Ok(engine) => engine,
+        Err(e) => return abandoned(Color::White, e),
+    };
+    let mut black = match start(pairing.black) {
+        Ok(engine) => engine,
+        Err(e) => return abandoned(Color::Black, e),
+    };
+    play_game(&mut white, &mut black, opening, &config.game)
+}
+
+/// One game of a match as PGN, `number` is its place in the schedule.
+pub fn game_pgn(event: &str, game: &GameRecord, number: usize, games_per_round: usize) -> String {
+    let round = format!(
+        "{}.{}",
+        number / games_per_round + 1,
+        number % games_per_round + 1
+    );
+    game.to_pgn(&[("Event", event.to_owned()), ("Round", round)])
+}
+
+/// Wins, draws and losses per engine.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Standings {
+    pub entries: Vec<Standing>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Standing {
+    pub name: String,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Standing {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn points(&self) -> f64 {
+        f64::from(self.wins) + f64::from(self.draws) / 2.0
+    }
+
+    /// The Elo difference to the average opponent implied by the score, `None` for 0% or 100%.
+    pub fn elo(&self) -> Option<f64> {
+        let score = self.points() / f64::from(self.games());
+        (score > 0.0 && score < 1.0).then(|| -400.0 * (1.0 / score - 1.0).log10())
+    }
+}
+
+impl Standings {
+    pub fn new(games: &[GameRecord]) -> Self {
+        let mut entries: Vec<Standing> = Vec::new();
+        for game in games {
+            for (name, own) in [
+                (&game.white, GameResult::White),
+                (&game.black, GameResult::Black),
+            ] {
+                let index = match entries.iter().position(|e| &e.name == name) {
+                    Some(index) => index,
+                    None => {
+                        entries.push(Standing {
+                            name: name.clone(),
+                            wins: 0,
+                            draws: 0,
+                            losses: 0,
+                        });
+                        entries.len() - 1
+                    }
+                };
+                let entry = &mut entries[index];
+                match game.result {
+                    GameResult::Draw => entry.draws += 1,
+                    result if result == own => entry.wins += 1,
+                    _ => entry.losses += 1,
+                }
+            }
+        }
+        entries.sort_by(|a, b| b.points().total_cmp(&a.points()));
+        Self { entries }
+    }
+}
+
+impl fmt::Display for Standings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>6} {:>6} {:>5} {:>5} {:>5} {:>7}",
+            "Name", "Points", "Games", "W", "D", "L", "Elo"
+        )?;
+        for entry in &self.entries {
+            let elo = entry
+                .elo()
+                .map_or_else(|| "-".to_owned(), |elo| format!("{elo:+.0}"));
+            writeln!(
+                f,
+                "{:<20} {:>6.1} {:>6} {:>5} {:>5} {:>5} {:>7}",
+                entry.name,
+                entry.points(),
+                entry.games(),
+                entry.wins,
+                entry.draws,
+                entry.losses,
+                elo
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads an opening suite, PGN games are cut after `plies` half moves and EPD lines are used as
+/// they are.
+pub fn read_openings(path: &Path, plies: usize) -> Result<Vec<Position>> {
+    let contents = std::fs::read_to_string(path)?;
+    let openings = if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pgn"))
+    {
+        pgn_to_openings(&contents, plies)?
+    } else {
+        epd_to_openings(&contents)?
+    };
+    if openings.is_empty() {
+        return Err(anyhow!("No openings in {}", path.display()));
+    }
+    Ok(openings)
+}
+
+fn epd_to_openings(contents: &str) -> Result<Vec<Position>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|line| {
+            let fields = line.split_whitespace().collect_vec();
+            if fields.len() < 4 {
+                return Err(anyhow!("Invalid EPD line {line}"));
+            }
+            // Operations like `bm` or `id` may follow, move counters only sometimes
+            let counters = match fields.get(4..6) {
+                Some([halfmoves, fullmoves])
+                    if halfmoves.parse::<u8>().is_ok() && fullmoves.parse::<u16>().is_ok() =>
+                {
+                    format!("{halfmoves} {fullmoves}")
+                }
+                _ => "0 1".to_owned(),
+            };
+            let fen = format!("{} {counters}", fields[..4].join(" "));
+            Position::from_str(&fen).map_err(|e| anyhow!("Invalid EPD line {line}: {e}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_runner::record::Termination;
+
+    #[test]
+    fn games_are_played_with_both_colors() {
+        let pairings = schedule(3, 5, 2, 1029384756);
+        assert_eq!(pairings.len(), 12);
+        for round in pairings.chunks(6) {
+            assert!(round.iter().map(|p| p.opening).all_equal());
+            for (first, second) in round.iter().tuples() {
+                assert_eq!((first.white, first.black), (second.black, second.white));
+            }
+        }
+        assert_ne!(pairings[0].opening, pairings[6].opening);
+        assert_eq!(pairings, schedule(3, 5, 2, 1029384756));
+
+        let self_play = schedule(1, 1, 2, 0);
+        assert_eq!(self_play.len(), 4);
+        assert!(self_play.iter().all(|p| p.white == 0 && p.black == 0));
+    }
+
+    #[test]
+    fn standings_count_points() {
+        let game = |white: &str, black: &str, result| GameRecord {
+            white: white.to_owned(),
+            black: black.to_owned(),
+            start: Position::default(),
+            moves: Vec::new(),
+            result,
+            termination: Termination::MaxMoves,
+            final_position: Position::default(),
+        };
+        let standings = Standings::new(&[
+            game("new", "main", GameResult::White),
+            game("main", "new", GameResult::Draw),
+            game("new", "main", GameResult::Black),
+            game("main", "new", GameResult::Black),
+        ]);
+        let new = &standings.entries[0];
+        assert_eq!(new.name, "new");
+        assert_eq!((new.wins, new.draws, new.losses), (2, 1, 1));
+        assert_eq!(new.points(), 2.5);
+        assert!((new.elo().unwrap() - 88.7).abs() < 0.1);
+        assert!(standings
+            .to_string()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("new"));
+
+        let pgn = game_pgn("Test", &game("b", "a", GameResult::Draw), 3, 2);
+        assert!(pgn.starts_with("[Event \"Test\"]\n[Round \"2.2\"]\n[White \"b\"]"));
+    }
+
+    #[test]
+    fn engines_that_fail_to_start_lose_their_games() {
+        let folder = tempfile::tempdir().unwrap();
+        let pgn_out = folder.path().join("match.pgn");
+        let config = MatchConfig {
+            engines: vec![EngineCommand {
+                name: "missing".to_owned(),
+                command: folder.path().join("missing"),
+                options: Vec::new(),
+            }],
+            openings: vec![Position::default()],
+            rounds: 2,
+            concurrency: 2,
+            game: GameSettings {
+                time_control: "1+0".parse().unwrap(),
+                max_moves: None,
+                resign: None,
+                time_margin: std::time::Duration::ZERO,
+            },
+            seed: 0,
+            log_folder: None,
+        };
+        let games = run_match(&config, "Test", &pgn_out).unwrap();
+        assert_eq!(games.len(), 4);
+        assert!(games
+            .iter()
+            .all(|g| g.result == GameResult::Black
+                && matches!(g.termination, Termination::Abandoned(_))));
+        let pgn = std::fs::read_to_string(pgn_out).unwrap();
+        assert_eq!(pgn.matches("[Termination \"abandoned\"]").count(), 4);
+    }
+
+    #[test]
+    fn opening_suites() {
+        let epd = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 bm e5; id \"1\";\n\n\
+                   4k3/8/8/8/8/8/8/4K3 w - - 12 40\n";
+        let openings = epd_to_openings(epd).unwrap();
+        assert_eq!(
+            openings[0].to_string(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        assert_eq!(openings[1].to_string(), "4k3/8/8/8/8/8/8/4K3 w - - 12 40");
+        assert!(epd_to_openings("4k3/8/8 w").is_err());
+
+        let pgn = "[Event \"a\"]\n[Result \"*\"]\n\n1. e4 e5 2. Nf3 Nc6 *\n\n\
+                   [Event \"b\"]\n[Result \"1/2-1/2\"]\n\n1. d4 1/2-1/2\n";
+        let openings = pgn_to_openings(pgn, 3).unwrap();
+        assert_eq!(
+            openings[0].to_string(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        assert_eq!(
+            openings[1].to_string(),
+            "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 1"
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use crate::match_runner::game::win_for;
+use crate::GameResult;
+use guts::{BasicMoveBuffer, Color, Move, MoveGenerator, MoveType, Piece, Position};
+use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Why a game ended.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+    MaxMoves,
+    Resignation,
+    TimeForfeit,
+    IllegalMove(String),
+    /// The engine crashed or stopped answering
+    Abandoned(String),
+}
+
+impl Termination {
+    /// The value of the PGN `Termination` tag.
+    fn tag(&self) -> &'static str {
+        match self {
+            Termination::Checkmate
+            | Termination::Stalemate
+            | Termination::FiftyMoves
+            | Termination::Repetition
+            | Termination::InsufficientMaterial => "normal",
+            Termination::MaxMoves | Termination::Resignation => "adjudication",
+            Termination::TimeForfeit => "time forfeit",
+            Termination::IllegalMove(_) => "illegal move",
+            Termination::Abandoned(_) => "abandoned",
+        }
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::Checkmate => write!(f, "checkmate"),
+            Termination::Stalemate => write!(f, "stalemate"),
+            Termination::FiftyMoves => write!(f, "fifty move rule"),
+            Termination::Repetition => write!(f, "threefold repetition"),
+            Termination::InsufficientMaterial => write!(f, "insufficient material"),
+            Termination::MaxMoves => write!(f, "maximum number of moves"),
+            Termination::Resignation => write!(f, "resignation"),
+            Termination::TimeForfeit => write!(f, "loss on time"),
+            Termination::IllegalMove(m) => write!(f, "illegal move {m}"),
+            Termination::Abandoned(reason) => write!(f, "abandoned: {reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MoveRecord {
+    pub m: Move,
+    pub san: String,
+    pub score: Option<i32>,
+    pub depth: Option<u32>,
+    pub time: Duration,
+}
+
+impl MoveRecord {
+    pub fn new(
+        position: &Position,
+        m: &Move,
+        move_generator: &MoveGenerator,
+        score: Option<i32>,
+        depth: Option<u32>,
+        time: Duration,
+    ) -> Self {
+        Self {
+            m: m.clone(),
+            san: san(position, m, move_generator),
+            score,
+            depth,
+            time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GameRecord {
+    pub white: String,
+    pub black: String,
+    pub start: Position,
+    pub moves: Vec<MoveRecord>,
+    pub result: GameResult,
+    pub termination: Termination,
+    pub final_position: Position,
+}
+
+impl GameRecord {
+    /// A game that ended before the first move because `loser` failed, it counts as a loss.
+    pub fn abandoned(
+        white: &str,
+        black: &str,
+        start: &Position,
+        loser: Color,
+        reason: String,
+    ) -> Self {
+        Self {
+            white: white.to_owned(),
+            black: black.to_owned(),
+            start: start.clone(),
+            moves: Vec::new(),
+            result: win_for(!loser),
+            termination: Termination::Abandoned(reason),
+            final_position: start.clone(),
+        }
+    }
+
+    /// The final position as EPD, the FEN without the move counters, like cutechess' `-epdout`.
+    pub fn final_epd(&self) -> String {
+        self.final_position
+            .to_string()
+            .split(' ')
+            .take(4)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The game as PGN with `tags` first, every move commented with its score, depth and time
+    /// like cutechess does.
+    pub fn to_pgn(&self, tags: &[(&str, String)]) -> String {
+        let mut pgn = String::new();
+        for (name, value) in tags {
+            let _ = writeln!(pgn, "[{name} \"{value}\"]");
+        }
+        let _ = writeln!(pgn, "[White \"{}\"]", self.white);
+        let _ = writeln!(pgn, "[Black \"{}\"]", self.black);
+        let _ = writeln!(pgn, "[Result \"{}\"]", self.result);
+        if self.start != Position::default() {
+            let _ = writeln!(pgn, "[SetUp \"1\"]");
+            let _ = writeln!(pgn, "[FEN \"{}\"]", self.start);
+        }
+        let _ = writeln!(pgn, "[PlyCount \"{}\"]", self.moves.len());
+        let _ = writeln!(pgn, "[Termination \"{}\"]", self.termination.tag());
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut position = self.start.clone();
+        for (ply, record) in self.moves.iter().enumerate() {
+            let white_to_move = position.active_color() == guts::Color::White;
+            if white_to_move {
+                tokens.push(format!("{}.", position.fullmove_number()));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", position.fullmove_number()));
+            }
+            tokens.push(record.san.clone());
+            tokens.push(comment(record));
+            position.make_move(&record.m);
+        }
+        tokens.push(format!("{{{}}}", self.termination));
+        tokens.push(self.result.to_string());
+        pgn.push_str(&wrap(&tokens));
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+fn comment(record: &MoveRecord) -> String {
+    let time = format!("{:.3}s", record.time.as_secs_f64());
+    match (record.score, record.depth) {
+        (Some(score), Some(depth)) => {
+            format!("{{{:+.2}/{depth} {time}}}", f64::from(score) / 100.0)
+        }
+        (Some(score), None) => format!("{{{:+.2} {time}}}", f64::from(score) / 100.0),
+        _ => format!("{{{time}}}"),
+    }
+}
+
+/// PGN lines should stay under 80 characters.
+fn wrap(tokens: &[String]) -> String {
+    let mut lines = vec![String::new()];
+    for token in tokens {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+            lines.push(token.clone());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(token);
+        }
+    }
+    lines.join("\n")
+}
+
+/// `m` in standard algebraic notation, `m` has to be legal in `position`.
+pub fn san(position: &Position, m: &Move, move_generator: &MoveGenerator) -> String {
+    let mut san = if m.move_type().contains(MoveType::CASTLE_KINGSIDE) {
+        "O-O".to_owned()
+    } else if m.move_type().contains(MoveType::CASTLE_QUEENSIDE) {
+        "O-O-O".to_owned()
+    } else {
+        let is_capture = m
+            .move_type()
+            .intersects(MoveType::CAPTURE | MoveType::EN_PASSANT);
+        let mut san = String::new();
+        if m.piece() == Piece::Pawn {
+            if is_capture {
+                san.push_str(&m.from().file().to_string());
+            }
+        } else {
+            san.push_str(&m.piece().to_string());
+            let mut legal_moves = BasicMoveBuffer::new();
+            move_generator.generate_legal_moves_for(position, &mut legal_moves);
+            let ambiguous = legal_moves
+                .iter()
+                .filter(|o| o.piece() == m.piece() && o.to() == m.to() && o.from() != m.from())
+                .collect::<Vec<_>>();
+            if !ambiguous.is_empty() {
+                if ambiguous.iter().all(|o| o.from().file() != m.from().file()) {
+                    san.push_str(&m.from().file().to_string());
+                } else if ambiguous.iter().all(|o| o.from().rank() != m.from().rank()) {
+                    san.push_str(&m.from().rank().to_string());
+                } else {
+                    san.push_str(&m.from().to_string());
+                }
+            }
+        }
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&m.to().to_string());
+        if let Some(promotion) = m.promotion() {
+            san.push('=');
+            san.push_str(&promotion.to_string());
+        }
+        san
+    };
+
+    let mut after = position.clone();
+    after.make_move(m);
+    let mut replies = BasicMoveBuffer::new();
+    if move_generator.generate_legal_moves_for(&after, &mut replies) {
+        san.push(if replies.is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn san_of(fen: &str, uci: &str) -> String {
+        let position = Position::from_str(fen).unwrap();
+        let move_generator = MoveGenerator::new();
+        let mut buf = BasicMoveBuffer::new();
+        move_generator.generate_legal_moves_for(&position, &mut buf);
+        let m = buf.iter().find(|m| m.as_uci() == uci).unwrap();
+        san(&position, m, &move_generator)
+    }
+
+    #[test]
+    fn standard_algebraic_notation() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_of(start, "e2e4"), "e4");
+        assert_eq!(san_of(start, "g1f3"), "Nf3");
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san_of(kiwipete, "e1g1"), "O-O");
+        assert_eq!(san_of(kiwipete, "e1c1"), "O-O-O");
+        assert_eq!(san_of(kiwipete, "d5e6"), "dxe6");
+        assert_eq!(san_of(kiwipete, "e2a6"), "Bxa6");
+        assert_eq!(san_of(kiwipete, "e5f7"), "Nxf7");
+        // Both knights and both rooks can reach the same square
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/N1N1K3 w - - 0 1", "a1b3"), "Nab3");
+        assert_eq!(san_of("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4"), "R1a4");
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/Q1Q1K1Q1 w - - 0 1", "a1b2"), "Qab2");
+        assert_eq!(san_of("4k3/8/8/8/Q1Q5/8/Q7/4K3 w - - 0 1", "a4b3"), "Qa4b3");
+        assert_eq!(san_of("8/1P2k3/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q");
+        assert_eq!(san_of("k7/8/1K6/8/8/8/8/7R w - - 0 1", "h1h8"), "Rh8#");
+        assert_eq!(san_of("k7/8/8/8/8/8/8/K6R w - - 0 1", "h1h8"), "Rh8+");
+    }
+
+    #[test]
+    fn pgn_output() {
+        let move_generator = MoveGenerator::new();
+        let mut position = Position::default();
+        let mut moves = Vec::new();
+        for (uci, score) in [
+            ("f2f3", None),
+            ("e7e5", Some(25)),
+            ("g2g4", None),
+            ("d8h4", Some(100_000)),
+        ] {
+            let mut buf = BasicMoveBuffer::new();
+            move_generator.generate_legal_moves_for(&position, &mut buf);
+            let m = buf.iter().find(|m| m.as_uci() == uci).unwrap().clone();
+            moves.push(MoveRecord::new(
+                &position,
+                &m,
+                &move_generator,
+                score,
+                score.map(|_| 3),
+                Duration::from_millis(20),
+            ));
+            position.make_move(&m);
+        }
+        let record = GameRecord {
+            white: "a".to_owned(),
+            black: "b".to_owned(),
+            start: Position::default(),
+            moves,
+            result: GameResult::Black,
+            termination: Termination::Checkmate,
+            final_position: position,
+        };
+        let pgn = record.to_pgn(&[("Event", "Test".to_owned())]);
+        assert!(
+            pgn.starts_with("[Event \"Test\"]\n[White \"a\"]\n[Black \"b\"]\n[Result \"0-1\"]\n")
+        );
+        assert!(!pgn.contains("[FEN"));
+        assert!(pgn.contains(
+            "1. f3 {0.020s} e5 {+0.25/3 0.020s} 2. g4 {0.020s} Qh4# {+1000.00/3 0.020s}"
+        ));
+        assert!(pgn.ends_with("{checkmate} 0-1\n\n"));
+        assert_eq!(
+            record.final_epd(),
+            "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq -"
+        );
+    }
+}
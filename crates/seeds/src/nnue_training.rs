@@ -0,0 +1,429 @@
+use crate::{validation_count, AnnotatedPosition};
+use anyhow::{anyhow, Result};
+use brain::evaluator::nnue::{Network, HIDDEN_SIZE, INPUT_SIZE, OUTPUT_SCALE, QA, QB};
+use guts::{Color, Piece, Position};
+use itertools::Itertools;
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Offsets into the flat parameter vector
+const FEATURE_BIAS: usize = INPUT_SIZE * HIDDEN_SIZE;
+const OUTPUT_WEIGHTS: usize = FEATURE_BIAS + HIDDEN_SIZE;
+const OUTPUT_BIAS: usize = OUTPUT_WEIGHTS + HIDDEN_SIZE;
+const PARAMETER_COUNT: usize = OUTPUT_BIAS + 1;
+
+/// Keeps the quantized accumulator of a full board well within an i16.
+const MAX_HIDDEN_WEIGHT: f32 = 1.98;
+const MAX_OUTPUT_WEIGHT: f32 = i16::MAX as f32 / QB as f32;
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+const SEED: u64 = 0xC4E55;
+
+#[derive(Debug, Clone)]
+pub struct TrainingConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    /// Part of the positions held out to measure the loss on.
+    pub validation_fraction: f64,
+    /// How much the search score counts towards the target when a position has one, the game
+    /// result makes up the rest.
+    pub score_weight: f32,
+    pub output_folder: PathBuf,
+    pub resume: Option<PathBuf>,
+}
+
+struct Sample {
+    features: Vec<u16>,
+    /// Expected score for white, from 0 for a loss to 1 for a win.
+    target: f32,
+}
+
+impl Sample {
+    fn new(annotated: &AnnotatedPosition, score_weight: f32) -> Self {
+        let result = (f32::from(annotated.result) + 1.0) / 2.0;
+        let target = match annotated.score {
+            Some(score) => {
+                score_weight * sigmoid(score as f32 / OUTPUT_SCALE as f32)
+                    + (1.0 - score_weight) * result
+            }
+            None => result,
+        };
+        Self {
+            features: features(&annotated.pos),
+            target,
+        }
+    }
+}
+
+fn features(position: &Position) -> Vec<u16> {
+    let mut features = Vec::with_capacity(32);
+    for color in Color::ALL {
+        for piece in Piece::ALL {
+            for square in position.board()[color][piece] {
+                features.push(Network::feature_index(color, piece, square) as u16);
+            }
+        }
+    }
+    features
+}
+
+/// The floating point network with its Adam state, all parameters live in one flat vector.
+pub struct Trainer {
+    params: Vec<f32>,
+    first_moments: Vec<f32>,
+    second_moments: Vec<f32>,
+    steps: i32,
+    epoch: usize,
+}
+
+impl Trainer {
+    pub fn new() -> Self {
+        let mut rng = ChaCha12Rng::seed_from_u64(SEED);
+        let hidden_range = 1.0 / (32.0f32).sqrt();
+        let output_range = 1.0 / (HIDDEN_SIZE as f32).sqrt();
+        let params = (0..PARAMETER_COUNT)
+            .map(|i| match i {
+                i if i < FEATURE_BIAS => rng.gen_range(-hidden_range..hidden_range),
+                i if (OUTPUT_WEIGHTS..OUTPUT_BIAS).contains(&i) => {
+                    rng.gen_range(-output_range..output_range)
+                }
+                _ => 0.0,
+            })
+            .collect();
+        Self {
+            params,
+            first_moments: vec![0.0; PARAMETER_COUNT],
+            second_moments: vec![0.0; PARAMETER_COUNT],
+            steps: 0,
+            epoch: 0,
+        }
+    }
+
+    pub fn load_checkpoint(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let (params, first_moments, second_moments, steps, epoch): (
+            Vec<f32>,
+            Vec<f32>,
+            Vec<f32>,
+            i32,
+            usize,
+        ) = bincode::deserialize(&data)?;
+        if params.len() != PARAMETER_COUNT {
+            return Err(anyhow!(
+                "Checkpoint has {} parameters, expected {PARAMETER_COUNT}",
+                params.len()
+            ));
+        }
+        Ok(Self {
+            params,
+            first_moments,
+            second_moments,
+            steps,
+            epoch,
+        })
+    }
+
+    pub fn save_checkpoint(&self, path: &Path) -> Result<()> {
+        let data = bincode::serialize(&(
+            &self.params,
+            &self.first_moments,
+            &self.second_moments,
+            self.steps,
+            self.epoch,
+        ))?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Rounds the weights to the format [`Network::from_bincode`] loads.
+    pub fn export(&self) -> Network {
+        let quantize = |w: &f32, scale: i32| (w * scale as f32).round() as i16;
+        let feature_weights = self.params[..FEATURE_BIAS]
+            .iter()
+            .map(|w| quantize(w, QA))
+            .collect_vec();
+        let feature_bias = self.params[FEATURE_BIAS..OUTPUT_WEIGHTS]
+            .iter()
+            .map(|w| quantize(w, QA))
+            .collect_vec();
+        let output_weights = self.params[OUTPUT_WEIGHTS..OUTPUT_BIAS]
+            .iter()
+            .map(|w| quantize(w, QB))
+            .collect_vec();
+        let output_bias = (self.params[OUTPUT_BIAS] * (QA * QB) as f32).round() as i32;
+        Network::new(
+            &feature_weights,
+            &feature_bias,
+            &output_weights,
+            output_bias,
+        )
+    }
+
+    /// The hidden layer before activation and the raw output, for white.
+    fn forward(&self, features: &[u16]) -> ([f32; HIDDEN_SIZE], f32) {
+        let mut hidden = [0.0; HIDDEN_SIZE];
+        hidden.copy_from_slice(&self.params[FEATURE_BIAS..OUTPUT_WEIGHTS]);
+        for &f in features {
+            let row = f as usize * HIDDEN_SIZE;
+            for (h, w) in hidden.iter_mut().zip(&self.params[row..row + HIDDEN_SIZE]) {
+                *h += w;
+            }
+        }
+        let output = hidden
+            .iter()
+            .zip(&self.params[OUTPUT_WEIGHTS..OUTPUT_BIAS])
+            .map(|(h, w)| h.clamp(0.0, 1.0) * w)
+            .sum::<f32>()
+            + self.params[OUTPUT_BIAS];
+        (hidden, output)
+    }
+
+    fn loss(&self, sample: &Sample) -> f32 {
+        let (_, output) = self.forward(&sample.features);
+        (sigmoid(output) - sample.target).powi(2)
+    }
+
+    /// The summed loss over the batch and the gradient of its mean.
+    fn gradient(&self, batch: &[Sample]) -> (f32, Vec<f32>) {
+        let chunk_size = batch.len().div_ceil(rayon::current_num_threads()).max(1);
+        let (loss, mut gradient) = batch
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut gradient = vec![0.0; PARAMETER_COUNT];
+                let mut loss = 0.0;
+                for sample in chunk {
+                    let (hidden, output) = self.forward(&sample.features);
+                    let predicted = sigmoid(output);
+                    let error = predicted - sample.target;
+                    loss += error * error;
+                    let d_output = 2.0 * error * predicted * (1.0 - predicted);
+                    gradient[OUTPUT_BIAS] += d_output;
+                    for (j, &h) in hidden.iter().enumerate() {
+                        gradient[OUTPUT_WEIGHTS + j] += d_output * h.clamp(0.0, 1.0);
+                        if h > 0.0 && h < 1.0 {
+                            let d_hidden = d_output * self.params[OUTPUT_WEIGHTS + j];
+                            gradient[FEATURE_BIAS + j] += d_hidden;
+                            for &f in &sample.features {
+                                gradient[f as usize * HIDDEN_SIZE + j] += d_hidden;
+                            }
+                        }
+                    }
+                }
+                (loss, gradient)
+            })
+            .reduce(
+                || (0.0, vec![0.0; PARAMETER_COUNT]),
+                |(loss_a, mut a), (loss_b, b)| {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                    (loss_a + loss_b, a)
+                },
+            );
+        let n = batch.len() as f32;
+        gradient.iter_mut().for_each(|g| *g /= n);
+        (loss, gradient)
+    }
+
+    fn adam_step(&mut self, gradient: &[f32], learning_rate: f32) {
+        self.steps += 1;
+        let first_correction = 1.0 - BETA1.powi(self.steps);
+        let second_correction = 1.0 - BETA2.powi(self.steps);
+        for (i, &g) in gradient.iter().enumerate() {
+            let m = &mut self.first_moments[i];
+            let v = &mut self.second_moments[i];
+            *m = BETA1 * *m + (1.0 - BETA1) * g;
+            *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+            let step = learning_rate * (*m / first_correction)
+                / ((*v / second_correction).sqrt() + EPSILON);
+            let max = if (OUTPUT_WEIGHTS..OUTPUT_BIAS).contains(&i) {
+                MAX_OUTPUT_WEIGHT
+            } else {
+                MAX_HIDDEN_WEIGHT
+            };
+            self.params[i] = (self.params[i] - step).clamp(-max, max);
+        }
+    }
+
+    /// One pass over the samples, returns the mean training loss.
+    fn train_epoch(&mut self, samples: &[Sample], batch_size: usize, learning_rate: f32) -> f32 {
+        let mut total = 0.0;
+        for batch in samples.chunks(batch_size) {
+            let (loss, gradient) = self.gradient(batch);
+            self.adam_step(&gradient, learning_rate);
+            total += loss;
+        }
+        self.epoch += 1;
+        total / samples.len() as f32
+    }
+
+    fn mean_loss(&self, samples: &[Sample]) -> f32 {
+        samples.par_iter().map(|s| self.loss(s)).sum::<f32>() / samples.len().max(1) as f32
+    }
+}
+
+impl Default for Trainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn train(config: &TrainingConfig, mut positions: Vec<AnnotatedPosition>) -> Result<()> {
+    let validation_count = validation_count(positions.len(), config.validation_fraction)?;
+    let mut rng = ChaCha12Rng::seed_from_u64(SEED);
+    positions.shuffle(&mut rng);
+    let mut samples = positions
+        .par_iter()
+        .map(|ap| Sample::new(ap, config.score_weight))
+        .collect::<Vec<_>>();
+    let validation = samples.split_off(samples.len() - validation_count);
+    println!(
+        "Training on {} positions, validating on {}",
+        samples.len(),
+        validation.len()
+    );
+
+    let mut trainer = match &config.resume {
+        Some(path) => Trainer::load_checkpoint(path)?,
+        None => Trainer::new(),
+    };
+    std::fs::create_dir_all(&config.output_folder)?;
+    let loss_log_path = config.output_folder.join("loss.csv");
+    // A resumed run continues the log of the run it resumes, a fresh one replaces any old log
+    let mut loss_log = if config.resume.is_some() {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(loss_log_path)?
+    } else {
+        let mut loss_log = std::fs::File::create(loss_log_path)?;
+        writeln!(loss_log, "epoch,training_loss,validation_loss")?;
+        loss_log
+    };
+
+    while trainer.epoch < config.epochs {
+        samples.shuffle(&mut rng);
+        let training_loss = trainer.train_epoch(&samples, config.batch_size, config.learning_rate);
+        let validation_loss = trainer.mean_loss(&validation);
+        println!(
+            "Epoch {}: training loss {training_loss:.6}, validation loss {validation_loss:.6}",
+            trainer.epoch
+        );
+        writeln!(
+            loss_log,
+            "{},{training_loss},{validation_loss}",
+            trainer.epoch
+        )?;
+        trainer.save_checkpoint(
+            &config
+                .output_folder
+                .join(format!("checkpoint-{}.bincode", trainer.epoch)),
+        )?;
+        std::fs::write(
+            config.output_folder.join("nnue.bincode"),
+            trainer.export().to_bincode(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameResult;
+    use std::str::FromStr;
+
+    fn annotated(fen: &str, result: GameResult) -> AnnotatedPosition {
+        AnnotatedPosition {
+            pos: Position::from_str(fen).unwrap(),
+            result,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn export_matches_float_network() {
+        let trainer = Trainer::new();
+        let network = Network::from_bincode(&trainer.export().to_bincode());
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let position = Position::from_str(fen).unwrap();
+            let (_, output) = trainer.forward(&features(&position));
+            let expected = output * OUTPUT_SCALE as f32;
+            let actual = network.evaluate(&position) as f32;
+            assert!(
+                (expected - actual).abs() < 10.0,
+                "{fen}: {expected} {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn training_lowers_the_loss() {
+        let samples = [
+            annotated("4k3/8/8/8/8/8/8/QQQ1K3 w - - 0 1", GameResult::White),
+            annotated("4k3/8/8/8/8/8/8/RR2K3 b - - 0 1", GameResult::White),
+            annotated("qqq1k3/8/8/8/8/8/8/4K3 w - - 0 1", GameResult::Black),
+            annotated("4k3/8/8/8/8/8/8/4K3 w - - 0 1", GameResult::Draw),
+        ]
+        .iter()
+        .map(|ap| Sample::new(ap, 0.0))
+        .collect_vec();
+        let mut trainer = Trainer::new();
+        let before = trainer.mean_loss(&samples);
+        for _ in 0..20 {
+            trainer.train_epoch(&samples, 2, 0.01);
+        }
+        assert!(trainer.mean_loss(&samples) < before / 2.0);
+    }
+
+    #[test]
+    fn fresh_runs_replace_the_loss_log_and_resumed_runs_extend_it() {
+        let folder = tempfile::tempdir().unwrap();
+        let positions = || {
+            vec![
+                annotated("4k3/8/8/8/8/8/8/RR2K3 w - - 0 1", GameResult::White),
+                annotated("4k3/8/8/8/8/8/8/4K3 w - - 0 1", GameResult::Draw),
+            ]
+        };
+        let config = |epochs, resume| TrainingConfig {
+            epochs,
+            batch_size: 2,
+            learning_rate: 0.01,
+            validation_fraction: 0.0,
+            score_weight: 0.0,
+            output_folder: folder.path().to_owned(),
+            resume,
+        };
+        let log_lines = || {
+            std::fs::read_to_string(folder.path().join("loss.csv"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+
+        train(&config(1, None), positions()).unwrap();
+        train(&config(1, None), positions()).unwrap();
+        assert_eq!(log_lines(), 2);
+        let checkpoint = folder.path().join("checkpoint-1.bincode");
+        train(&config(3, Some(checkpoint)), positions()).unwrap();
+        assert_eq!(log_lines(), 4);
+        train(&config(1, None), positions()).unwrap();
+        assert_eq!(log_lines(), 2);
+
+        let mut config = config(1, None);
+        config.validation_fraction = 1.0;
+        assert!(train(&config, positions()).is_err());
+    }
+}
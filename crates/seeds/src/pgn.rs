@@ -42,21 +42,32 @@ pub fn pgn_to_annotated_fen(
             ps.into_iter()
                 .dropping(dropped_positions_start_of_game)
                 .dropping_back(dropped_positions_end_of_game)
-                .map(move |pos| AnnotatedPosition { pos, result })
+                .map(move |pos| AnnotatedPosition {
+                    pos,
+                    result,
+                    score: None,
+                })
         })
         .collect())
 }
 
-fn parse_moves(list: &str) -> Result<(Vec<Position>, GameResult)> {
-    let movegen = MoveGenerator::new();
-
-    let split = list
-        .split_whitespace()
-        .filter(|s| !s.is_empty())
-        .filter(|s| !s.ends_with('.'))
-        .filter(|&s| s != "1-0" && s != "0-1" && s != "1/2-1/2")
-        .map(str::trim);
+/// The first `plies` half moves of every game in `raw`, for playing matches from.
+pub fn pgn_to_openings(raw: &str, plies: usize) -> Result<Vec<Position>> {
+    raw.split("[Event")
+        .filter(|g| !g.trim().is_empty())
+        .map(|g| {
+            let list = g
+                .lines()
+                .filter(|l| !l.starts_with('[') && !l.is_empty() && !l.ends_with(']'))
+                .map(|s| s.trim())
+                .join(" ");
+            let positions = replay(&list)?;
+            Ok(positions[plies.min(positions.len() - 1)].clone())
+        })
+        .collect()
+}
 
+fn parse_moves(list: &str) -> Result<(Vec<Position>, GameResult)> {
     let gameresult = list
         .split_whitespace()
         .find(|&s| s == "1-0" || s == "0-1" || s == "1/2-1/2")
@@ -68,6 +79,23 @@ fn parse_moves(list: &str) -> Result<(Vec<Position>, GameResult)> {
         _ => unreachable!("Found a strange gameresult {gameresult} in {list}"),
     };
 
+    let mut res = replay(list)?;
+    res.pop();
+
+    Ok((res, gameresult))
+}
+
+/// Every position of the game in `list`, from the starting position up to the final one.
+fn replay(list: &str) -> Result<Vec<Position>> {
+    let movegen = MoveGenerator::new();
+
+    let split = list
+        .split_whitespace()
+        .filter(|s| !s.is_empty())
+        .filter(|s| !s.ends_with('.'))
+        .filter(|&s| s != "1-0" && s != "0-1" && s != "1/2-1/2" && s != "*")
+        .map(str::trim);
+
     let mut cur_pos = Position::default();
 
     let mut res = Vec::new();
@@ -121,8 +149,9 @@ fn parse_moves(list: &str) -> Result<(Vec<Position>, GameResult)> {
         res.push(cur_pos.clone());
         cur_pos.make_move_clone(m);
     }
+    res.push(cur_pos);
 
-    Ok((res, gameresult))
+    Ok(res)
 }
 
 fn parse_move(m: &str) -> Result<MoveParseResult> {
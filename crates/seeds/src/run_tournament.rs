@@ -1,3 +1,6 @@
+use crate::match_runner::engine::EngineCommand;
+use crate::match_runner::game::{GameSettings, TimeControl};
+use crate::match_runner::{read_openings, run_match, MatchConfig, Standings};
 use anyhow::{anyhow, Result};
 use git2::build::RepoBuilder;
 use git2::{Cred, FetchOptions, RemoteCallbacks};
@@ -6,6 +9,7 @@ use std::env;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct IdAndFilename {
@@ -31,6 +35,7 @@ impl FromStr for IdAndFilename {
 pub fn run_tournament(hashes: &[IdAndFilename], output_folder: PathBuf) -> Result<()> {
     let _ = std::fs::remove_dir_all(&output_folder);
     std::fs::create_dir(&output_folder)?;
+    let mut signatures = String::new();
     for IdAndFilename { id, name } in hashes {
         let tempfolder = tempfile::tempdir()?;
         let path = tempfolder.path();
@@ -57,58 +62,66 @@ pub fn run_tournament(hashes: &[IdAndFilename], output_folder: PathBuf) -> Resul
         }
         let binary_path = path.join(Path::new("target/release/chessatiel"));
         let target_path = output_folder.join(name);
-        std::fs::copy(binary_path, target_path)?;
+        std::fs::copy(binary_path, &target_path)?;
+        match bench_signature(&target_path) {
+            Ok(signature) => signatures.push_str(&format!("{name} {id} {signature}\n")),
+            // Older commits don't have the bench subcommand yet
+            Err(e) => println!("No bench signature for {name}: {e}"),
+        }
     }
+    std::fs::write(output_folder.join("signatures.txt"), signatures)?;
 
-    let mut args = vec![
-        "-tournament",
-        "round-robin",
-        "-rounds",
-        "120",
-        "-openings",
-        "file=../openings/openings.pgn",
-        "format=pgn",
-        "order=random",
-        "plies=10",
-        "-each",
-        "tc=150/1+1",
-        // "st=5",
-        "restart=on",
-        "proto=uci",
-        "-concurrency",
-        "30",
-        "-maxmoves",
-        "100",
-        "-games",
-        "1",
-        "-epdout",
-        "tournament.epd",
-        "-pgnout",
-        "tournament.pgn",
-        "-srand",
-        "1029384756",
-        "-recover",
-    ]
-    .into_iter()
-    .map(|s| s.to_owned())
-    .collect_vec();
-    for IdAndFilename { name, .. } in hashes {
-        args.extend(engine_args(name).into_iter());
-    }
-    println!("{args:?}");
-    let mut cutechess_cli = Command::new("cutechess-cli")
-        .current_dir(output_folder)
-        .args(args)
-        .spawn()?;
-    let exit_status = cutechess_cli.wait()?;
-    if !exit_status.success() {
-        return Err(anyhow!("Cutechess-cli returned with error {exit_status}"));
-    }
+    let openings = read_openings(&output_folder.join("../openings/openings.pgn"), 10)?;
+    let config = MatchConfig {
+        engines: hashes
+            .iter()
+            .map(|IdAndFilename { name, .. }| EngineCommand {
+                name: name.clone(),
+                command: output_folder.join(name),
+                options: Vec::new(),
+            })
+            .collect(),
+        openings,
+        rounds: 60,
+        concurrency: 30,
+        game: GameSettings {
+            time_control: TimeControl::from_str("150/1+1").map_err(|e| anyhow!(e))?,
+            max_moves: Some(100),
+            resign: None,
+            time_margin: Duration::from_millis(100),
+        },
+        seed: 1029384756,
+        log_folder: Some(output_folder.clone()),
+    };
+    let games = run_match(&config, "Tournament", &output_folder.join("tournament.pgn"))?;
+    let epd = games
+        .iter()
+        .map(|g| g.final_epd() + "\n")
+        .collect::<String>();
+    std::fs::write(output_folder.join("tournament.epd"), epd)?;
+    println!("{}", Standings::new(&games));
 
     Ok(())
 }
 
-fn builder(branch: Option<&str>) -> RepoBuilder {
+/// The node count of `chessatiel bench`, which only changes with functional changes.
+fn bench_signature(binary: &Path) -> Result<u64> {
+    let output = Command::new(binary).arg("bench").output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Bench returned with error {}", output.status));
+    }
+    parse_signature(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow!("Bench output has no node count"))
+}
+
+fn parse_signature(bench_output: &str) -> Option<u64> {
+    bench_output
+        .lines()
+        .find_map(|l| l.strip_prefix("Nodes searched"))
+        .and_then(|l| l.trim_start_matches([' ', ':']).trim().parse().ok())
+}
+
+fn builder(branch: Option<&str>) -> RepoBuilder<'_> {
     let mut callbacks = RemoteCallbacks::new();
     callbacks.credentials(|_url, username, _allowed_types| {
         Cred::ssh_key(
@@ -128,11 +141,18 @@ fn builder(branch: Option<&str>) -> RepoBuilder {
     builder
 }
 
-fn engine_args(name: &str) -> Vec<String> {
-    vec![
-        "-engine".to_owned(),
-        format!("name={name}"),
-        format!("cmd=./{name}"),
-        format!("stderr={name}.log"),
-    ]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_the_node_count() {
+        let output =
+            "Total time (ms) : 7647\nNodes searched  : 11305396\nNodes/second    : 1478237\n";
+        assert_eq!(parse_signature(output), Some(11305396));
+        assert_eq!(
+            parse_signature("error: unrecognized subcommand 'bench'"),
+            None
+        );
+    }
 }
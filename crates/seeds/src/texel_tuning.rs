@@ -0,0 +1,330 @@
+use crate::{validation_count, AnnotatedPosition};
+use anyhow::Result;
+use brain::evaluator::main_evaluator::pst::PieceSquareTable;
+use brain::evaluator::main_evaluator::terms::EvalParams;
+use brain::evaluator::main_evaluator::{embedded_params, embedded_pst};
+use guts::Position;
+use itertools::Itertools;
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// The PST is trained in these units, the tuner works in centipawns for every weight.
+const CENTIPAWNS_PER_PST_VALUE: f64 = 1000.0;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+const SEED: u64 = 0x7E8E1;
+const MAX_K: f64 = 5.0;
+const K_ITERATIONS: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct TuningConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// In centipawns per step.
+    pub learning_rate: f64,
+    /// Part of the positions held out to measure the error on.
+    pub validation_fraction: f64,
+    pub output_folder: PathBuf,
+}
+
+struct Sample {
+    /// Sparse coefficients of every weight for this position, from white's point of view.
+    features: Vec<(usize, f64)>,
+    /// The game result from 0 for a black win to 1 for a white win.
+    result: f64,
+}
+
+impl Sample {
+    fn new(annotated: &AnnotatedPosition, pst_len: usize) -> Self {
+        Self {
+            features: features(&annotated.pos, pst_len),
+            result: (f64::from(f32::from(annotated.result)) + 1.0) / 2.0,
+        }
+    }
+}
+
+/// The PST features come first, followed by those of [`EvalParams`].
+fn features(position: &Position, pst_len: usize) -> Vec<(usize, f64)> {
+    PieceSquareTable::position_as_vec(position)
+        .into_iter()
+        .chain(
+            EvalParams::position_as_vec(position)
+                .into_iter()
+                .map(|(idx, x)| (pst_len + idx, x)),
+        )
+        .filter(|(_, x)| *x != 0.0)
+        .collect()
+}
+
+/// All weights of the main evaluator in centipawns, tuned with Adam.
+pub struct Tuner {
+    weights: Vec<f64>,
+    pst_len: usize,
+    /// Scales centipawns to the winning probability, fitted to the data before tuning.
+    k: f64,
+    first_moments: Vec<f64>,
+    second_moments: Vec<f64>,
+    steps: i32,
+}
+
+impl Tuner {
+    pub fn new(pst: &PieceSquareTable, params: &EvalParams) -> Self {
+        let weights = pst
+            .values()
+            .iter()
+            .map(|v| v * CENTIPAWNS_PER_PST_VALUE)
+            .chain(params.weights().iter().map(|&w| w as f64))
+            .collect_vec();
+        let len = weights.len();
+        Self {
+            weights,
+            pst_len: pst.values().len(),
+            k: 1.0,
+            first_moments: vec![0.0; len],
+            second_moments: vec![0.0; len],
+            steps: 0,
+        }
+    }
+
+    pub fn pst_values(&self) -> Vec<f64> {
+        self.weights[..self.pst_len]
+            .iter()
+            .map(|w| w / CENTIPAWNS_PER_PST_VALUE)
+            .collect()
+    }
+
+    pub fn eval_params(&self) -> EvalParams {
+        EvalParams::from_weights(
+            self.weights[self.pst_len..]
+                .iter()
+                .map(|w| w.round() as i32)
+                .collect(),
+        )
+    }
+
+    fn evaluate(&self, sample: &Sample) -> f64 {
+        sample
+            .features
+            .iter()
+            .map(|(idx, x)| x * self.weights[*idx])
+            .sum()
+    }
+
+    fn predict(&self, sample: &Sample, k: f64) -> f64 {
+        sigmoid(self.evaluate(sample), k)
+    }
+
+    fn mean_error(&self, samples: &[Sample], k: f64) -> f64 {
+        samples
+            .par_iter()
+            .map(|s| (s.result - self.predict(s, k)).powi(2))
+            .sum::<f64>()
+            / samples.len().max(1) as f64
+    }
+
+    /// Golden section search for the K with the lowest error, the error is convex in K.
+    fn fit_k(&mut self, samples: &[Sample]) {
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (0.0, MAX_K);
+        for _ in 0..K_ITERATIONS {
+            let a = high - ratio * (high - low);
+            let b = low + ratio * (high - low);
+            if self.mean_error(samples, a) < self.mean_error(samples, b) {
+                high = b;
+            } else {
+                low = a;
+            }
+        }
+        self.k = (low + high) / 2.0;
+    }
+
+    /// The gradient of the mean error for every weight a position in the batch uses, the rest of
+    /// the gradient is zero.
+    fn gradient(&self, batch: &[Sample]) -> HashMap<usize, f64> {
+        let scale = self.k * std::f64::consts::LN_10 / 400.0;
+        let mut gradient = batch
+            .par_iter()
+            .fold(HashMap::new, |mut gradient, sample| {
+                let predicted = self.predict(sample, self.k);
+                let d_eval =
+                    2.0 * (predicted - sample.result) * predicted * (1.0 - predicted) * scale;
+                for (idx, x) in &sample.features {
+                    *gradient.entry(*idx).or_insert(0.0) += d_eval * x;
+                }
+                gradient
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (idx, g) in b {
+                    *a.entry(idx).or_insert(0.0) += g;
+                }
+                a
+            });
+        let n = batch.len() as f64;
+        gradient.values_mut().for_each(|g| *g /= n);
+        gradient
+    }
+
+    /// Adam on the weights in `gradient` only, the moments of the others are left alone instead
+    /// of decaying towards zero.
+    fn adam_step(&mut self, gradient: &HashMap<usize, f64>, learning_rate: f64) {
+        self.steps += 1;
+        let first_correction = 1.0 - BETA1.powi(self.steps);
+        let second_correction = 1.0 - BETA2.powi(self.steps);
+        for (&i, &g) in gradient {
+            let m = &mut self.first_moments[i];
+            let v = &mut self.second_moments[i];
+            *m = BETA1 * *m + (1.0 - BETA1) * g;
+            *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+            self.weights[i] -= learning_rate * (*m / first_correction)
+                / ((*v / second_correction).sqrt() + EPSILON);
+        }
+    }
+
+    fn tune_epoch(&mut self, samples: &[Sample], batch_size: usize, learning_rate: f64) {
+        for batch in samples.chunks(batch_size) {
+            let gradient = self.gradient(batch);
+            self.adam_step(&gradient, learning_rate);
+        }
+    }
+}
+
+pub fn tune(config: &TuningConfig, mut positions: Vec<AnnotatedPosition>) -> Result<()> {
+    let validation_count = validation_count(positions.len(), config.validation_fraction)?;
+    let mut rng = ChaCha12Rng::seed_from_u64(SEED);
+    positions.shuffle(&mut rng);
+    // Starting from the weights the engine plays with only leaves the tuner the corrections
+    let mut tuner = Tuner::new(embedded_pst(), embedded_params());
+    let mut samples = positions
+        .par_iter()
+        .map(|ap| Sample::new(ap, tuner.pst_len))
+        .collect::<Vec<_>>();
+    let validation = samples.split_off(samples.len() - validation_count);
+    println!(
+        "Tuning on {} positions, validating on {}",
+        samples.len(),
+        validation.len()
+    );
+
+    tuner.fit_k(&samples);
+    println!(
+        "Fitted K = {:.4}, training error {:.6}, validation error {:.6}",
+        tuner.k,
+        tuner.mean_error(&samples, tuner.k),
+        tuner.mean_error(&validation, tuner.k)
+    );
+
+    for epoch in 1..=config.epochs {
+        samples.shuffle(&mut rng);
+        tuner.tune_epoch(&samples, config.batch_size, config.learning_rate);
+        println!(
+            "Epoch {epoch}: training error {:.6}, validation error {:.6}",
+            tuner.mean_error(&samples, tuner.k),
+            tuner.mean_error(&validation, tuner.k)
+        );
+    }
+
+    std::fs::create_dir_all(&config.output_folder)?;
+    // Both replace the files of the same name in brain/resources
+    std::fs::write(
+        config.output_folder.join("pst.bincode"),
+        bincode::serialize(&tuner.pst_values())?,
+    )?;
+    std::fs::write(
+        config.output_folder.join("eval_params.bincode"),
+        tuner.eval_params().to_bincode(),
+    )?;
+    Ok(())
+}
+
+/// The expected result for white of a score in centipawns.
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameResult;
+    use brain::evaluator::main_evaluator::terms::Term;
+    use guts::Piece;
+    use std::str::FromStr;
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        let samples = [
+            ("4k3/8/8/8/8/8/8/RR2K3 w - - 0 1", GameResult::White),
+            ("4k3/8/8/8/8/8/8/1R2K3 b - - 0 1", GameResult::White),
+            ("rr2k3/8/8/8/8/8/8/4K3 w - - 0 1", GameResult::Black),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", GameResult::Draw),
+        ]
+        .iter()
+        .map(|(fen, result)| {
+            let ap = AnnotatedPosition {
+                pos: Position::from_str(fen).unwrap(),
+                result: *result,
+                score: None,
+            };
+            Sample::new(&ap, PieceSquareTable::zeroes().values().len())
+        })
+        .collect_vec();
+        let mut params = EvalParams::zeroes();
+        params.set(Term::Material(Piece::Rook), (10, 10));
+        let mut tuner = Tuner::new(&PieceSquareTable::zeroes(), &params);
+        tuner.fit_k(&samples);
+        let before = tuner.mean_error(&samples, tuner.k);
+        for _ in 0..50 {
+            tuner.tune_epoch(&samples, 2, 5.0);
+        }
+        assert!(tuner.mean_error(&samples, tuner.k) < before);
+        assert!(tuner.eval_params().get(Term::Material(Piece::Rook)).0 > 10);
+    }
+
+    #[test]
+    fn weights_missing_from_the_batch_are_left_alone() {
+        let pst_len = PieceSquareTable::zeroes().values().len();
+        let sample = |fen: &str, result| {
+            let ap = AnnotatedPosition {
+                pos: Position::from_str(fen).unwrap(),
+                result,
+                score: None,
+            };
+            Sample::new(&ap, pst_len)
+        };
+        let queens = [sample("4k3/8/8/8/8/8/8/Q3K3 w - - 0 1", GameResult::White)];
+        let rooks = [sample("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", GameResult::White)];
+        let mut tuner = Tuner::new(&PieceSquareTable::zeroes(), &EvalParams::zeroes());
+        tuner.tune_epoch(&queens, 1, 5.0);
+        let before = (
+            tuner.weights.clone(),
+            tuner.first_moments.clone(),
+            tuner.second_moments.clone(),
+        );
+        tuner.tune_epoch(&rooks, 1, 5.0);
+
+        let used = rooks[0].features.iter().map(|(idx, _)| *idx).collect_vec();
+        for i in (0..tuner.weights.len()).filter(|i| !used.contains(i)) {
+            assert_eq!(tuner.weights[i], before.0[i]);
+            assert_eq!(tuner.first_moments[i], before.1[i]);
+            assert_eq!(tuner.second_moments[i], before.2[i]);
+        }
+        assert!(used.iter().any(|&i| tuner.weights[i] != before.0[i]));
+    }
+
+    #[test]
+    fn validation_fraction_has_to_leave_training_positions() {
+        for validation_fraction in [-0.1, 1.0, f64::NAN] {
+            let config = TuningConfig {
+                epochs: 1,
+                batch_size: 1,
+                learning_rate: 1.0,
+                validation_fraction,
+                output_folder: PathBuf::new(),
+            };
+            assert!(tune(&config, Vec::new()).is_err());
+        }
+    }
+}